@@ -0,0 +1,24 @@
+/*
+ * Firmware for blink.hlzm. Build it with:
+ *
+ *     cc -Ihal -o examples/blink examples/blink.c hal/hiloz_hal.c
+ */
+
+#include "hiloz_hal.h"
+
+int main(void) {
+    for (int i = 0; i < 5; i++) {
+        /* Wait for the button to be pressed, then blink once */
+        while (gpio_read("button") != HILOZ_HIGH) {
+            wait_for_change();
+        }
+
+        gpio_write("led", HILOZ_HIGH);
+        delay_us(100);
+        gpio_write("led", HILOZ_LOW);
+        delay_us(100);
+    }
+
+    hiloz_log("done blinking");
+    return 0;
+}
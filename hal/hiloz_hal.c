@@ -0,0 +1,187 @@
+/*
+ * The implementation of the stub HAL. This speaks the protocol described in
+ * src/external.rs, with the firmware's execution fitted around it:
+ *
+ * - hiloz_init answers the construct message, then waits for the first wake.
+ * - Pin writes are sent as `set` messages as they happen.
+ * - Delays and waits finish the current wake with `sleep` or `trigger`, and
+ *   then block until the next wake.
+ * - When the firmware exits, the current wake is finished with `halt`.
+ */
+
+#include "hiloz_hal.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define MAX_LINE 256
+
+/* Grows as pins are seen, since pin arrays can make for a lot of them */
+static struct pin {
+    char name[MAX_LINE];
+    hiloz_value value;
+} *pins = NULL;
+static int pin_count = 0;
+static int pin_capacity = 0;
+
+static uint64_t current_time = 0;
+static int initialised = 0;
+
+static void send(const char *line) {
+    puts(line);
+    fflush(stdout);
+}
+
+static void send_time(const char *command, uint64_t time) {
+    printf("%s %llu\n", command, (unsigned long long)time);
+    fflush(stdout);
+}
+
+static hiloz_value parse_value(const char *value) {
+    switch (value[0]) {
+        case 'H': return HILOZ_HIGH;
+        case 'L': return HILOZ_LOW;
+        default: return HILOZ_UNKNOWN;
+    }
+}
+
+static const char *format_value(hiloz_value value) {
+    switch (value) {
+        case HILOZ_HIGH: return "H";
+        case HILOZ_LOW: return "L";
+        default: return "X";
+    }
+}
+
+static void set_pin(const char *name, hiloz_value value) {
+    for (int i = 0; i < pin_count; i++) {
+        if (strcmp(pins[i].name, name) == 0) {
+            pins[i].value = value;
+            return;
+        }
+    }
+
+    if (pin_count == pin_capacity) {
+        int capacity = pin_capacity == 0 ? 64 : pin_capacity * 2;
+        struct pin *grown = realloc(pins, capacity * sizeof *pins);
+        if (grown == NULL) {
+            fprintf(stderr, "hiloz_hal: out of memory for %d pins\n", capacity);
+            abort();
+        }
+        pins = grown;
+        pin_capacity = capacity;
+    }
+
+    strncpy(pins[pin_count].name, name, MAX_LINE - 1);
+    pins[pin_count].name[MAX_LINE - 1] = '\0';
+    pins[pin_count].value = value;
+    pin_count++;
+}
+
+/*
+ * Reads a `construct` or `wake` message, up to its `end`, and returns which it
+ * was. If Hiloz has asked the firmware to quit, or gone away, this exits.
+ */
+static const char *receive(void) {
+    static char header[MAX_LINE];
+    char line[MAX_LINE];
+
+    header[0] = '\0';
+    while (fgets(line, sizeof line, stdin) != NULL) {
+        char command[MAX_LINE], name[MAX_LINE], value[MAX_LINE];
+        int fields = sscanf(line, "%255s %255s %255s", command, name, value);
+        if (fields < 1) {
+            continue;
+        }
+
+        if (strcmp(command, "construct") == 0 || strcmp(command, "wake") == 0) {
+            strcpy(header, command);
+            if (fields >= 2) {
+                current_time = strtoull(name, NULL, 10);
+            }
+        } else if (strcmp(command, "pin") == 0 && fields == 3) {
+            set_pin(name, parse_value(value));
+        } else if (strcmp(command, "end") == 0) {
+            return header;
+        } else if (strcmp(command, "quit") == 0) {
+            break;
+        }
+    }
+
+    /* Exit without running atexit handlers, since Hiloz isn't listening */
+    _Exit(0);
+}
+
+static void wait_for_wake(void) {
+    while (strcmp(receive(), "wake") != 0) {
+        send("ok");
+    }
+}
+
+static void halt(void) {
+    if (initialised) {
+        send("halt");
+        initialised = 0;
+
+        /* Stay around until Hiloz is finished with us */
+        for (;;) {
+            receive();
+        }
+    }
+}
+
+void hiloz_init(void) {
+    if (initialised) {
+        return;
+    }
+    initialised = 1;
+    atexit(halt);
+
+    wait_for_wake();
+}
+
+void hiloz_gpio_write(const char *pin, hiloz_value value) {
+    hiloz_init();
+    printf("set %s %s\n", pin, format_value(value));
+    fflush(stdout);
+}
+
+hiloz_value hiloz_gpio_read(const char *pin) {
+    hiloz_init();
+    for (int i = 0; i < pin_count; i++) {
+        if (strcmp(pins[i].name, pin) == 0) {
+            return pins[i].value;
+        }
+    }
+    return HILOZ_UNKNOWN;
+}
+
+void hiloz_delay_us(uint64_t us) {
+    hiloz_init();
+    send_time("sleep", us * HILOZ_TIME_UNITS_PER_US);
+    wait_for_wake();
+}
+
+void hiloz_wait_for_change(void) {
+    hiloz_init();
+    send("trigger");
+    wait_for_wake();
+}
+
+uint64_t hiloz_time(void) {
+    hiloz_init();
+    return current_time;
+}
+
+void hiloz_log(const char *message) {
+    hiloz_init();
+    printf("log info %s\n", message);
+    fflush(stdout);
+}
+
+void hiloz_fail(const char *message) {
+    hiloz_init();
+    printf("fail %s\n", message);
+    fflush(stdout);
+}
@@ -0,0 +1,85 @@
+/*
+ * A stub hardware abstraction layer which lets firmware compiled for the host
+ * run as a Hiloz `External` component.
+ *
+ * Build your firmware with hiloz_hal.c instead of the real HAL for your
+ * microcontroller, and instantiate it in a model with:
+ *
+ *     component mcu = External("./firmware", "led button");
+ *
+ * Pins are named the same way as in the model. Calls into the HAL talk to
+ * Hiloz over standard input and output, so the firmware must not use them for
+ * anything else. Write debug output to standard error, or use hiloz_log.
+ *
+ * The firmware starts running at time 0 of the simulation. Virtual time only
+ * passes in hiloz_delay_us and hiloz_wait_for_change; everything else happens
+ * instantly. When main returns, or the firmware calls exit, the component
+ * halts.
+ */
+
+#ifndef HILOZ_HAL_H
+#define HILOZ_HAL_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The number of simulation time units in one microsecond. */
+#ifndef HILOZ_TIME_UNITS_PER_US
+#define HILOZ_TIME_UNITS_PER_US 1
+#endif
+
+typedef enum {
+    HILOZ_LOW = 0,
+    HILOZ_HIGH = 1,
+    HILOZ_UNKNOWN = 2,
+} hiloz_value;
+
+/*
+ * Connects to Hiloz and waits for the simulation to start. This is called
+ * automatically by every other function, so calling it is optional.
+ */
+void hiloz_init(void);
+
+/*
+ * Drives a pin. Like a pin write in a script, the rest of the model sees the
+ * new value once the firmware next waits.
+ */
+void hiloz_gpio_write(const char *pin, hiloz_value value);
+
+/*
+ * Reads a pin. If it is connected to other pins, this is the value of the
+ * whole connection as of the last time the firmware waited.
+ */
+hiloz_value hiloz_gpio_read(const char *pin);
+
+/* Waits for the given number of microseconds of virtual time. */
+void hiloz_delay_us(uint64_t us);
+
+/* Waits until any of the component's pins change. */
+void hiloz_wait_for_change(void);
+
+/* The current virtual time, in simulation time units. */
+uint64_t hiloz_time(void);
+
+/* Writes a message to the simulation's log, at info level. */
+void hiloz_log(const char *message);
+
+/* Records a failure in the simulation, like a failed assert. */
+void hiloz_fail(const char *message);
+
+/* Shorter names, for firmware written against a typical HAL. */
+#ifndef HILOZ_NO_SHORT_NAMES
+#define gpio_write hiloz_gpio_write
+#define gpio_read hiloz_gpio_read
+#define delay_us hiloz_delay_us
+#define wait_for_change hiloz_wait_for_change
+#endif
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
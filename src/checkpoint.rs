@@ -0,0 +1,57 @@
+//! Checkpoints, which save the whole state of a simulation to a file so that
+//! it can be carried on with later.
+//!
+//! A checkpoint holds everything in the model - components, interpreters with
+//! their frames and stacks, the timing queue and trigger list, and the time -
+//! so a long warm-up, like firmware booting, only needs simulating once.
+//!
+//! The file is a short header followed by the model, serialised with bincode
+//! and compressed with gzip. Checkpoints are only readable by the version of
+//! Hiloz which wrote them.
+
+use std::{error::Error, io::{Read, Write}};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+use crate::model as m;
+
+const MAGIC: &[u8] = b"HILOZCKP";
+const VERSION: u32 = 1;
+
+/// Writes a checkpoint of a model. Models with native or external components
+/// can't be checkpointed, since their state lives outside of Hiloz.
+pub fn save(model: &m::Model, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if let Some(interpreter) = model.interpreters.iter().find(|i| i.native.is_some()) {
+        let instance_name = interpreter.component_idx
+            .map(|idx| model.components[idx].instance_name.clone())
+            .unwrap_or_default();
+        return Err(format!("cannot checkpoint native component {}", instance_name).into());
+    }
+
+    output.write_all(MAGIC)?;
+    output.write_all(&VERSION.to_le_bytes())?;
+
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    bincode::serialize_into(&mut encoder, model)?;
+    encoder.finish()?;
+
+    Ok(())
+}
+
+/// Reads a checkpoint written by `save`.
+pub fn load(input: &mut dyn Read) -> Result<m::Model, Box<dyn Error>> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic).map_err(|_| "not a checkpoint")?;
+    if magic != MAGIC {
+        return Err("not a checkpoint".into());
+    }
+
+    let mut version = [0; 4];
+    input.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != VERSION {
+        return Err(format!("unsupported checkpoint version {}", version).into());
+    }
+
+    Ok(bincode::deserialize_from(GzDecoder::new(input))?)
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use crate::logic as l;
+use crate::stimulus;
+use crate::vcd::VcdFile;
+
+/// The first point at which a signal of a simulation stopped matching the
+/// reference trace it was compared against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub signal: String,
+    pub time: u64,
+
+    /// The value of the signal in the reference at this time, or None if it
+    /// had not been given one yet.
+    pub expected: Option<l::Value>,
+
+    /// The value of the signal in the simulation at this time, or None if
+    /// it had not been given one yet, or does not exist at all.
+    pub actual: Option<l::Value>,
+}
+
+fn format_value(value: Option<l::Value>) -> &'static str {
+    match value {
+        Some(l::Value::High) => "H",
+        Some(l::Value::Low) => "L",
+        Some(l::Value::Unknown) => "X",
+        None => "nothing",
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{} diverges at {}: expected {}, got {}",
+            self.signal, self.time, format_value(self.expected), format_value(self.actual),
+        )
+    }
+}
+
+fn value_at(changes: &[(u64, l::Value)], time: u64) -> Option<l::Value> {
+    changes.iter()
+        .take_while(|(t, _)| *t <= time)
+        .last()
+        .map(|(_, v)| *v)
+}
+
+/// Compares one signal of a simulation against the reference. The two match
+/// if they go through the same sequence of values, with each change
+/// happening within `skew` time units of the reference.
+pub fn compare_signal(name: &str, expected: &[(u64, l::Value)], actual: &[(u64, l::Value)], skew: u64) -> Option<Divergence> {
+    let expected = stimulus::transitions(expected.to_vec());
+    let actual = stimulus::transitions(actual.to_vec());
+
+    let divergence_at = |time| Some(Divergence {
+        signal: name.into(),
+        time,
+        expected: value_at(&expected, time),
+        actual: value_at(&actual, time),
+    });
+
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some((expected_time, expected_value)), Some((actual_time, actual_value))) => {
+                let time = *expected_time.min(actual_time);
+                if expected_value != actual_value || expected_time.abs_diff(*actual_time) > skew {
+                    return divergence_at(time);
+                }
+            }
+
+            (Some((time, _)), None) | (None, Some((time, _))) => return divergence_at(*time),
+
+            (None, None) => unreachable!(),
+        }
+    }
+
+    None
+}
+
+/// Compares every single-bit signal of the reference with the signal of the
+/// same name in the simulation, returning the first divergence of each one
+/// which doesn't match. Signals which only appear in the simulation are
+/// ignored.
+pub fn compare_traces(reference: &VcdFile, actual: &VcdFile, skew: u64) -> Vec<Divergence> {
+    reference.signals.iter()
+        .filter(|signal| signal.width == 1)
+        .filter_map(|signal| {
+            let actual_changes = actual.signal(&signal.name)
+                .map(|s| &s.changes[..])
+                .unwrap_or(&[]);
+            compare_signal(&signal.name, &signal.changes, actual_changes, skew)
+        })
+        .collect()
+}
@@ -0,0 +1,538 @@
+use std::{fmt::Write, str::FromStr};
+
+use crate::Simulation;
+use crate::model::{self as m, ConnectedComponents};
+use crate::logic as l;
+use crate::script_engine as se;
+
+/// Which changes of a pin's value stop the simulation at a breakpoint.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Any,
+}
+
+impl Edge {
+    fn name(&self) -> &'static str {
+        match self {
+            Edge::Rising => "rising",
+            Edge::Falling => "falling",
+            Edge::Any => "any",
+        }
+    }
+
+    fn matches(&self, before: l::Value, after: l::Value) -> bool {
+        match self {
+            Edge::Rising => before != l::Value::High && after == l::Value::High,
+            Edge::Falling => before != l::Value::Low && after == l::Value::Low,
+            Edge::Any => before != after,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Breakpoint {
+    pub pin: String,
+    pub edge: Edge,
+}
+
+/// A breakpoint which stops a script before it runs the statement on a line
+/// of the source. If an instance is given, only that instance's script stops.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LineBreakpoint {
+    pub instance: Option<String>,
+    pub line: usize,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum AnyBreakpoint {
+    Pin(Breakpoint),
+    Line(LineBreakpoint),
+}
+
+/// A command typed into the debugger.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Command {
+    /// Run this many steps.
+    Step(u64),
+
+    /// Run until this time.
+    Run(u64),
+
+    /// Run until a breakpoint is hit, or the simulation halts.
+    Continue,
+
+    /// Run until this pin changes value.
+    Until(String),
+
+    /// Print a pin, or every pin and variable of an instance, or every
+    /// instance if no name is given.
+    Print(Option<String>),
+
+    /// Print every connection and its value.
+    Nets,
+
+    Break(Breakpoint),
+    BreakLine(LineBreakpoint),
+    Delete(usize),
+
+    /// Run until the next script instruction, in any instance.
+    StepInstruction,
+
+    /// Print the frames of the script which is paused.
+    Where,
+    Breakpoints,
+
+    /// Drive a pin, as if its component had written it.
+    Set(String, l::Value),
+
+    /// Force a pin and everything connected to it to a value, overriding
+    /// its drivers.
+    Force(String, l::Value),
+    Release(String),
+
+    Time,
+    Help,
+    Quit,
+}
+
+pub const HELP: &str = "\
+step [n]                  run one step, or n steps
+stepi                     run until the next script instruction
+run <time>                run until the given time
+continue                  run until a breakpoint is hit or the simulation halts
+until <inst.pin>          run until the pin changes
+print [inst | inst.pin]   print a pin, an instance, or every instance
+nets                      print every connection and its value
+break <inst.pin> [edge]   stop when the pin changes; edge is rising, falling or any
+break [inst:]<line>       stop scripts before they run the statement on this line of
+                          the main model file; imported files can't have breakpoints
+where                     print the locals and stack of the paused script
+breakpoints               list breakpoints
+delete <n>                delete a breakpoint
+set <inst.pin> <H|L|X>    drive a pin
+force <inst.pin> <H|L|X>  force a pin and its net to a value, overriding its drivers
+release <inst.pin>        stop forcing a pin and its net
+time                      print the current time
+help                      print this message
+quit                      leave the debugger";
+
+fn parse_value(value: &str) -> Result<l::Value, String> {
+    match value {
+        "H" | "h" | "1" => Ok(l::Value::High),
+        "L" | "l" | "0" => Ok(l::Value::Low),
+        "X" | "x" => Ok(l::Value::Unknown),
+        _ => Err(format!("invalid logic value {}", value)),
+    }
+}
+
+fn format_value(value: Option<l::Value>) -> &'static str {
+    match value {
+        Some(l::Value::High) => "H",
+        Some(l::Value::Low) => "L",
+        Some(l::Value::Unknown) => "X",
+        None => "conflict",
+    }
+}
+
+fn parse_number(number: &str) -> Result<u64, String> {
+    number.parse().map_err(|_| format!("invalid number {}", number))
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["step" | "s"] => Ok(Command::Step(1)),
+            ["step" | "s", n] => Ok(Command::Step(parse_number(n)?)),
+            ["run" | "r", time] => Ok(Command::Run(parse_number(time)?)),
+            ["stepi" | "si"] => Ok(Command::StepInstruction),
+            ["continue" | "c"] => Ok(Command::Continue),
+            ["until" | "u", pin] => Ok(Command::Until(pin.into())),
+            ["print" | "p"] => Ok(Command::Print(None)),
+            ["print" | "p", name] => Ok(Command::Print(Some(name.into()))),
+            ["nets"] => Ok(Command::Nets),
+            ["break" | "b", target] => {
+                if let Ok(line) = target.parse() {
+                    Ok(Command::BreakLine(LineBreakpoint { instance: None, line }))
+                } else if let Some((instance, line)) = target.split_once(':') {
+                    Ok(Command::BreakLine(LineBreakpoint {
+                        instance: Some(instance.into()),
+                        line: parse_number(line)? as usize,
+                    }))
+                } else {
+                    Ok(Command::Break(Breakpoint { pin: target.into(), edge: Edge::Any }))
+                }
+            }
+            ["break" | "b", pin, edge] => Ok(Command::Break(Breakpoint {
+                pin: pin.into(),
+                edge: match edge {
+                    "rising" => Edge::Rising,
+                    "falling" => Edge::Falling,
+                    "any" => Edge::Any,
+                    _ => return Err(format!("invalid edge {}", edge)),
+                },
+            })),
+            ["breakpoints"] => Ok(Command::Breakpoints),
+            ["where" | "bt"] => Ok(Command::Where),
+            ["delete" | "d", n] => Ok(Command::Delete(parse_number(n)? as usize)),
+            ["set", pin, value] => Ok(Command::Set(pin.into(), parse_value(value)?)),
+            ["force", pin, value] => Ok(Command::Force(pin.into(), parse_value(value)?)),
+            ["release", pin] => Ok(Command::Release(pin.into())),
+            ["time"] => Ok(Command::Time),
+            ["help" | "h"] => Ok(Command::Help),
+            ["quit" | "q"] => Ok(Command::Quit),
+            _ => Err(format!("unknown command {:?}, try \"help\"", s.trim())),
+        }
+    }
+}
+
+/// Runs a simulation under the control of debugger commands, stopping at
+/// breakpoints.
+pub struct Debugger {
+    simulation: Simulation,
+    breakpoints: Vec<Option<AnyBreakpoint>>,
+    max_time: u64,
+    halted: bool,
+}
+
+impl Debugger {
+    /// Creates a debugger for a simulation. `continue` never runs it past
+    /// `max_time`.
+    pub fn new(mut simulation: Simulation, max_time: u64) -> Debugger {
+        simulation.construct();
+        Debugger { simulation, breakpoints: vec![], max_time, halted: false }
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn simulation_mut(&mut self) -> &mut Simulation {
+        &mut self.simulation
+    }
+
+    fn pin(&self, name: &str) -> Result<m::PinConnection, String> {
+        self.simulation.model().pin_by_name(name).ok_or_else(|| format!("no pin named {}", name))
+    }
+
+    /// Carries out a command, returning what should be printed. `Quit` does
+    /// nothing here.
+    pub fn execute(&mut self, command: Command) -> Result<String, String> {
+        match command {
+            Command::Step(n) => Ok(self.run(|_, steps| steps >= n, u64::MAX, false)),
+            Command::StepInstruction => Ok(self.run(|_, steps| steps >= 1, u64::MAX, true)),
+            Command::Run(time) => Ok(self.run(|_, _| false, time, false)),
+            Command::Continue => Ok(self.run(|_, _| false, self.max_time, false)),
+            Command::Until(name) => {
+                let pin = self.pin(&name)?;
+                let initial = self.simulation.model().pin_value(&pin);
+                Ok(self.run(
+                    |simulation, _| simulation.model().pin_value(&pin) != initial,
+                    self.max_time,
+                    false,
+                ))
+            }
+
+            Command::Print(None) => Ok(self.simulation.model().components.iter()
+                .map(|c| self.describe_component(c))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Print(Some(name)) => {
+                let model = self.simulation.model();
+                if let Some(idx) = model.component_idx(&name) {
+                    Ok(self.describe_component(&model.components[idx]))
+                } else {
+                    let pin = self.pin(&name)?;
+                    Ok(format!("{} = {}", name, format_value(Some(model.pin_value(&pin)))))
+                }
+            }
+            Command::Nets => {
+                let model = self.simulation.model();
+                Ok(model.connections.iter().enumerate()
+                    .map(|(i, connection)| format!(
+                        "net {}: {} = {}{}",
+                        i,
+                        connection.pins.iter().map(|pc| self.pin_name(pc)).collect::<Vec<_>>().join(", "),
+                        format_value(model.connection_value(i)),
+                        if model.forced_value(&connection.pins).is_some() { " (forced)" } else { "" },
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+
+            Command::Break(breakpoint) => {
+                self.pin(&breakpoint.pin)?;
+                self.breakpoints.push(Some(AnyBreakpoint::Pin(breakpoint)));
+                Ok(format!("Breakpoint {} set", self.breakpoints.len()))
+            }
+            Command::BreakLine(breakpoint) => {
+                let model = self.simulation.model();
+                let component_idx = match &breakpoint.instance {
+                    Some(instance) => Some(model.component_idx(instance).ok_or_else(|| format!("no instance named {}", instance))?),
+                    None => None,
+                };
+                let scripts = |component_idx: usize| {
+                    let definition = &model.components[component_idx].definition;
+                    definition.script.iter().chain(definition.functions.iter())
+                };
+
+                // Only the main model file is parsed with source lines, so
+                // scripts from imported files have none to stop at
+                if let (Some(instance), Some(component_idx)) = (&breakpoint.instance, component_idx) {
+                    let has_lines = scripts(component_idx)
+                        .any(|f| f.body.iter().any(|i| matches!(i, se::Instruction::Line(_))));
+                    if !has_lines {
+                        return Err(format!("{} has no script lines to stop at; scripts from imported files can't have breakpoints", instance));
+                    }
+                }
+
+                let has_line = (0..model.components.len())
+                    .filter(|i| component_idx.is_none() || component_idx == Some(*i))
+                    .flat_map(scripts)
+                    .any(|f| f.body.contains(&se::Instruction::Line(breakpoint.line)));
+                if !has_line {
+                    return Err(format!("no script statement on line {} of the main model file", breakpoint.line));
+                }
+
+                self.breakpoints.push(Some(AnyBreakpoint::Line(breakpoint)));
+                Ok(format!("Breakpoint {} set", self.breakpoints.len()))
+            }
+            Command::Delete(n) => match self.breakpoints.get_mut(n.wrapping_sub(1)) {
+                Some(breakpoint @ Some(_)) => {
+                    *breakpoint = None;
+                    Ok(format!("Breakpoint {} deleted", n))
+                }
+                _ => Err(format!("no breakpoint {}", n)),
+            },
+            Command::Breakpoints => Ok(self.breakpoints.iter().enumerate()
+                .filter_map(|(i, b)| b.as_ref().map(|b| match b {
+                    AnyBreakpoint::Pin(b) => format!("{}: {} {}", i + 1, b.pin, b.edge.name()),
+                    AnyBreakpoint::Line(LineBreakpoint { instance: Some(instance), line }) =>
+                        format!("{}: {} line {}", i + 1, instance, line),
+                    AnyBreakpoint::Line(LineBreakpoint { instance: None, line }) =>
+                        format!("{}: line {}", i + 1, line),
+                }))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Where => self.describe_pause(),
+
+            Command::Set(name, value) => {
+                let pin = self.pin(&name)?;
+                self.simulation.model_mut().drive_pin(&pin, value);
+
+                // This might have woken something up
+                self.halted = false;
+                Ok(format!("{} = {}", name, format_value(Some(self.simulation.model().pin_value(&pin)))))
+            }
+
+            Command::Force(name, value) => {
+                self.simulation.force_pin(&name, value)?;
+                self.halted = false;
+                Ok(format!("{} = {} (forced)", name, format_value(self.simulation.pin(&name))))
+            }
+            Command::Release(name) => {
+                if !self.simulation.release_pin(&name)? {
+                    return Err(format!("{} is not forced", name));
+                }
+                self.halted = false;
+                Ok(format!("{} = {}", name, format_value(self.simulation.pin(&name))))
+            }
+
+            Command::Time => Ok(format!("Time is {}", self.simulation.time())),
+            Command::Help => Ok(HELP.into()),
+            Command::Quit => Ok("".into()),
+        }
+    }
+
+    fn is_forced(&self, pc: &m::PinConnection) -> bool {
+        let model = self.simulation.model();
+        match model.pin_connection(pc) {
+            Some(c) => model.forced_value(&model.connections[c].pins).is_some(),
+            None => model.forced_value(std::slice::from_ref(pc)).is_some(),
+        }
+    }
+
+    fn pin_name(&self, pc: &m::PinConnection) -> String {
+        let component = &self.simulation.model().components[pc.component_idx];
+        format!("{}.{}", component.instance_name, component.definition.pins[pc.pin_idx].name)
+    }
+
+    fn describe_component(&self, component: &m::Component) -> String {
+        let model = self.simulation.model();
+        let idx = model.component_idx(&component.instance_name).unwrap();
+        let mut result = format!("{} ({})", component.instance_name, component.definition.name);
+
+        for (pin_idx, pin) in component.pins.iter().enumerate() {
+            let value = model.pin_value(&m::PinConnection { component_idx: idx, pin_idx });
+            write!(result, "\n  pin {} = {}", pin.definition.name, format_value(Some(value))).unwrap();
+            if self.is_forced(&m::PinConnection { component_idx: idx, pin_idx }) {
+                result.push_str(" (forced)");
+            }
+        }
+        for variable in component.variables.iter() {
+            write!(result, "\n  var {} = {}", variable.definition.name, variable.value).unwrap();
+        }
+        for (i, interpreter) in model.interpreters.iter().enumerate().filter(|(_, i)| i.component_idx == Some(idx)) {
+            let status = match interpreter.status {
+                _ if self.simulation.paused_interpreter() == Some(i) => match interpreter.current_line() {
+                    Some(line) => format!("paused at line {}", line),
+                    None => "paused".into(),
+                },
+                se::InterpreterStatus::Normal => "running".into(),
+                se::InterpreterStatus::Suspended => "suspended".into(),
+                se::InterpreterStatus::Halted => "halted".into(),
+            };
+            write!(result, "\n  script {}", status).unwrap();
+        }
+
+        result
+    }
+
+    /// Describes the paused script, with the locals and stack of each of its
+    /// frames, innermost first.
+    fn describe_pause(&self) -> Result<String, String> {
+        let model = self.simulation.model();
+        let interpreter = &model.interpreters[self.simulation.paused_interpreter().ok_or("no script is paused")?];
+        let component = &model.components[interpreter.component_idx.unwrap()];
+
+        let mut result = format!("{} ({})", component.instance_name, component.definition.name);
+        if let Some(instruction) = interpreter.next_instruction() {
+            write!(result, " before {:?}", instruction).unwrap();
+        }
+        for (i, frame) in interpreter.frames.iter().rev().enumerate() {
+            write!(result, "\n  frame {}: instruction {}", i, frame.ip).unwrap();
+            if let Some(line) = frame.line() {
+                write!(result, ", line {}", line).unwrap();
+            }
+
+            let mut locals = frame.locals.iter().collect::<Vec<_>>();
+            locals.sort_by_key(|(name, _)| name.as_str());
+            for (name, value) in locals {
+                write!(result, "\n    var {} = {}", name, value).unwrap();
+            }
+            for (i, argument) in frame.arguments.iter().enumerate() {
+                write!(result, "\n    argument {} = {}", i, argument).unwrap();
+            }
+            write!(
+                result, "\n    stack [{}]",
+                frame.stack.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(", "),
+            ).unwrap();
+        }
+
+        Ok(result)
+    }
+
+    /// Describes where a script was paused, and by which breakpoint if any.
+    fn describe_paused_at(&self, interpreter_idx: usize, breakpoint_idx: Option<usize>) -> String {
+        let model = self.simulation.model();
+        let interpreter = &model.interpreters[interpreter_idx];
+        let mut location = model.components[interpreter.component_idx.unwrap()].instance_name.clone();
+        if let Some(line) = interpreter.current_line() {
+            write!(location, " line {}", line).unwrap();
+        }
+
+        match breakpoint_idx {
+            Some(i) => format!("Breakpoint {} hit at {}: {}", i + 1, self.simulation.time(), location),
+            None => format!("Paused at {}: {}", self.simulation.time(), location),
+        }
+    }
+
+    /// Steps the simulation until `stop` returns true (given the number of
+    /// steps taken so far), a breakpoint is hit, it reaches `time`, or it
+    /// halts. If `pause_anywhere` is set, it also stops before the next
+    /// script instruction. Describes why it stopped.
+    fn run<F>(&mut self, mut stop: F, time: u64, pause_anywhere: bool) -> String where F : FnMut(&Simulation, u64) -> bool {
+        if self.halted {
+            return format!("Simulation has halted at {}", self.simulation.time());
+        }
+
+        let mut steps = 0;
+        loop {
+            if self.simulation.time() >= time {
+                return format!("Stopped at {}", self.simulation.time());
+            }
+
+            // Don't step past the time we're running to
+            if let Some(idle_time) = self.simulation.model().idle_time() {
+                if self.simulation.time().saturating_add(idle_time) > time {
+                    let duration = time - self.simulation.time();
+                    self.simulation.model_mut().advance_time(duration);
+                    return format!("Stopped at {}", self.simulation.time());
+                }
+            }
+
+            // Find the components and lines which scripts should stop at
+            let model = self.simulation.model();
+            let line_breakpoints = self.breakpoints.iter().enumerate()
+                .filter_map(|(i, b)| match b {
+                    Some(AnyBreakpoint::Line(LineBreakpoint { instance, line })) => Some((
+                        i,
+                        instance.as_ref().and_then(|instance| model.component_idx(instance)),
+                        *line,
+                    )),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            let before = self.breakpoint_values();
+            let mut line_breakpoint_hit = None;
+            let result = self.simulation.step_with_pause(|_, interpreter| {
+                if pause_anywhere {
+                    return true;
+                }
+                if let Some(se::Instruction::Line(line)) = interpreter.next_instruction() {
+                    line_breakpoint_hit = line_breakpoints.iter()
+                        .find(|(_, component_idx, breakpoint_line)|
+                            breakpoint_line == line && (component_idx.is_none() || *component_idx == interpreter.component_idx))
+                        .map(|(i, _, _)| *i);
+                }
+                line_breakpoint_hit.is_some()
+            });
+
+            match result {
+                m::StepResult::Halt => {
+                    self.halted = true;
+                    return format!("Simulation halted at {}", self.simulation.time());
+                }
+                m::StepResult::Paused(interpreter_idx) =>
+                    return self.describe_paused_at(interpreter_idx, line_breakpoint_hit),
+                m::StepResult::Ok(_) => (),
+            }
+            steps += 1;
+
+            let after = self.breakpoint_values();
+            for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                let breakpoint = match breakpoint {
+                    Some(AnyBreakpoint::Pin(breakpoint)) => breakpoint,
+                    _ => continue,
+                };
+                if let (Some(before), Some(after)) = (before[i], after[i]) {
+                    if breakpoint.edge.matches(before, after) {
+                        return format!(
+                            "Breakpoint {} hit at {}: {} {} -> {}",
+                            i + 1, self.simulation.time(), breakpoint.pin,
+                            format_value(Some(before)), format_value(Some(after)),
+                        );
+                    }
+                }
+            }
+
+            if stop(&self.simulation, steps) {
+                return format!("Stopped at {}", self.simulation.time());
+            }
+        }
+    }
+
+    fn breakpoint_values(&self) -> Vec<Option<l::Value>> {
+        self.breakpoints.iter()
+            .map(|b| match b {
+                Some(AnyBreakpoint::Pin(b)) => self.simulation.pin(&b.pin),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,346 @@
+//! Components whose behaviour comes from another program, such as firmware
+//! built for a microcontroller, which Hiloz talks to over a pipe.
+//!
+//! An external component is instantiated with a command and a list of pins:
+//!
+//! ```text
+//! component mcu = External("./firmware", "tx rx led");
+//! ```
+//!
+//! The command is run with `sh -c`, and the child is sent messages on its
+//! standard input and replies on its standard output. Alternatively, a
+//! command of the form `unix:/path/to/socket` connects to a program which is
+//! already listening on a Unix socket, and uses the same protocol over that.
+//!
+//! # Protocol
+//!
+//! Every message is a line of text, made of words separated by spaces.
+//!
+//! When the simulation is constructed, Hiloz sends `construct <time>`,
+//! followed by `pin <name> <H|L|X>` for each pin, followed by `end`. The
+//! child may then send any number of these, followed by `ok`:
+//!
+//! - `set <name> <H|L|X>` - drive one of the component's pins
+//! - `log <debug|info|warn|error> <message>` - write a log message
+//! - `fail <message>` - record a failure, like a failed `assert`
+//!
+//! Each time the component wakes, Hiloz sends `wake <time>` followed by the
+//! pins and `end` in the same way. The child replies with any number of the
+//! messages above, followed by one of:
+//!
+//! - `sleep <n>` - wake again after `n` time units
+//! - `trigger` - wake again when any of the component's pins change
+//! - `halt` - never wake again
+//!
+//! The component first wakes at time 0, and virtual time does not advance
+//! while Hiloz is waiting for a reply, so the child can take as long as it
+//! likes. When the simulation is finished, Hiloz sends `quit`, closes the
+//! pipes and waits briefly for the child to exit, killing it if it doesn't.
+//!
+//! If the child exits or replies with something unexpected, a failure is
+//! recorded and the component halts.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::model as m;
+use crate::logic as l;
+use crate::log::LogLevel;
+use crate::script_engine as se;
+use crate::native::{NativeComponent, NativeContext, Wait};
+
+/// The definition of an external component, and the native component which
+/// runs it.
+type ExternalInstance = (m::ComponentDefinition, Box<dyn NativeComponent>);
+
+/// Creates a built-in external component, if `name` is the name of one. Like
+/// stimulus sources, it is only used if the model doesn't define a component
+/// with the same name.
+pub fn builtin_component(name: &str, arguments: &[se::Object]) -> Option<Result<ExternalInstance, String>> {
+    if name != "External" {
+        return None;
+    }
+
+    Some(match arguments {
+        [se::Object::String(command), se::Object::String(pins)] => {
+            let pins = pins.split_whitespace().map(|p| p.to_string()).collect::<Vec<_>>();
+            let definition = m::ComponentDefinition {
+                name: name.into(),
+                pins: pins.iter().map(|p| Arc::new(m::PinDefinition { name: p.clone() })).collect(),
+                variables: vec![],
+                constructor: None,
+                functions: vec![],
+                script: None,
+                timing_checks: vec![],
+                generic_arguments: vec![],
+            };
+            Ok((definition, Box::new(ExternalComponent::new(command, pins))))
+        }
+
+        _ => Err(format!("{} takes a command and a list of pin names, got {:?}", name, arguments)),
+    })
+}
+
+/// Either end of the conversation with the child.
+struct Connection {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Option<Child>,
+}
+
+impl Connection {
+    fn open(command: &str) -> Result<Connection, String> {
+        if let Some(path) = command.strip_prefix("unix:") {
+            return Connection::open_socket(path);
+        }
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not start {}: {}", command, e))?;
+
+        Ok(Connection {
+            writer: Box::new(child.stdin.take().unwrap()),
+            reader: Box::new(BufReader::new(child.stdout.take().unwrap())),
+            child: Some(child),
+        })
+    }
+
+    #[cfg(unix)]
+    fn open_socket(path: &str) -> Result<Connection, String> {
+        let stream = std::os::unix::net::UnixStream::connect(path)
+            .map_err(|e| format!("could not connect to {}: {}", path, e))?;
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+
+        Ok(Connection {
+            writer: Box::new(stream),
+            reader: Box::new(BufReader::new(reader)),
+            child: None,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn open_socket(path: &str) -> Result<Connection, String> {
+        Err(format!("could not connect to {}: Unix sockets are not supported here", path))
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("could not write to process: {}", e))
+    }
+
+    fn receive(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("process closed its output".into()),
+            Ok(_) => Ok(line.trim_end().into()),
+            Err(e) => Err(format!("could not read from process: {}", e)),
+        }
+    }
+}
+
+/// How long a child is given to exit after being sent `quit`, before it is
+/// killed.
+const QUIT_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+
+        // Close both pipes, so that a child which doesn't understand `quit`
+        // sees the end of its input, and one blocked writing doesn't stay so
+        self.writer = Box::new(io::sink());
+        self.reader = Box::new(io::empty());
+
+        if let Some(child) = &mut self.child {
+            let deadline = Instant::now() + QUIT_GRACE_PERIOD;
+            while let Ok(None) = child.try_wait() {
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+enum ConnectionState {
+    NotStarted,
+    Open(Connection),
+
+    /// Something has already gone wrong with the child and been reported, so
+    /// it won't be talked to again.
+    Broken,
+
+    /// This is a copy of a component whose child had already started. The
+    /// child's state can't be copied, so this fails the first time it is
+    /// used, rather than sharing the child with the original.
+    Copied,
+}
+
+/// The native component which relays between the simulation and the child.
+pub struct ExternalComponent {
+    command: String,
+    pins: Vec<String>,
+    connection: Arc<Mutex<ConnectionState>>,
+}
+
+// A copy made before the child starts gets a child of its own when it runs
+impl Clone for ExternalComponent {
+    fn clone(&self) -> Self {
+        let state = match *self.connection.lock().unwrap() {
+            ConnectionState::NotStarted => ConnectionState::NotStarted,
+            _ => ConnectionState::Copied,
+        };
+        ExternalComponent {
+            command: self.command.clone(),
+            pins: self.pins.clone(),
+            connection: Arc::new(Mutex::new(state)),
+        }
+    }
+}
+
+impl fmt::Debug for ExternalComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalComponent")
+            .field("command", &self.command)
+            .field("pins", &self.pins)
+            .finish()
+    }
+}
+
+fn format_value(value: l::Value) -> &'static str {
+    match value {
+        l::Value::High => "H",
+        l::Value::Low => "L",
+        l::Value::Unknown => "X",
+    }
+}
+
+fn parse_value(value: &str) -> Result<l::Value, String> {
+    match value {
+        "H" => Ok(l::Value::High),
+        "L" => Ok(l::Value::Low),
+        "X" => Ok(l::Value::Unknown),
+        _ => Err(format!("invalid logic value {}", value)),
+    }
+}
+
+impl ExternalComponent {
+    pub fn new(command: &str, pins: Vec<String>) -> ExternalComponent {
+        ExternalComponent {
+            command: command.into(),
+            pins,
+            connection: Arc::new(Mutex::new(ConnectionState::NotStarted)),
+        }
+    }
+
+    /// Sends the current state of the component under the given header, then
+    /// handles replies until one which isn't a `set`, `log` or `fail`, which
+    /// is returned. Returns None without doing anything if the connection is
+    /// broken. If this fails, the connection becomes broken.
+    fn exchange(&self, header: &str, context: &mut NativeContext) -> Option<Result<String, String>> {
+        let mut state = self.connection.lock().unwrap();
+        if let ConnectionState::NotStarted = *state {
+            *state = match Connection::open(&self.command) {
+                Ok(connection) => ConnectionState::Open(connection),
+                Err(e) => {
+                    *state = ConnectionState::Broken;
+                    return Some(Err(e));
+                }
+            };
+        }
+        let connection = match &mut *state {
+            ConnectionState::Open(connection) => connection,
+            ConnectionState::Copied => {
+                *state = ConnectionState::Broken;
+                return Some(Err("cannot run a copy of an external component after its process has started".into()));
+            }
+            _ => return None,
+        };
+
+        let result = Self::exchange_on(connection, &self.pins, header, context);
+        if result.is_err() {
+            *state = ConnectionState::Broken;
+        }
+        Some(result)
+    }
+
+    fn exchange_on(connection: &mut Connection, pins: &[String], header: &str, context: &mut NativeContext) -> Result<String, String> {
+        connection.send(&format!("{} {}", header, context.time()))?;
+        for pin in pins.iter() {
+            connection.send(&format!("pin {} {}", pin, format_value(context.read_pin(pin))))?;
+        }
+        connection.send("end")?;
+
+        loop {
+            let line = connection.receive()?;
+            let (command, rest) = line.split_once(' ').unwrap_or((&line, ""));
+            match command {
+                "set" => {
+                    let (pin, value) = rest.split_once(' ')
+                        .ok_or_else(|| format!("invalid set: {}", line))?;
+                    if !pins.iter().any(|p| p == pin) {
+                        return Err(format!("no pin named {}", pin));
+                    }
+                    context.write_pin(pin, parse_value(value)?);
+                }
+                "log" => {
+                    let (level, message) = rest.split_once(' ').unwrap_or((rest, ""));
+                    context.log(level.parse::<LogLevel>()?, message);
+                }
+                "fail" => context.fail(rest),
+
+                _ => return Ok(line),
+            }
+        }
+    }
+}
+
+impl NativeComponent for ExternalComponent {
+    fn on_construct(&mut self, context: &mut NativeContext) {
+        match self.exchange("construct", context) {
+            Some(Ok(reply)) if reply == "ok" => (),
+            Some(Ok(reply)) => {
+                context.fail(&format!("{}: expected ok, got {}", self.command, reply));
+                *self.connection.lock().unwrap() = ConnectionState::Broken;
+            }
+            Some(Err(e)) => context.fail(&format!("{}: {}", self.command, e)),
+            None => (),
+        }
+    }
+
+    fn on_wake(&mut self, context: &mut NativeContext) -> Wait {
+        let reply = match self.exchange("wake", context) {
+            Some(Ok(reply)) => reply,
+            Some(Err(e)) => {
+                context.fail(&format!("{}: {}", self.command, e));
+                return Wait::Halt;
+            }
+            None => return Wait::Halt,
+        };
+
+        match reply.split_once(' ').unwrap_or((&reply, "")) {
+            ("sleep", time) if time.parse::<u64>().is_ok() => Wait::Sleep(time.parse().unwrap()),
+            ("trigger", "") => Wait::Trigger,
+            ("halt", "") => Wait::Halt,
+            _ => {
+                context.fail(&format!("{}: expected sleep, trigger or halt, got {}", self.command, reply));
+                *self.connection.lock().unwrap() = ConnectionState::Broken;
+                Wait::Halt
+            }
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use std::io::{self, Write};
+
+use flate2::{Compression, write::{GzEncoder, ZlibEncoder}};
+use m::ConnectedComponents;
+
+use crate::model as m;
+use crate::logic as l;
+use crate::trace::{TraceFilter, TraceWindow, TraceWriter};
+
+// Block types, hierarchy tags and variable types, as defined by GtkWave's
+// fstapi.h
+const BLOCK_HEADER: u8 = 0;
+const BLOCK_GEOMETRY: u8 = 3;
+const BLOCK_HIERARCHY: u8 = 4;
+const BLOCK_VALUE_CHANGES: u8 = 8;
+
+const HIERARCHY_SCOPE: u8 = 254;
+const HIERARCHY_UPSCOPE: u8 = 255;
+const SCOPE_TYPE_MODULE: u8 = 0;
+const VAR_TYPE_WIRE: u8 = 16;
+const VAR_DIRECTION_IMPLICIT: u8 = 0;
+
+const HEADER_LENGTH: u64 = 329;
+const HEADER_VERSION_LENGTH: usize = 128;
+const HEADER_DATE_LENGTH: usize = 119;
+const ENDIAN_TEST: f64 = std::f64::consts::E;
+
+// Value changes are buffered in memory, then compressed and written out as a
+// block once they reach about this many bytes
+const BLOCK_SIZE_LIMIT: usize = 1 << 22;
+
+fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn write_signed_varint(output: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_bit_clear = byte & 0x40 == 0;
+        if (value == 0 && sign_bit_clear) || (value == -1 && !sign_bit_clear) {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn write_u64(output: &mut Vec<u8>, value: u64) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_fixed_str(output: &mut Vec<u8>, string: &str, length: usize) {
+    let bytes = &string.as_bytes()[..string.len().min(length - 1)];
+    output.extend_from_slice(bytes);
+    output.resize(output.len() + length - bytes.len(), 0);
+}
+
+fn write_block(output: &mut Vec<u8>, block_type: u8, contents: &[u8]) {
+    // The section length counts itself, but not the block type
+    output.push(block_type);
+    write_u64(output, contents.len() as u64 + 8);
+    output.extend_from_slice(contents);
+}
+
+fn zlib_compress(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(bytes).expect("writing to memory failed");
+    encoder.finish().expect("writing to memory failed")
+}
+
+fn logic_to_symbol(value: l::Value) -> u8 {
+    match value {
+        l::Value::High => b'1',
+        l::Value::Low => b'0',
+        l::Value::Unknown => b'x',
+    }
+}
+
+fn write_change(output: &mut Vec<u8>, time_idx_delta: u64, value: l::Value) {
+    // Single-bit signals pack the value into the bottom bits of the delta
+    match value {
+        l::Value::Low => write_varint(output, time_idx_delta << 2),
+        l::Value::High => write_varint(output, (time_idx_delta << 2) | 0b10),
+        l::Value::Unknown => write_varint(output, (time_idx_delta << 4) | 1),
+    }
+}
+
+/// The value changes since the start of the current block, which have not
+/// been written out yet.
+struct ValueChangeBlock {
+    start_time: u64,
+    end_time: u64,
+
+    // Values of every signal at the start of the block
+    frame: Vec<l::Value>,
+
+    // Each distinct time at which something changed; changes refer to these
+    // by index
+    times: Vec<u64>,
+
+    // Encoded changes for each signal, and the time index of each signal's
+    // last change
+    changes: Vec<Vec<u8>>,
+    last_time_idxs: Vec<u64>,
+    size: usize,
+}
+
+impl ValueChangeBlock {
+    fn new(time: u64, values: Vec<l::Value>) -> ValueChangeBlock {
+        ValueChangeBlock {
+            start_time: time,
+            end_time: time,
+            changes: vec![vec![]; values.len()],
+            last_time_idxs: vec![0; values.len()],
+            frame: values,
+            times: vec![time],
+            size: 0,
+        }
+    }
+
+    fn record(&mut self, time: u64, previous_values: &[l::Value], values: &[l::Value]) {
+        self.end_time = time;
+
+        // Changes at the very start of the block just update the frame
+        if self.times.len() == 1 && time == self.start_time {
+            self.frame = values.to_vec();
+            return;
+        }
+
+        for (signal_idx, value) in values.iter().enumerate() {
+            if previous_values[signal_idx] == *value {
+                continue;
+            }
+
+            if self.times.last() != Some(&time) {
+                self.times.push(time);
+            }
+            let time_idx = self.times.len() as u64 - 1;
+            let delta = time_idx - self.last_time_idxs[signal_idx];
+            self.last_time_idxs[signal_idx] = time_idx;
+
+            let changes = &mut self.changes[signal_idx];
+            let length_before = changes.len();
+            write_change(changes, delta, *value);
+            self.size += changes.len() - length_before;
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut result = vec![];
+        write_u64(&mut result, self.start_time);
+        write_u64(&mut result, self.end_time);
+
+        // Memory a reader needs to hold every signal's decompressed changes,
+        // including the initial value of each
+        write_u64(&mut result, self.size as u64 + self.frame.len() as u64);
+
+        // Initial values
+        let frame = self.frame.iter().map(|v| logic_to_symbol(*v)).collect::<Vec<_>>();
+        let compressed_frame = zlib_compress(&frame);
+        write_varint(&mut result, frame.len() as u64);
+        if compressed_frame.len() < frame.len() {
+            write_varint(&mut result, compressed_frame.len() as u64);
+            write_varint(&mut result, self.frame.len() as u64);
+            result.extend_from_slice(&compressed_frame);
+        } else {
+            write_varint(&mut result, frame.len() as u64);
+            write_varint(&mut result, self.frame.len() as u64);
+            result.extend_from_slice(&frame);
+        }
+
+        // Changes for each signal, with offsets relative to the pack type
+        write_varint(&mut result, self.frame.len() as u64);
+        let changes_start = result.len();
+        result.push(b'Z');
+
+        // Some readers ignore the frame, so the initial values are repeated
+        // as changes at the start time
+        let mut offsets = vec![];
+        for (initial_value, later_changes) in self.frame.iter().zip(&self.changes) {
+            let mut changes = vec![];
+            write_change(&mut changes, 0, *initial_value);
+            changes.extend_from_slice(later_changes);
+
+            offsets.push((result.len() - changes_start) as i64);
+            let compressed_changes = zlib_compress(&changes);
+            if compressed_changes.len() < changes.len() {
+                write_varint(&mut result, changes.len() as u64);
+                result.extend_from_slice(&compressed_changes);
+            } else {
+                write_varint(&mut result, 0);
+                result.extend_from_slice(&changes);
+            }
+        }
+
+        // Table of where each signal's changes are, as deltas between
+        // offsets (the format can also describe signals without changes, or
+        // aliases, but every signal here has at least its initial value)
+        let mut chain = vec![];
+        let mut previous_offset = 0;
+        for offset in offsets {
+            write_signed_varint(&mut chain, ((offset - previous_offset) << 1) | 1);
+            previous_offset = offset;
+        }
+        result.extend_from_slice(&chain);
+        write_u64(&mut result, chain.len() as u64);
+
+        // Time table, as deltas
+        let mut times = vec![];
+        let mut previous_time = 0;
+        for time in &self.times {
+            write_varint(&mut times, time - previous_time);
+            previous_time = *time;
+        }
+        let compressed_times = zlib_compress(&times);
+        if compressed_times.len() < times.len() {
+            result.extend_from_slice(&compressed_times);
+            write_u64(&mut result, times.len() as u64);
+            write_u64(&mut result, compressed_times.len() as u64);
+        } else {
+            result.extend_from_slice(&times);
+            write_u64(&mut result, times.len() as u64);
+            write_u64(&mut result, times.len() as u64);
+        }
+        write_u64(&mut result, self.times.len() as u64);
+
+        result
+    }
+}
+
+/// Writes traces in GtkWave's compressed FST format, which is far smaller
+/// than VCD for long simulations.
+#[derive(Default)]
+pub struct FstGenerator {
+    filter: TraceFilter,
+    window: TraceWindow<Vec<l::Value>>,
+    traced_pins: Vec<m::PinConnection>,
+
+    hierarchy: Vec<u8>,
+    scope_count: u64,
+
+    values: Vec<l::Value>,
+    block: Option<ValueChangeBlock>,
+    blocks: Vec<u8>,
+    block_count: u64,
+
+    start_time: Option<u64>,
+    end_time: u64,
+}
+
+impl FstGenerator {
+    pub fn new(filter: TraceFilter) -> FstGenerator {
+        FstGenerator {
+            filter,
+            ..FstGenerator::default()
+        }
+    }
+
+    fn open_scope(&mut self, name: &str) {
+        self.hierarchy.push(HIERARCHY_SCOPE);
+        self.hierarchy.push(SCOPE_TYPE_MODULE);
+        self.hierarchy.extend_from_slice(name.as_bytes());
+        self.hierarchy.push(0);
+        // Component name, which we don't use
+        self.hierarchy.push(0);
+        self.scope_count += 1;
+    }
+
+    fn close_scope(&mut self) {
+        self.hierarchy.push(HIERARCHY_UPSCOPE);
+    }
+
+    fn add_var(&mut self, name: &str) {
+        self.hierarchy.push(VAR_TYPE_WIRE);
+        self.hierarchy.push(VAR_DIRECTION_IMPLICIT);
+        self.hierarchy.extend_from_slice(name.as_bytes());
+        self.hierarchy.push(0);
+        // Width, then 0 to say this isn't an alias of another var
+        write_varint(&mut self.hierarchy, 1);
+        write_varint(&mut self.hierarchy, 0);
+    }
+
+    pub fn generate_header(&mut self, model: &m::Model) {
+        self.traced_pins = self.filter.traced_pins(model);
+
+        self.open_scope("simulation");
+        for (component_idx, component) in model.components.iter().enumerate() {
+            let traced_pin_idxs = self.traced_pins.iter()
+                .filter(|pc| pc.component_idx == component_idx)
+                .map(|pc| pc.pin_idx)
+                .collect::<Vec<_>>();
+            if traced_pin_idxs.is_empty() {
+                continue;
+            }
+
+            self.open_scope(&component.instance_name);
+            for pin_idx in traced_pin_idxs {
+                self.add_var(&component.definition.pins[pin_idx].name);
+            }
+            self.close_scope();
+        }
+        self.close_scope();
+    }
+
+    pub fn step(&mut self, model: &m::Model, _modifications: &[m::ComponentStateModification]) {
+        let values = self.traced_pins.iter()
+            .map(|pc| model.pin_value(pc))
+            .collect::<Vec<_>>();
+
+        for (time, values) in self.window.admit(&self.filter, model.time_elapsed, values) {
+            self.record(time, values);
+        }
+    }
+
+    fn record(&mut self, time: u64, values: Vec<l::Value>) {
+        if self.traced_pins.is_empty() {
+            return;
+        }
+
+        self.start_time.get_or_insert(time);
+        self.end_time = time;
+
+        match &mut self.block {
+            Some(block) => block.record(time, &self.values, &values),
+            None => self.block = Some(ValueChangeBlock::new(time, values.clone())),
+        }
+        self.values = values;
+
+        if self.block.as_ref().is_some_and(|b| b.size >= BLOCK_SIZE_LIMIT) {
+            self.flush_block();
+        }
+    }
+
+    fn flush_block(&mut self) {
+        if let Some(block) = self.block.take() {
+            write_block(&mut self.blocks, BLOCK_VALUE_CHANGES, &block.encode());
+            self.block_count += 1;
+        }
+    }
+
+    fn encode_header(&self) -> Vec<u8> {
+        let signal_count = self.traced_pins.len() as u64;
+
+        let mut result = vec![];
+        write_u64(&mut result, self.start_time.unwrap_or(0));
+        write_u64(&mut result, self.end_time);
+        // Readers use this to detect the byte order of floats
+        result.extend_from_slice(&ENDIAN_TEST.to_ne_bytes());
+        write_u64(&mut result, BLOCK_SIZE_LIMIT as u64);
+        write_u64(&mut result, self.scope_count);
+        write_u64(&mut result, signal_count);
+        write_u64(&mut result, signal_count);
+        write_u64(&mut result, self.block_count);
+        // TODO: customisable time unit, shared with the VCD generator
+        result.push(-3i8 as u8);
+        write_fixed_str(&mut result, "Hiloz", HEADER_VERSION_LENGTH);
+        write_fixed_str(&mut result, "", HEADER_DATE_LENGTH);
+        // File type - Verilog
+        result.push(0);
+        // Time zero
+        write_u64(&mut result, 0);
+
+        debug_assert_eq!(result.len() as u64 + 8, HEADER_LENGTH);
+        result
+    }
+
+    fn encode_geometry(&self) -> Vec<u8> {
+        let mut widths = vec![];
+        for _ in &self.traced_pins {
+            write_varint(&mut widths, 1);
+        }
+
+        let mut result = vec![];
+        write_u64(&mut result, widths.len() as u64);
+        write_u64(&mut result, self.traced_pins.len() as u64);
+        let compressed_widths = zlib_compress(&widths);
+        if compressed_widths.len() < widths.len() {
+            result.extend_from_slice(&compressed_widths);
+        } else {
+            result.extend_from_slice(&widths);
+        }
+        result
+    }
+
+    fn encode_hierarchy(&self) -> io::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&self.hierarchy)?;
+
+        let mut result = vec![];
+        write_u64(&mut result, self.hierarchy.len() as u64);
+        result.extend_from_slice(&encoder.finish()?);
+        Ok(result)
+    }
+
+    pub fn contents(&mut self) -> io::Result<Vec<u8>> {
+        self.flush_block();
+
+        let mut result = vec![];
+        write_block(&mut result, BLOCK_HEADER, &self.encode_header());
+        result.extend_from_slice(&self.blocks);
+        write_block(&mut result, BLOCK_GEOMETRY, &self.encode_geometry());
+        write_block(&mut result, BLOCK_HIERARCHY, &self.encode_hierarchy()?);
+        Ok(result)
+    }
+}
+
+impl TraceWriter for FstGenerator {
+    fn generate_header(&mut self, model: &m::Model) {
+        FstGenerator::generate_header(self, model)
+    }
+
+    fn step(&mut self, model: &m::Model, modifications: &[m::ComponentStateModification]) {
+        FstGenerator::step(self, model, modifications)
+    }
+
+    fn write(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        output.write_all(&self.contents()?)
+    }
+}
@@ -0,0 +1,57 @@
+#![feature(bindings_after_at)]
+#![feature(box_patterns)]
+#![feature(or_patterns)]
+
+//! Hiloz is a digital system simulator. The simplest way to drive it from
+//! Rust is through a [`Simulation`]:
+//!
+//! ```no_run
+//! use hiloz::{Simulation, Value};
+//!
+//! let mut simulation = Simulation::load("model.hlzm").unwrap();
+//! simulation.drive_pin("cpu.reset", Value::High).unwrap();
+//! simulation.run_for(100);
+//! assert_eq!(simulation.pin("cpu.ready"), Some(Value::High));
+//! ```
+//!
+//! Components written in Rust implement [`NativeComponent`], and are
+//! registered in a [`NativeRegistry`]. Everything else is internal to Hiloz,
+//! and may change at any time.
+
+#[macro_use]
+extern crate pest_derive;
+
+mod logic;
+mod script_engine;
+mod script_compiler;
+mod fst;
+mod stimulus;
+mod simulation;
+mod native;
+mod external;
+mod random;
+mod library;
+
+// These are used by the command line tool, but aren't part of the API
+#[doc(hidden)] pub mod model;
+#[doc(hidden)] pub mod parser;
+#[doc(hidden)] pub mod model_compiler;
+#[doc(hidden)] pub mod vcd;
+#[doc(hidden)] pub mod trace;
+#[doc(hidden)] pub mod compare;
+#[doc(hidden)] pub mod log;
+#[doc(hidden)] pub mod testbench;
+#[doc(hidden)] pub mod debugger;
+#[doc(hidden)] pub mod checkpoint;
+
+pub use simulation::Simulation;
+pub use logic::Value;
+pub use native::*;
+pub use log::LogMessage;
+pub use model::Failure;
+
+/// The type of the arguments which native components are created with.
+pub use script_engine::Object;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,31 @@
+//! Libraries of components which are bundled with Hiloz, and can be used in
+//! any model with `import name;`.
+//!
+//! The only library is `std`, which has:
+//!   - Gates: `Buffer`, `NotGate`, `AndGate`, `OrGate`, `NandGate`, `NorGate`,
+//!     `XorGate` and `XnorGate`.
+//!   - Latches and flip-flops: `SRLatch`, `DLatch`, `DFlipFlop`, `TFlipFlop`
+//!     and `JKFlipFlop`.
+//!   - `Counter4` and `ShiftRegister4`.
+//!   - Multiplexers: `Mux2` and `Mux4`.
+//!   - Sources: `Clock(period)` and `ResetGenerator(time)`.
+//!   - 7400-series chips: `IC7400`, `IC7402`, `IC7404`, `IC7408`, `IC7432`,
+//!     `IC7486` and `IC7474`.
+
+const STD: &[&str] = &[
+    include_str!("library/std/gates.hlzm"),
+    include_str!("library/std/sequential.hlzm"),
+    include_str!("library/std/counters.hlzm"),
+    include_str!("library/std/mux.hlzm"),
+    include_str!("library/std/sources.hlzm"),
+    include_str!("library/std/ttl.hlzm"),
+];
+
+/// The sources of a bundled library, or None if there is no library with
+/// this name.
+pub fn sources(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "std" => Some(STD),
+        _ => None,
+    }
+}
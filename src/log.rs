@@ -0,0 +1,84 @@
+use std::{fmt, str::FromStr};
+use serde::{Serialize, Deserialize};
+
+use crate::script_engine as se;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!("unknown log level {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        })
+    }
+}
+
+/// A message written by a `print` or `log` statement.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct LogMessage {
+    pub time: u64,
+    pub instance_name: String,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {} {}: {}", self.time, self.level, self.instance_name, self.message)
+    }
+}
+
+/// Counts the `{}` placeholders in a format string, not including escaped
+/// braces.
+pub fn placeholder_count(format: &str) -> usize {
+    format.replace("{{", "").replace("}}", "").matches("{}").count()
+}
+
+/// Substitutes each `{}` placeholder in a format string with the next
+/// argument. `{{` and `}}` are literal braces. Any placeholders without an
+/// argument are left as they are.
+pub fn format_message(format: &str, arguments: &[se::Object]) -> String {
+    let mut result = String::new();
+    let mut arguments = arguments.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                result.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                match arguments.next() {
+                    Some(argument) => result.push_str(&argument.to_string()),
+                    None => result.push_str("{}"),
+                }
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum Value {
     Unknown,
     Low,
@@ -26,3 +28,30 @@ impl From<bool> for Value {
         }
     }
 }
+
+/// A transition of a value between low and high.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+impl Edge {
+    /// Whether a change between two values is this edge. Changes to or from
+    /// an unknown value aren't edges.
+    pub fn is(self, from: Value, to: Value) -> bool {
+        match self {
+            Edge::Rising => from == Value::Low && to == Value::High,
+            Edge::Falling => from == Value::High && to == Value::Low,
+        }
+    }
+}
+
+impl std::fmt::Display for Edge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edge::Rising => write!(f, "rising"),
+            Edge::Falling => write!(f, "falling"),
+        }
+    }
+}
@@ -1,67 +1,467 @@
-#![feature(bindings_after_at)]
-#![feature(box_patterns)]
-#![feature(or_patterns)]
-
-use std::{error::Error, fs::File, path::PathBuf, io::prelude::*};
+use std::{error::Error, fs::File, path::{Path, PathBuf}, process, io::{self, prelude::*}};
 use structopt::StructOpt;
 
-#[macro_use]
-extern crate pest_derive;
+use hiloz::{model, model_compiler, parser, vcd, trace, compare, log, testbench, debugger, checkpoint, Simulation};
+
+#[derive(StructOpt)]
+#[structopt(name="simulator")]
+enum Opt {
+    /// Simulate a model and write a trace of its pins
+    Run(RunOpt),
+
+    /// Simulate a model and compare its trace against a reference VCD file,
+    /// reporting the first divergence of each signal
+    Compare(CompareOpt),
 
-mod logic;
-mod model;
-mod script_engine;
-mod parser;
-mod script_compiler;
-mod model_compiler;
-mod vcd;
+    /// Run every `test` block in a model, reporting which pass and fail
+    Test(TestOpt),
 
-#[cfg(test)]
-mod tests;
+    /// Step through a simulation interactively
+    Debug(DebugOpt),
+}
 
 #[derive(StructOpt)]
-#[structopt(name="simulator")]
-struct Opt {
+struct RunOpt {
     /// Input model file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
 
-    /// Output VCD file
+    /// Output trace file
     #[structopt(parse(from_os_str))]
     output: PathBuf,
 
+    /// Format of the output trace, either "vcd" or "fst" (defaults to the
+    /// extension of the output file, or VCD if it is not recognised)
+    #[structopt(long="format")]
+    format: Option<trace::TraceFormat>,
+
+    /// The maximum number of time units to simulate for
+    #[structopt(short="t", long="max-time", default_value="100000000000")]
+    max_time: u64,
+
+    /// Carry on from a checkpoint saved by an earlier run of the same model,
+    /// rather than starting from the beginning
+    #[structopt(long="resume", parse(from_os_str))]
+    resume: Option<PathBuf>,
+
+    /// Save a checkpoint to this file once the simulation is complete
+    #[structopt(long="save-checkpoint", parse(from_os_str))]
+    save_checkpoint: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    imports: ImportOpt,
+
+    #[structopt(flatten)]
+    trace: TraceOpt,
+
+    #[structopt(flatten)]
+    random: RandomOpt,
+
+    #[structopt(flatten)]
+    log: LogOpt,
+}
+
+#[derive(StructOpt)]
+struct CompareOpt {
+    /// Input model file
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Known-good VCD file to compare against
+    #[structopt(parse(from_os_str))]
+    reference: PathBuf,
+
     /// The maximum number of time units to simulate for
     #[structopt(short="t", long="max-time", default_value="100000000000")]
     max_time: u64,
+
+    /// How many time units a change may be early or late by, compared to
+    /// the reference, and still match
+    #[structopt(long="skew", default_value="0")]
+    skew: u64,
+
+    #[structopt(flatten)]
+    imports: ImportOpt,
+
+    #[structopt(flatten)]
+    trace: TraceOpt,
+
+    #[structopt(flatten)]
+    random: RandomOpt,
+
+    #[structopt(flatten)]
+    log: LogOpt,
+}
+
+#[derive(StructOpt)]
+struct TestOpt {
+    /// Input model file
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The maximum number of time units to run each test for, after which
+    /// it fails if its script hasn't finished
+    #[structopt(short="t", long="max-time", default_value="100000000000")]
+    max_time: u64,
+
+    #[structopt(flatten)]
+    imports: ImportOpt,
+
+    #[structopt(flatten)]
+    log: LogOpt,
+}
+
+#[derive(StructOpt)]
+struct DebugOpt {
+    /// Input model file
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The maximum number of time units which `continue` will run to
+    #[structopt(short="t", long="max-time", default_value="100000000000")]
+    max_time: u64,
+
+    #[structopt(flatten)]
+    imports: ImportOpt,
+
+    #[structopt(flatten)]
+    random: RandomOpt,
+
+    #[structopt(flatten)]
+    log: LogOpt,
+}
+
+#[derive(StructOpt)]
+struct ImportOpt {
+    /// Look for files imported by the model in this directory, if they
+    /// aren't found relative to the file which imports them (can be given
+    /// multiple times, and directories are searched in order)
+    #[structopt(short="I", long="include", number_of_values=1, parse(from_os_str))]
+    include: Vec<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct TraceOpt {
+    /// Only trace pins matching this glob pattern, such as "cpu.*" or "*.clk"
+    /// (can be given multiple times, and adds to any `trace` directives in
+    /// the model)
+    #[structopt(long="trace", number_of_values=1)]
+    trace: Vec<String>,
+
+    /// Don't trace changes before this time
+    #[structopt(long="trace-from")]
+    trace_from: Option<u64>,
+
+    /// Don't trace changes after this time
+    #[structopt(long="trace-to")]
+    trace_to: Option<u64>,
+}
+
+impl TraceOpt {
+    fn filter(&self, model: &model::Model) -> trace::TraceFilter {
+        trace::TraceFilter {
+            patterns: [&model.trace_patterns[..], &self.trace[..]].concat(),
+            from: self.trace_from,
+            to: self.trace_to,
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct RandomOpt {
+    /// Seed `random` and `random_bit` in scripts with this, instead of the
+    /// model's `seed` directive
+    #[structopt(long="seed")]
+    seed: Option<u64>,
+
+    /// Run scripts in a random order each step, chosen using this seed,
+    /// rather than in the order their components were declared. Pins never
+    /// depend on this order, so it only reorders messages and failures from
+    /// the same step
+    #[structopt(long="shuffle-seed")]
+    shuffle_seed: Option<u64>,
+}
+
+impl RandomOpt {
+    fn apply(&self, model: &mut model::Model) {
+        if let Some(seed) = self.seed {
+            model.seed_random(seed);
+        }
+        println!("Random seed is {}", model.seed);
+
+        if let Some(seed) = self.shuffle_seed {
+            println!("Shuffling execution order with seed {}", seed);
+            model.shuffle_execution_order(seed);
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct LogOpt {
+    /// Only show messages from `print` and `log` at this level or above,
+    /// one of "debug", "info", "warn" or "error"
+    #[structopt(long="log-level", default_value="info")]
+    log_level: log::LogLevel,
+
+    /// Write messages from `print` and `log` to this file, rather than
+    /// standard output
+    #[structopt(long="log-file", parse(from_os_str))]
+    log_file: Option<PathBuf>,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let opt = Opt::from_args();
+impl LogOpt {
+    fn output(&self) -> io::Result<Box<dyn Write>> {
+        Ok(match &self.log_file {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        })
+    }
 
-    // Load model
-    let mut input_model = "".into();
-    File::open(opt.input)?.read_to_string(&mut input_model)?;
-    let mut model = model::Model::compile(input_model)?;
+    /// Records the random seed in the log file, so that the run it came from
+    /// can be reproduced. This is already printed when logging to standard
+    /// output.
+    fn write_seed(&self, model: &model::Model, output: &mut dyn Write) -> io::Result<()> {
+        if self.log_file.is_some() {
+            writeln!(output, "Random seed is {}", model.seed)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_log_messages(messages: &[log::LogMessage], level: log::LogLevel, output: &mut dyn Write) -> io::Result<()> {
+    for message in messages.iter().filter(|m| m.level >= level) {
+        writeln!(output, "{}", message)?;
+    }
+    Ok(())
+}
+
+fn load_model(input: &Path, imports: &ImportOpt) -> Result<model::Model, Box<dyn Error>> {
+    load_model_with(input, imports, parser::parse_file)
+}
+
+fn load_model_with<F>(input: &Path, imports: &ImportOpt, parse: F) -> Result<model::Model, Box<dyn Error>>
+where F : FnOnce(&Path, &[PathBuf]) -> Result<parser::Node, Box<dyn Error>>
+{
+    let model = model_compiler::compile_model(&parse(input, &imports.include)?)
+        .map_err(|e| model_compiler::in_file(e, &input.display().to_string()))?;
 
     println!("Model loaded with:");
     println!("  - {} component definitions", model.component_definitions.len());
     println!("  - {} component instances", model.components.len());
     println!("  - {} connections", model.connections.len());
     println!("  - {} script interpreters", model.interpreters.len());
-    println!("Simulating for up to {} time units", opt.max_time);
 
-    // Prepare VCD generator
-    let mut vcd = vcd::VcdGenerator::default();
-    vcd.generate_header(&model);
+    Ok(model)
+}
+
+/// Reads a checkpoint to resume from, checking that it was saved from the
+/// same model.
+fn load_checkpoint(path: &Path, model: &model::Model) -> Result<model::Model, Box<dyn Error>> {
+    let resumed = checkpoint::load(&mut io::BufReader::new(File::open(path)?))?;
+    if resumed.component_definitions != model.component_definitions
+        || resumed.connections != model.connections {
+        return Err(format!("{} was saved from a different model", path.display()).into());
+    }
+
+    println!("Resuming from {} at {} time units", path.display(), resumed.time_elapsed);
+    Ok(resumed)
+}
+
+fn simulate(model: &mut model::Model, max_time: u64, resumed: bool, trace: &mut dyn trace::TraceWriter, log: &LogOpt) -> Result<(), Box<dyn Error>> {
+    println!("Simulating for up to {} time units", max_time);
+    let mut log_output = log.output()?;
+    log.write_seed(model, &mut log_output)?;
+
+    // A resumed model has already been constructed, and its messages so far
+    // were written by the run which saved it
+    trace.generate_header(model);
+    if resumed {
+        model.take_log_messages();
+    } else {
+        model.construct();
+        write_log_messages(&model.take_log_messages(), log.log_level, &mut log_output)?;
+    }
+
+    // Write messages out as they're logged, rather than all at the end, and
+    // then drop them so that long runs don't hold on to every one
+    loop {
+        let modifications = match model.step() {
+            model::StepResult::Ok(modifications) => modifications,
+            model::StepResult::Halt => break,
+            model::StepResult::Paused(_) => unreachable!("steps without a pause function never pause"),
+        };
+        trace.step(model, &modifications);
+        write_log_messages(&model.take_log_messages(), log.log_level, &mut log_output)?;
+
+        if model.time_elapsed >= max_time {
+            break;
+        }
+    }
 
-    // Simulate
-    model.construct();
-    model.run(opt.max_time, |a, b| vcd.step(a, b));
+    println!("Simulation complete at {}{} time units", model.time_elapsed, if model.time_elapsed > max_time { "(!!!)" } else { "" });
 
-    println!("Simulation complete at {}{} time units", model.time_elapsed, if model.time_elapsed > opt.max_time { "(!!!)" } else { "" });
+    if model.failed() {
+        println!("{} failure(s):", model.failures.len());
+        for failure in &model.failures {
+            println!("  - {}", failure);
+        }
+    }
 
-    // Write VCD
-    File::create(opt.output)?.write_all(vcd.contents().as_bytes())?;
+    Ok(())
+}
+
+fn run(opt: RunOpt) -> Result<(), Box<dyn Error>> {
+    // Reseeding would start every component's numbers again part of the way
+    // through the run
+    if opt.resume.is_some() && opt.random.seed.is_some() {
+        return Err("--seed cannot be used with --resume, since a checkpoint carries on with the seed it was saved with".into());
+    }
+
+    let mut model = load_model(&opt.input, &opt.imports)?;
+    if let Some(path) = &opt.resume {
+        model = load_checkpoint(path, &model)?;
+    }
+    opt.random.apply(&mut model);
+
+    let trace_format = match opt.format {
+        Some(format) => format,
+        None => trace::TraceFormat::from_path(&opt.output),
+    };
+    let mut trace = trace_format.writer(opt.trace.filter(&model));
+    simulate(&mut model, opt.max_time, opt.resume.is_some(), trace.as_mut(), &opt.log)?;
+
+    trace.write(&mut File::create(opt.output)?)?;
+
+    if let Some(path) = &opt.save_checkpoint {
+        let mut output = io::BufWriter::new(File::create(path)?);
+        checkpoint::save(&model, &mut output)?;
+        output.flush()?;
+        println!("Checkpoint saved to {}", path.display());
+    }
+
+    if model.failed() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn compare(opt: CompareOpt) -> Result<(), Box<dyn Error>> {
+    let mut reference = "".into();
+    File::open(&opt.reference)?.read_to_string(&mut reference)?;
+    let reference = vcd::parse_vcd(&reference)
+        .map_err(|e| format!("could not parse {}: {}", opt.reference.display(), e))?;
+
+    let mut model = load_model(&opt.input, &opt.imports)?;
+    opt.random.apply(&mut model);
+    let mut trace = vcd::VcdGenerator::new(opt.trace.filter(&model));
+    simulate(&mut model, opt.max_time, false, &mut trace, &opt.log)?;
+    let actual = vcd::parse_vcd(&trace.contents())?;
+
+    let divergences = compare::compare_traces(&reference, &actual, opt.skew);
+    if divergences.is_empty() {
+        println!("Trace matches {}", opt.reference.display());
+        if model.failed() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    for divergence in &divergences {
+        println!("{}", divergence);
+    }
+    println!("{} signal(s) diverged from {}", divergences.len(), opt.reference.display());
+    process::exit(1);
+}
 
+fn test(opt: TestOpt) -> Result<(), Box<dyn Error>> {
+    let parsed = parser::parse_file(&opt.input, &opt.imports.include)?;
+    let mut log_output = opt.log.output()?;
+
+    let results = testbench::run_tests(&parsed, opt.max_time)
+        .map_err(|e| model_compiler::in_file(e, &opt.input.display().to_string()))?;
+    println!("Running {} test(s)", results.len());
+
+    for result in &results {
+        write_log_messages(&result.log_messages, opt.log.log_level, &mut log_output)?;
+        println!("test {:?} ... {}", result.name, if result.passed() { "ok" } else { "FAILED" });
+        for failure in &result.failures {
+            println!("  - {}", failure);
+        }
+        if !result.finished {
+            println!("  - script did not finish by {} time units", result.time_elapsed);
+        }
+    }
+
+    let failed = results.iter().filter(|r| !r.passed()).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
     Ok(())
 }
+
+fn debug(opt: DebugOpt) -> Result<(), Box<dyn Error>> {
+    let mut model = load_model_with(&opt.input, &opt.imports, parser::parse_file_with_source_lines)?;
+    opt.random.apply(&mut model);
+    let mut log_output = opt.log.output()?;
+    opt.log.write_seed(&model, &mut log_output)?;
+    let mut debugger = debugger::Debugger::new(Simulation::new(model), opt.max_time);
+    println!("Type \"help\" for a list of commands");
+
+    let mut failures_written = 0;
+    let mut line = String::new();
+    loop {
+        // Show anything which happened since the last command
+        let simulation = debugger.simulation_mut();
+        write_log_messages(&simulation.take_log_messages(), opt.log.log_level, &mut log_output)?;
+        for failure in &simulation.failures()[failures_written..] {
+            println!("failure: {}", failure);
+        }
+        failures_written = simulation.failures().len();
+
+        print!("(hiloz) ");
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match line.parse::<debugger::Command>() {
+            Ok(debugger::Command::Quit) => break,
+            Ok(command) => match debugger.execute(command) {
+                Ok(output) => if !output.is_empty() { println!("{}", output) },
+                Err(e) => println!("error: {}", e),
+            },
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// The subcommands, and the options which are handled before one is chosen.
+const SUBCOMMANDS: &[&str] = &["run", "compare", "test", "debug", "help", "-h", "--help", "-V", "--version"];
+
+fn main() {
+    // Before there were subcommands, `hiloz <input> <output>` ran a model, so
+    // anything which doesn't start with a subcommand still does
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    if args.get(1).is_some_and(|arg| !SUBCOMMANDS.iter().any(|s| arg == s)) {
+        args.insert(1, "run".into());
+    }
+
+    let result = match Opt::from_iter(args) {
+        Opt::Run(opt) => run(opt),
+        Opt::Compare(opt) => compare(opt),
+        Opt::Test(opt) => test(opt),
+        Opt::Debug(opt) => debug(opt),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e.to_string().trim_end());
+        process::exit(1);
+    }
+}
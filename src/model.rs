@@ -6,45 +6,122 @@ use super::logic;
 use super::script_engine as se;
 use super::model_compiler as mc;
 use super::parser as p;
+use super::log::{LogLevel, LogMessage};
+use super::native::NativeRegistry;
+use super::random::Rng;
 
-use std::{cmp::Ordering, collections::{BinaryHeap, HashMap, VecDeque, binary_heap}, sync::Arc};
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap, VecDeque, binary_heap}, fmt, sync::Arc};
+use serde::{Serialize, Deserialize};
 use std::collections::HashSet;
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct PinDefinition {
     pub name: String,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Pin {
     pub definition: Arc<PinDefinition>,
     pub value: logic::Value,
     pub pull: logic::Value,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct PinConnection {
     pub component_idx: usize,
     pub pin_idx: usize
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Connection {
     pub pins: Vec<PinConnection>,
+
+    /// If this is set, changes to the value driven onto the connection take
+    /// this long to be seen by the pins on it.
+    pub delay: Option<Delay>,
+}
+
+/// How a delay treats changes which come quicker than it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum DelayKind {
+    /// A change cancels any which haven't arrived yet, so pulses shorter than
+    /// the delay are swallowed, like in a real gate.
+    Inertial,
+
+    /// Every change arrives, however short the pulse.
+    Transport,
+}
+
+/// A propagation delay on a connection.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Delay {
+    pub time: u64,
+    pub kind: DelayKind,
+}
+
+/// The value which the pins on a delayed connection see, and the changes
+/// which are on their way through the delay.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct DelayedValue {
+    pub value: Option<logic::Value>,
+
+    /// Changes which haven't arrived yet, as the time they arrive and the new
+    /// value, soonest first.
+    pub pending: Vec<(u64, Option<logic::Value>)>,
+}
+
+impl Default for DelayedValue {
+    fn default() -> DelayedValue {
+        DelayedValue { value: Some(logic::Value::Unknown), pending: vec![] }
+    }
+}
+
+impl DelayedValue {
+    /// The value which the connection will end up at once every pending
+    /// change has arrived.
+    fn target(&self) -> Option<logic::Value> {
+        self.pending.last().map(|(_, value)| *value).unwrap_or(self.value)
+    }
+
+    /// Sends a change in the driven value through a delay, starting now.
+    fn schedule(&mut self, delay: &Delay, now: u64, value: Option<logic::Value>) {
+        if value == self.target() {
+            return;
+        }
+
+        if delay.kind == DelayKind::Inertial {
+            self.pending.clear();
+            if value == self.value {
+                return;
+            }
+        }
+        self.pending.push((now + delay.time, value));
+    }
+
+    /// Applies the changes which have arrived by now.
+    fn deliver(&mut self, now: u64) {
+        while let Some((time, value)) = self.pending.first() {
+            if *time > now {
+                break;
+            }
+            self.value = *value;
+            self.pending.remove(0);
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct VariableDefinition {
     pub name: String,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Variable {
     pub definition: Arc<VariableDefinition>,
     pub value: se::Object,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct ComponentDefinition {
     pub name: String,
     pub pins: Vec<Arc<PinDefinition>>,
@@ -52,6 +129,68 @@ pub struct ComponentDefinition {
     pub constructor: Option<Arc<se::Function>>,
     pub functions: Vec<Arc<se::Function>>,
     pub script: Option<Arc<se::Function>>,
+    pub timing_checks: Vec<TimingCheck>,
+
+    /// The values of the generic parameters which this definition was
+    /// compiled with, if it has any.
+    pub generic_arguments: Vec<(String, i64)>,
+}
+
+/// A timing requirement on the pins of a component, which is checked as they
+/// change. Pins are given by index.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub enum TimingCheck {
+    /// The data pin must not change for this long before the clock edge.
+    Setup { data: usize, clock: usize, edge: logic::Edge, time: u64 },
+
+    /// The data pin must not change for this long after the clock edge.
+    Hold { data: usize, clock: usize, edge: logic::Edge, time: u64 },
+
+    /// Every high or low pulse on the pin must last at least this long.
+    Width { pin: usize, time: u64 },
+}
+
+impl TimingCheck {
+    fn pins(&self) -> Vec<usize> {
+        match self {
+            Self::Setup { data, clock, .. } | Self::Hold { data, clock, .. } => vec![*data, *clock],
+            Self::Width { pin, .. } => vec![*pin],
+        }
+    }
+}
+
+/// When a pin with timing checks on it last changed.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct PinHistory {
+    pub value: logic::Value,
+    pub changed_at: Option<u64>,
+    pub rose_at: Option<u64>,
+    pub fell_at: Option<u64>,
+
+    /// When the pin reached its current value from the opposite one, or None
+    /// if it came from an unknown value.
+    pub level_since: Option<u64>,
+}
+
+impl Default for PinHistory {
+    fn default() -> Self {
+        PinHistory {
+            value: logic::Value::Unknown,
+            changed_at: None,
+            rose_at: None,
+            fell_at: None,
+            level_since: None,
+        }
+    }
+}
+
+impl PinHistory {
+    fn edge_at(&self, edge: logic::Edge) -> Option<u64> {
+        match edge {
+            logic::Edge::Rising => self.rose_at,
+            logic::Edge::Falling => self.fell_at,
+        }
+    }
 }
 
 impl ComponentDefinition {
@@ -61,6 +200,15 @@ impl ComponentDefinition {
             .position(|pin_def| &pin_def.name == name)
     }
 
+    /// The index of the first pin in a pin array, and how many pins it has.
+    pub fn pin_array(&self, name: &str) -> Option<(usize, usize)> {
+        let first = self.pin_idx(&format!("{}[0]", name))?;
+        let size = (first..self.pins.len())
+            .take_while(|idx| self.pins[*idx].name == format!("{}[{}]", name, idx - first))
+            .count();
+        Some((first, size))
+    }
+
     pub fn variable_idx(&self, name: &String) -> Option<usize> {
         self.variables
             .iter()
@@ -68,32 +216,55 @@ impl ComponentDefinition {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Component {
     pub instance_name: String,
     pub definition: Arc<ComponentDefinition>,
     pub pins: Vec<Pin>,
     pub variables: Vec<Variable>,
-    pub dumps: Vec<se::Object>,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+/// An assertion or expectation which didn't hold during simulation.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub time: u64,
+    pub instance_name: String,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.time, self.instance_name, self.message)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct TimingQueueEntry {
     interpreter_idx: usize,
     time_remaining: u64,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct TriggerListEntry {
     interpreter_idx: usize,
 }
 
+/// A pin assignment made with `after`, waiting for its time to come.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPinWrite {
+    pub time: u64,
+    pub pin: PinConnection,
+    pub value: logic::Value,
+}
+
 impl PartialOrd for TimingQueueEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
+// Soonest first. Entries with the same time are all woken in the same step,
+// so the tie-break on index only keeps the order total.
 impl Ord for TimingQueueEntry {
     fn cmp(&self, other: &Self) -> Ordering {
         other.time_remaining.cmp(&self.time_remaining)
@@ -101,7 +272,7 @@ impl Ord for TimingQueueEntry {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Model {
     pub component_definitions: Vec<Arc<ComponentDefinition>>,
     pub components: Vec<Component>,
@@ -112,16 +283,74 @@ pub struct Model {
     pub time_elapsed: u64,
     pub suspended_timing_queue: BinaryHeap<TimingQueueEntry>,
     pub suspended_trigger_list: Vec<TriggerListEntry>,
+
+    /// Pin assignments made with `after` which haven't happened yet, soonest
+    /// first.
+    pub pending_pin_writes: Vec<PendingPinWrite>,
+
+    /// The values of delayed connections, by the index of the connection.
+    /// Connections which aren't in here haven't had anything driven onto
+    /// them yet.
+    pub delayed_values: HashMap<usize, DelayedValue>,
+
+    /// The changes of the pins which have timing checks on them.
+    pub pin_histories: HashMap<PinConnection, PinHistory>,
+
+    pub trace_patterns: Vec<String>,
+    pub failures: Vec<Failure>,
+    pub log_messages: Vec<LogMessage>,
+
+    /// The step which is in progress, if a script was paused part of the way
+    /// through it.
+    pub paused_step: Option<PausedStep>,
+
+    /// Pins which have been forced to a value from outside the model, most
+    /// recent last. These override every driver of their connection.
+    pub forced_pins: Vec<(PinConnection, logic::Value)>,
+
+    /// If this is set, interpreters run in a random order each step, rather
+    /// than in the order their components were declared.
+    pub shuffle_rng: Option<Rng>,
+
+    /// The seed for `random` and `random_bit` in scripts. Each component has
+    /// its own generator made from it, so the numbers one component gets
+    /// don't depend on what the others do.
+    pub seed: u64,
+    pub component_rngs: Vec<Rng>,
+}
+
+/// Everything needed to carry on with a step after a script pauses in it.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct PausedStep {
+    pub interpreter_idx: usize,
+    order: Vec<usize>,
+    order_position: usize,
+    intermediate_state: ComponentIntermediateState,
+    interpreter_state: ComponentIntermediateState,
+    modifications: Vec<ComponentStateModification>,
 }
 
 impl PartialEq for Model {
     fn eq(&self, other: &Model) -> bool {
-        // TODO: Doesn't compare suspended_timing_queue!
         self.component_definitions == other.component_definitions
         && self.components == other.components
         && self.connections == other.connections
         && self.interpreters == other.interpreters
+        && self.constructor_interpreters == other.constructor_interpreters
         && self.time_elapsed == other.time_elapsed
+        && self.suspended_timing_queue.clone().into_sorted_vec() == other.suspended_timing_queue.clone().into_sorted_vec()
+        && self.suspended_trigger_list == other.suspended_trigger_list
+        && self.pending_pin_writes == other.pending_pin_writes
+        && self.delayed_values == other.delayed_values
+        && self.pin_histories == other.pin_histories
+        && self.trace_patterns == other.trace_patterns
+        && self.failures == other.failures
+        && self.log_messages == other.log_messages
+        && self.paused_step == other.paused_step
+        && self.forced_pins == other.forced_pins
+        && self.shuffle_rng == other.shuffle_rng
+        && self.seed == other.seed
+        && self.component_rngs == other.component_rngs
     }
 }
 impl Eq for Model {}
@@ -137,7 +366,7 @@ impl Eq for Model {}
 //   - Once every interpreter has run, apply all of the changes to the true
 //     list of components.
 
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct ComponentStateModification {
     pub component_idx: usize,
     pub description: ComponentStateModificationDescription,
@@ -149,7 +378,7 @@ impl ComponentStateModification {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum ComponentStateModificationDescription {
     Pin {
         idx: usize,
@@ -159,7 +388,16 @@ pub enum ComponentStateModificationDescription {
         idx: usize,
         value: se::Object,
     },
-    Dump(se::Object),
+    /// A pin assignment which happens after a delay. The delay kind decides
+    /// whether it replaces assignments to the pin which are still waiting.
+    DelayedPin {
+        idx: usize,
+        value: logic::Value,
+        delay: u64,
+        kind: DelayKind,
+    },
+    Failure(String),
+    Log { level: LogLevel, message: String },
 }
 
 impl ComponentStateModificationDescription {
@@ -171,19 +409,25 @@ impl ComponentStateModificationDescription {
             Self::Variable { idx, value } => {
                 component.variables[idx].value = value;
             }
-            Self::Dump(value) => {
-                component.dumps.push(value);
-            }
+            // These are collected by the model, since they need the time
+            Self::DelayedPin { .. } | Self::Failure(_) | Self::Log { .. } => (),
         }
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ComponentIntermediateState {
     pub components: Vec<Component>,
     pub connections: Vec<Connection>,
     pub modifications: Vec<ComponentStateModification>,
     pub current_component_idx: Option<usize>,
+    pub time_elapsed: u64,
+    pub forced_pins: Vec<(PinConnection, logic::Value)>,
+    pub delayed_values: HashMap<usize, DelayedValue>,
+
+    /// The generator of the component which is running, for scripts to take
+    /// random numbers from.
+    pub rng: Rng,
 }
 
 impl ComponentIntermediateState {
@@ -198,77 +442,90 @@ impl ComponentIntermediateState {
 pub enum StepResult {
     Ok(Vec<ComponentStateModification>),
     Halt,
+
+    /// The interpreter with this index was paused part of the way through
+    /// the step. Stepping again carries on from where it stopped.
+    Paused(usize),
 }
 
 impl Model {
+    /// Runs one step of the simulation. Every interpreter which can run does
+    /// so, in order of index - which is the order their components were
+    /// declared in - unless `shuffle_execution_order` has been used. They
+    /// all see the components as they were at the start of the step, and
+    /// their modifications are applied in the same order once they have all
+    /// run, so the order only affects the order of modifications, such as
+    /// log messages and failures. Interpreters which wake at the same time,
+    /// whether from sleeping or being triggered, always run in the same step.
     pub fn step(&mut self) -> StepResult {
-        // Make a copy of the current state of the component system
-        let intermediate_state = ComponentIntermediateState {
-            components: self.components.clone(),
-            connections: self.connections.clone(),
-            ..ComponentIntermediateState::default()
-        };
-        let mut all_modifications = vec![];
-
-        // Is there no interpreter which can run without unsuspension?
-        // (I think there should always be none, but check just in case)
-        if !self.interpreters.iter().any(|i| i.can_run()) {
-            // If there are no interpreters to unsuspend, and no interpreters
-            // which were going to take a step, then the model will never change
-            // again
-            let first_interpreter_to_unsuspend = if let Some(x) = self.suspended_timing_queue.pop() {
-                x
-            } else {
-                return StepResult::Halt;
-            };
-
-            // Unsuspend the soonest interpreters
-            let mut next_interpreters_to_unsuspend = vec![
-                first_interpreter_to_unsuspend
-            ];
-
-            // Are there any at the same time? If so, let's unsuspend those too
-            let queue_step_time = next_interpreters_to_unsuspend[0].time_remaining;
-            while let Some(entry) = self.suspended_timing_queue.peek_mut() {
-                if entry.time_remaining != queue_step_time {
-                    break;
-                }
-
-                next_interpreters_to_unsuspend.push(entry.clone());
-                binary_heap::PeekMut::pop(entry);
-            }
-
-            // Actually unsuspend them
-            for i in next_interpreters_to_unsuspend {
-                self.interpreters[i.interpreter_idx].resume();
-            }
+        self.step_with_pause(&mut |_, _| false)
+    }
 
-            // Advance other items in the timing queue
-            // TODO: This is probably _super_ expensive, we should do this better
-            self.suspended_timing_queue = self.suspended_timing_queue.iter()
-                .map(|item| TimingQueueEntry {
-                    time_remaining: item.time_remaining - queue_step_time,
-                    ..item.clone()
-                })
-                .collect::<BinaryHeap<_>>();
-                
-            // Advance time elapsed
-            self.time_elapsed += queue_step_time;
-        }
+    /// Runs a step like `step`, but calls `pause` with each interpreter and
+    /// its index before it executes an instruction. If that returns true,
+    /// the step stops there, and the next step will carry on from it. This
+    /// lets debuggers stop part of the way through a script.
+    pub fn step_with_pause(&mut self, pause: &mut dyn FnMut(usize, &se::Interpreter) -> bool) -> StepResult {
+        let (intermediate_state, mut all_modifications, order, first_position, mut paused_state) = match self.paused_step.take() {
+            Some(paused) => (
+                paused.intermediate_state,
+                paused.modifications,
+                paused.order,
+                paused.order_position,
+                Some(paused.interpreter_state),
+            ),
+            None => match self.begin_step() {
+                Some(intermediate_state) => (intermediate_state, vec![], self.execution_order(), 0, None),
+                None => return StepResult::Halt,
+            },
+        };
 
         // Execute all scripts, collecting component modifications
-        for (i, interpreter) in self.interpreters.iter_mut().enumerate() {
+        let time_elapsed = self.time_elapsed;
+        self.create_component_rngs();
+        for position in first_position..order.len() {
+            let i = order[position];
+            let interpreter = &mut self.interpreters[i];
+
             // Only execute if the interpreter can run
             if !interpreter.can_run() {
                 continue;
             }
 
-            // Customise the state with some component-specific info
-            let mut interpreter_state = intermediate_state.clone();
-            interpreter_state.current_component_idx = interpreter.component_idx;
+            // Customise the state with some component-specific info, unless
+            // this interpreter is carrying on from a pause
+            let resuming = paused_state.is_some();
+            let component_idx = interpreter.component_idx;
+            let rng = match component_idx {
+                Some(c) => self.component_rngs[c].clone(),
+                None => Rng::default(),
+            };
+            let mut interpreter_state = paused_state.take().unwrap_or_else(|| {
+                let mut interpreter_state = intermediate_state.clone();
+                interpreter_state.current_component_idx = component_idx;
+                interpreter_state.time_elapsed = time_elapsed;
+                interpreter_state.rng = rng;
+                interpreter_state
+            });
 
-            let interpreter_result = interpreter.execute_until_done(&mut interpreter_state);
+            let interpreter_result = match interpreter.execute_until_paused(&mut interpreter_state, resuming, &mut |interpreter| pause(i, interpreter)) {
+                Some(result) => result,
+                None => {
+                    self.paused_step = Some(PausedStep {
+                        interpreter_idx: i,
+                        order,
+                        order_position: position,
+                        intermediate_state,
+                        interpreter_state,
+                        modifications: all_modifications,
+                    });
+                    return StepResult::Paused(i);
+                }
+            };
             all_modifications.append(&mut interpreter_state.modifications);
+            if let Some(c) = component_idx {
+                self.component_rngs[c] = interpreter_state.rng;
+            }
 
             // If this interpreter suspended, add to the timing queue
             match interpreter_result {
@@ -289,10 +546,23 @@ impl Model {
                     }
                 },
                 se::InterpreterExecutionResult::Halt => (),
-                se::InterpreterExecutionResult::Err(s) => panic!(s),
+
+                // The interpreter has halted, and the error is reported like
+                // a failed assertion
+                se::InterpreterExecutionResult::Err(message) => {
+                    if let Some(component_idx) = component_idx {
+                        all_modifications.push(ComponentStateModification {
+                            component_idx,
+                            description: ComponentStateModificationDescription::Failure(message),
+                        });
+                    }
+                }
             }
         }
 
+        self.record_messages(&all_modifications);
+        self.queue_pin_writes(&all_modifications);
+
         // Save all connection values before applying modifications
         let connection_values_before_modification = self.all_connection_values();
 
@@ -300,14 +570,255 @@ impl Model {
         for modification in &all_modifications {
             modification.apply(&mut self.components);
         }
+        self.schedule_delayed_connections();
+        self.check_timing();
+
+        self.resume_triggered_interpreters(&connection_values_before_modification, &all_modifications);
 
+        StepResult::Ok(all_modifications)
+    }
+
+    /// The order which interpreters run in this step. This is the order of
+    /// their components' declarations, unless the order is being shuffled.
+    fn execution_order(&mut self) -> Vec<usize> {
+        let mut order = (0..self.interpreters.len()).collect::<Vec<_>>();
+        if let Some(rng) = &mut self.shuffle_rng {
+            rng.shuffle(&mut order);
+        }
+        order
+    }
+
+    /// Seeds the generators which scripts take random numbers from, starting
+    /// them again from the beginning.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.seed = seed;
+        self.component_rngs.clear();
+    }
+
+    /// Makes a generator from the seed for any component which doesn't have
+    /// one yet.
+    fn create_component_rngs(&mut self) {
+        for component_idx in self.component_rngs.len()..self.components.len() {
+            self.component_rngs.push(Rng::stream(self.seed, component_idx as u64));
+        }
+    }
+
+    /// Runs interpreters in a random order each step, chosen by a generator
+    /// with the given seed. Every interpreter in a step runs against the same
+    /// copy of the components, so this never changes pins; it only reorders
+    /// the messages and failures from each step. The same seed always gives
+    /// the same order.
+    pub fn shuffle_execution_order(&mut self, seed: u64) {
+        self.shuffle_rng = Some(Rng::new(seed));
+    }
+
+    /// Wakes the interpreters which will run in the next step, moving time
+    /// forward to when they wake if none can run now. Returns a copy of the
+    /// current state of the component system for them to run against, or
+    /// None if the model will never change again.
+    fn begin_step(&mut self) -> Option<ComponentIntermediateState> {
+        // Is there no interpreter which can run without unsuspension?
+        // (I think there should always be none, but check just in case)
+        if !self.interpreters.iter().any(|i| i.can_run()) {
+            // If there are no interpreters to unsuspend or changes on their
+            // way through delays, and no interpreters which were going to
+            // take a step, then the model will never change again
+            let step_time = self.idle_time()?;
+            self.advance_time(step_time);
+
+            // Unsuspend the interpreters which are due now
+            while let Some(entry) = self.suspended_timing_queue.peek_mut() {
+                if entry.time_remaining != 0 {
+                    break;
+                }
+
+                self.interpreters[entry.interpreter_idx].resume();
+                binary_heap::PeekMut::pop(entry);
+            }
+
+            // Changes which arrive through delays or from assignments with
+            // `after` can trigger interpreters too
+            let connection_values_before_delivery = self.all_connection_values();
+            let time_elapsed = self.time_elapsed;
+            for delayed_value in self.delayed_values.values_mut() {
+                delayed_value.deliver(time_elapsed);
+            }
+            let pin_writes = self.deliver_pin_writes();
+            self.schedule_delayed_connections();
+            self.check_timing();
+            self.resume_triggered_interpreters(&connection_values_before_delivery, &pin_writes);
+        }
+
+        // Make a copy of the current state of the component system
+        Some(ComponentIntermediateState {
+            components: self.components.clone(),
+            connections: self.connections.clone(),
+            forced_pins: self.forced_pins.clone(),
+            delayed_values: self.delayed_values.clone(),
+            ..ComponentIntermediateState::default()
+        })
+    }
+
+    /// Queues the assignments with `after` from a step's modifications. Any
+    /// other assignment to a pin cancels the writes waiting for it, as do
+    /// inertial ones; transport ones only cancel those due at or after them.
+    fn queue_pin_writes(&mut self, modifications: &[ComponentStateModification]) {
+        for modification in modifications {
+            let component_idx = modification.component_idx;
+            match &modification.description {
+                ComponentStateModificationDescription::Pin { idx, .. } => {
+                    let pin = PinConnection { component_idx, pin_idx: *idx };
+                    self.pending_pin_writes.retain(|write| write.pin != pin);
+                }
+                ComponentStateModificationDescription::DelayedPin { idx, value, delay, kind } => {
+                    let pin = PinConnection { component_idx, pin_idx: *idx };
+                    let time = self.time_elapsed + delay;
+                    self.pending_pin_writes.retain(|write| write.pin != pin
+                        || (*kind == DelayKind::Transport && write.time < time));
+
+                    // The sort is stable, so later assignments for the same
+                    // time still happen later
+                    self.pending_pin_writes.push(PendingPinWrite { time, pin, value: *value });
+                    self.pending_pin_writes.sort_by_key(|write| write.time);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Applies the assignments with `after` which are due now, returning them
+    /// as modifications.
+    fn deliver_pin_writes(&mut self) -> Vec<ComponentStateModification> {
+        let due = self.pending_pin_writes.iter()
+            .take_while(|write| write.time <= self.time_elapsed)
+            .count();
+
+        let modifications = self.pending_pin_writes.drain(..due)
+            .map(|write| ComponentStateModification {
+                component_idx: write.pin.component_idx,
+                description: ComponentStateModificationDescription::Pin { idx: write.pin.pin_idx, value: write.value },
+            })
+            .collect::<Vec<_>>();
+        for modification in &modifications {
+            modification.apply(&mut self.components);
+        }
+        modifications
+    }
+
+    /// Sends any changes to the values driven onto delayed connections
+    /// through their delays. This must be called whenever pins change.
+    fn schedule_delayed_connections(&mut self) {
+        for idx in 0..self.connections.len() {
+            if let Some(delay) = &self.connections[idx].delay {
+                let value = self.driven_value(&self.connections[idx]);
+                let time_elapsed = self.time_elapsed;
+                self.delayed_values.entry(idx).or_default().schedule(&delay.clone(), time_elapsed, value);
+            }
+        }
+    }
+
+    /// Looks for changes to pins with timing checks on them, and records a
+    /// failure for each check which a change breaks. This must be called
+    /// whenever pins change.
+    fn check_timing(&mut self) {
+        let time_elapsed = self.time_elapsed;
+        let mut failures = vec![];
+
+        for component_idx in 0..self.components.len() {
+            let definition = self.components[component_idx].definition.clone();
+            if definition.timing_checks.is_empty() {
+                continue;
+            }
+
+            // Record every change before checking any, so that pins which
+            // changed together see each other's changes
+            let mut pin_idxs = definition.timing_checks.iter().flat_map(|c| c.pins()).collect::<Vec<_>>();
+            pin_idxs.sort_unstable();
+            pin_idxs.dedup();
+            let mut changes = vec![];
+            for pin_idx in pin_idxs {
+                let pin = PinConnection { component_idx, pin_idx };
+                let value = self.pin_value(&pin);
+                let history = self.pin_histories.entry(pin).or_default();
+                if history.value == value {
+                    continue;
+                }
+
+                changes.push((pin_idx, history.clone(), value));
+                let rising = logic::Edge::Rising.is(history.value, value);
+                let falling = logic::Edge::Falling.is(history.value, value);
+                if rising {
+                    history.rose_at = Some(time_elapsed);
+                }
+                if falling {
+                    history.fell_at = Some(time_elapsed);
+                }
+                history.level_since = if rising || falling { Some(time_elapsed) } else { None };
+                history.changed_at = Some(time_elapsed);
+                history.value = value;
+            }
+
+            let pin_name = |idx: usize| &definition.pins[idx].name;
+            let history = |idx: usize| &self.pin_histories[&PinConnection { component_idx, pin_idx: idx }];
+            for (pin_idx, before, value) in changes {
+                for check in &definition.timing_checks {
+                    let message = match *check {
+                        TimingCheck::Setup { data, clock, edge, time } if clock == pin_idx && edge.is(before.value, value) => {
+                            history(data).changed_at
+                                .map(|changed_at| time_elapsed - changed_at)
+                                .filter(|since| *since < time)
+                                .map(|since| format!(
+                                    "setup violation: {} changed {} before {} edge of {}, needs {}",
+                                    pin_name(data), since, edge, pin_name(clock), time,
+                                ))
+                        }
+                        TimingCheck::Hold { data, clock, edge, time } if data == pin_idx => {
+                            history(clock).edge_at(edge)
+                                .map(|edge_at| time_elapsed - edge_at)
+                                .filter(|since| *since < time)
+                                .map(|since| format!(
+                                    "hold violation: {} changed {} after {} edge of {}, needs {}",
+                                    pin_name(data), since, edge, pin_name(clock), time,
+                                ))
+                        }
+                        TimingCheck::Width { pin, time } if pin == pin_idx => {
+                            before.level_since
+                                .map(|level_since| time_elapsed - level_since)
+                                .filter(|width| *width < time)
+                                .map(|width| format!(
+                                    "width violation: {} was {} for {}, needs {}",
+                                    pin_name(pin), if before.value == Value::High { "high" } else { "low" }, width, time,
+                                ))
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(message) = message {
+                        failures.push(Failure {
+                            time: time_elapsed,
+                            instance_name: self.components[component_idx].instance_name.clone(),
+                            message,
+                        });
+                    }
+                }
+            }
+        }
+
+        self.failures.append(&mut failures);
+    }
+
+    /// Resumes interpreters suspended on a trigger, if any connection to
+    /// their component has changed value since the given values were taken.
+    /// Pins which the component itself modified are not counted, as we don't
+    /// want a component to trigger itself.
+    fn resume_triggered_interpreters(&mut self, connection_values_before_modification: &HashSet<(usize, Value)>, modifications: &[ComponentStateModification]) {
         // So that we can determine which interpreters to trigger, find out
         // which connections this step changed
         let connection_values_after_modification = self.all_connection_values();
 
         // Find which connections changed
         let connection_values_modified =
-            connection_values_after_modification.difference(&connection_values_before_modification)
+            connection_values_after_modification.difference(connection_values_before_modification)
             .map(|(idx, _)| *idx)
             .collect::<HashSet<_>>();
         
@@ -318,7 +829,7 @@ impl Model {
             let component_idx = self.interpreters[interpreter_idx].component_idx.unwrap();
             let component = &self.components[component_idx];
 
-            let all_pins_modified_by_component_this_step = all_modifications.iter()
+            let all_pins_modified_by_component_this_step = modifications.iter()
                 .filter_map(|modification| match modification {
                     ComponentStateModification {
                         component_idx: modification_component_idx,
@@ -359,38 +870,199 @@ impl Model {
 
         // Remove all of them from the list
         self.suspended_trigger_list.retain(|entry| !interpreters_to_resume.contains(&entry));
+    }
 
-        StepResult::Ok(all_modifications)
+    /// How long the model will go without changing, or None if it never will
+    /// again. This is zero if there are interpreters which can run now.
+    pub fn idle_time(&self) -> Option<u64> {
+        if self.interpreters.iter().any(|i| i.can_run()) {
+            return Some(0);
+        }
+
+        let next_delayed_change = self.delayed_values.values()
+            .filter_map(|d| d.pending.first())
+            .map(|(time, _)| time - self.time_elapsed)
+            .min();
+        let next_pin_write = self.pending_pin_writes.first()
+            .map(|write| write.time - self.time_elapsed);
+        self.suspended_timing_queue.peek()
+            .map(|entry| entry.time_remaining)
+            .into_iter()
+            .chain(next_delayed_change)
+            .chain(next_pin_write)
+            .min()
+    }
+
+    /// Moves time forward without waking any interpreters. This must not be
+    /// longer than the idle time of the model.
+    pub fn advance_time(&mut self, duration: u64) {
+        // Advance other items in the timing queue
+        // TODO: This is probably _super_ expensive, we should do this better
+        self.suspended_timing_queue = self.suspended_timing_queue.iter()
+            .map(|item| TimingQueueEntry {
+                time_remaining: item.time_remaining - duration,
+                ..item.clone()
+            })
+            .collect::<BinaryHeap<_>>();
+
+        // Advance time elapsed
+        self.time_elapsed += duration;
+    }
+
+    /// Finds a pin by its name in the form `instance.pin`.
+    pub fn pin_by_name(&self, name: &str) -> Option<PinConnection> {
+        let (instance_name, pin_name) = name.split_at(name.rfind('.')?);
+        let component_idx = self.component_idx(&instance_name.to_string())?;
+        let pin_idx = self.components[component_idx].definition.pin_idx(&pin_name[1..].to_string())?;
+        Some(PinConnection { component_idx, pin_idx })
+    }
+
+    /// Sets a pin's value from outside of the simulation, as if its own
+    /// component had assigned it. Any interpreters waiting on a trigger from
+    /// it are resumed, and will run on the next step.
+    pub fn drive_pin(&mut self, pin: &PinConnection, value: logic::Value) {
+        let connection_values_before_modification = self.all_connection_values();
+
+        let modification = ComponentStateModification {
+            component_idx: pin.component_idx,
+            description: ComponentStateModificationDescription::Pin { idx: pin.pin_idx, value },
+        };
+        modification.apply(&mut self.components);
+        self.queue_pin_writes(&[modification]);
+
+        self.schedule_delayed_connections();
+        self.check_timing();
+        self.resume_triggered_interpreters(&connection_values_before_modification, &[]);
+    }
+
+    /// Forces a pin, and every pin connected to it, to a value until it is
+    /// released, overriding anything which drives it. Any interpreters
+    /// waiting on a trigger from it are resumed, and will run on the next
+    /// step.
+    pub fn force_pin(&mut self, pin: &PinConnection, value: logic::Value) {
+        let connection_values_before_modification = self.all_connection_values();
+
+        self.forced_pins.retain(|(forced, _)| forced != pin);
+        self.forced_pins.push((pin.clone(), value));
+
+        self.schedule_delayed_connections();
+        self.check_timing();
+        self.resume_triggered_interpreters(&connection_values_before_modification, &[]);
+    }
+
+    /// Releases any forces on a pin, or on pins connected to it, so that its
+    /// drivers decide its value again. Returns false if it wasn't forced.
+    pub fn release_pin(&mut self, pin: &PinConnection) -> bool {
+        let connection_values_before_modification = self.all_connection_values();
+
+        let connected_pins = match self.pin_connection(pin) {
+            Some(c) => self.connections[c].pins.clone(),
+            None => vec![pin.clone()],
+        };
+        let forces_before = self.forced_pins.len();
+        self.forced_pins.retain(|(forced, _)| !connected_pins.contains(forced));
+
+        self.schedule_delayed_connections();
+        self.check_timing();
+        self.resume_triggered_interpreters(&connection_values_before_modification, &[]);
+        self.forced_pins.len() != forces_before
     }
 
     pub fn construct(&mut self) {
         let intermediate_state = ComponentIntermediateState {
             components: self.components.clone(),
             connections: self.connections.clone(),
+            forced_pins: self.forced_pins.clone(),
+            delayed_values: self.delayed_values.clone(),
             ..ComponentIntermediateState::default()
         };
 
+        let mut all_modifications = vec![];
+        self.create_component_rngs();
         for interpreter in self.constructor_interpreters.iter_mut() {
             let mut interpreter_state = intermediate_state.clone();
             interpreter_state.current_component_idx = interpreter.component_idx;
+            if let Some(c) = interpreter.component_idx {
+                interpreter_state.rng = self.component_rngs[c].clone();
+            }
 
             let interpreter_result = interpreter.execute_until_done(&mut interpreter_state);
+            if let Some(c) = interpreter.component_idx {
+                self.component_rngs[c] = interpreter_state.rng.clone();
+            }
 
             match interpreter_result {
                 se::InterpreterExecutionResult::Suspend(_) =>
                     panic!("constructors may not suspend"),
 
-                se::InterpreterExecutionResult::Err(s) => panic!(s),
+                se::InterpreterExecutionResult::Err(message) => {
+                    if let Some(component_idx) = interpreter.component_idx {
+                        interpreter_state.modifications.push(ComponentStateModification {
+                            component_idx,
+                            description: ComponentStateModificationDescription::Failure(message),
+                        });
+                    }
+                }
 
                 _ => (),
             }
 
-            for modification in interpreter_state.modifications {
+            for modification in interpreter_state.modifications.iter() {
                 modification.apply(&mut self.components);
             }
+            all_modifications.append(&mut interpreter_state.modifications);
+        }
+
+        // Native components don't have constructor interpreters, so they are
+        // constructed separately
+        for interpreter in self.interpreters.iter_mut().filter(|i| i.native.is_some()) {
+            let mut interpreter_state = intermediate_state.clone();
+            interpreter_state.current_component_idx = interpreter.component_idx;
+
+            interpreter.construct_native(&mut interpreter_state);
+
+            for modification in interpreter_state.modifications.iter() {
+                modification.apply(&mut self.components);
+            }
+            all_modifications.append(&mut interpreter_state.modifications);
+        }
+
+        self.record_messages(&all_modifications);
+        self.check_timing();
+    }
+
+    /// Collects the failures and log messages from a step's modifications.
+    fn record_messages(&mut self, modifications: &[ComponentStateModification]) {
+        for modification in modifications {
+            let instance_name = &self.components[modification.component_idx].instance_name;
+            match &modification.description {
+                ComponentStateModificationDescription::Failure(message) => {
+                    self.failures.push(Failure {
+                        time: self.time_elapsed,
+                        instance_name: instance_name.clone(),
+                        message: message.clone(),
+                    });
+                }
+
+                ComponentStateModificationDescription::Log { level, message } => {
+                    self.log_messages.push(LogMessage {
+                        time: self.time_elapsed,
+                        instance_name: instance_name.clone(),
+                        level: *level,
+                        message: message.clone(),
+                    });
+                }
+
+                _ => (),
+            }
         }
     }
 
+    /// Whether any assertion or expectation has failed so far.
+    pub fn failed(&self) -> bool {
+        !self.failures.is_empty()
+    }
+
     pub fn run<F>(&mut self, until_time: u64, mut between_steps: F) where F : FnMut(&Model, &Vec<ComponentStateModification>) {
         loop {
             match self.step() {
@@ -401,15 +1073,43 @@ impl Model {
                     }
                 }
 
-                StepResult::Halt => break
+                StepResult::Halt => break,
+                StepResult::Paused(_) => unreachable!("steps without a pause function never pause"),
             };
         }
     }
 
+    /// Removes and returns the messages logged so far.
+    pub fn take_log_messages(&mut self) -> Vec<LogMessage> {
+        std::mem::take(&mut self.log_messages)
+    }
+
     pub fn compile(str: String) -> Result<Model, Box<dyn std::error::Error>> {
         let parsed = p::parse(&str)?;
         mc::compile_model(&parsed)
     }
+
+    /// Reads and compiles a model file, along with the files it imports,
+    /// which are looked for relative to it and then in the search paths.
+    pub fn compile_file(path: &std::path::Path, search_paths: &[std::path::PathBuf]) -> Result<Model, Box<dyn std::error::Error>> {
+        let parsed = p::parse_file(path, search_paths)?;
+        mc::compile_model(&parsed).map_err(|e| mc::in_file(e, &path.display().to_string()))
+    }
+
+    /// Compiles a model, recording which line of the source each script
+    /// instruction came from. This is slightly slower to run, but lets
+    /// debuggers stop at and show lines of scripts.
+    pub fn compile_with_source_lines(str: String) -> Result<Model, Box<dyn std::error::Error>> {
+        let parsed = p::parse_with_source_lines(&str)?;
+        mc::compile_model(&parsed)
+    }
+
+    /// Compiles a model which may instantiate native components from the
+    /// given registry.
+    pub fn compile_with_natives(str: String, natives: &NativeRegistry) -> Result<Model, Box<dyn std::error::Error>> {
+        let parsed = p::parse(&str)?;
+        mc::compile_model_with_natives(&parsed, natives)
+    }
 }
 
 pub trait ConnectedComponents {
@@ -419,7 +1119,42 @@ pub trait ConnectedComponents {
     fn components_mut(&mut self) -> &mut Vec<Component>;
     fn connections_mut(&mut self) -> &mut Vec<Connection>;
 
-    fn connection_value(&self, connection: &Connection) -> Option<logic::Value> {
+    /// Pins which have been forced to a value, most recent last.
+    fn forced_pins(&self) -> &[(PinConnection, logic::Value)] {
+        &[]
+    }
+
+    /// The value which any of these pins has been forced to, if they have
+    /// been. If more than one has, the most recent force wins.
+    fn forced_value(&self, pins: &[PinConnection]) -> Option<logic::Value> {
+        self.forced_pins().iter()
+            .rev()
+            .find(|(pin, _)| pins.contains(pin))
+            .map(|(_, value)| *value)
+    }
+
+    /// The values of delayed connections, by the index of the connection.
+    fn delayed_values(&self) -> &HashMap<usize, DelayedValue>;
+
+    fn connection_value(&self, connection_idx: usize) -> Option<logic::Value> {
+        let connection = &self.connections()[connection_idx];
+
+        // Forces override every driver
+        if let Some(value) = self.forced_value(&connection.pins) {
+            return Some(value);
+        }
+
+        match &connection.delay {
+            Some(_) => self.delayed_values().get(&connection_idx)
+                .map(|d| d.value)
+                .unwrap_or(Some(logic::Value::Unknown)),
+            None => self.driven_value(connection),
+        }
+    }
+
+    /// The value which the pins on a connection are driving it to, before
+    /// any delay or force.
+    fn driven_value(&self, connection: &Connection) -> Option<logic::Value> {
         let mut value_set = HashSet::new();
 
         for conn in connection.pins.iter() {
@@ -465,32 +1200,36 @@ pub trait ConnectedComponents {
 
     fn pin_value(&self, conn: &PinConnection) -> logic::Value {
         if let Some(c) = self.pin_connection(conn) {
-            self.connection_value(&self.connections()[c]).expect("invalid value for connection")
+            self.connection_value(c).expect("invalid value for connection")
         } else {
-            self.components()[conn.component_idx].pins[conn.pin_idx].value
+            self.forced_value(std::slice::from_ref(conn))
+                .unwrap_or(self.components()[conn.component_idx].pins[conn.pin_idx].value)
         }
     }
 
     fn connect_pins(&mut self, pin_connections: &[PinConnection]) {
-        // Is any one of these pins already in a connection?
-        let mut existing_connection_idxs = vec![];
+        // Which connections are these pins already in?
         let all_connections = self.connections();
-        for pin_connection in pin_connections {
-            if let Some(conn) = all_connections
-                .iter()
-                .position(|c| c.pins.contains(pin_connection))
-            {
-                existing_connection_idxs.push(conn);
-                break;
-            }
-        }
+        let mut existing_connection_idxs = pin_connections.iter()
+            .filter_map(|pin_connection| all_connections.iter().position(|c| c.pins.contains(pin_connection)))
+            .collect::<Vec<_>>();
+        existing_connection_idxs.sort_unstable();
+        existing_connection_idxs.dedup();
 
         if !existing_connection_idxs.is_empty() {
             // If there are existing connections, merge them and add the new
-            // pins to that
-            let mut merged_connection = Connection { pins: vec![] };
-            for existing_connection_idx in existing_connection_idxs {
-                let existing_connection = self.connections_mut().remove(existing_connection_idx);
+            // pins to that. They are removed from the back, so that the
+            // indexes of the others don't change
+            let mut existing_connections = existing_connection_idxs.iter().rev()
+                .map(|idx| self.connections_mut().remove(*idx))
+                .collect::<Vec<_>>();
+            existing_connections.reverse();
+
+            let mut merged_connection = Connection { pins: vec![], delay: None };
+            for existing_connection in existing_connections {
+                // The compiler doesn't allow delayed connections to be
+                // merged with anything, so there is at most one delay here
+                merged_connection.delay = merged_connection.delay.or(existing_connection.delay);
                 for existing_pin in existing_connection.pins {
                     if !merged_connection.pins.contains(&existing_pin) {
                         merged_connection.pins.push(existing_pin);
@@ -508,6 +1247,7 @@ pub trait ConnectedComponents {
             // pins, create a new one
             self.connections_mut().push(Connection {
                 pins: pin_connections.to_vec(),
+                delay: None,
             });
         }        
     }
@@ -520,7 +1260,7 @@ pub trait ConnectedComponents {
         self.connections()
             .iter()
             .enumerate()
-            .map(|(i, conn)| (i, self.connection_value(conn).unwrap()))
+            .map(|(i, _)| (i, self.connection_value(i).unwrap()))
             .collect()
     }
 }
@@ -528,6 +1268,8 @@ pub trait ConnectedComponents {
 impl ConnectedComponents for Model {
     fn components(&self) -> &Vec<Component> { &self.components }
     fn connections(&self) -> &Vec<Connection> { &self.connections }
+    fn forced_pins(&self) -> &[(PinConnection, logic::Value)] { &self.forced_pins }
+    fn delayed_values(&self) -> &HashMap<usize, DelayedValue> { &self.delayed_values }
 
     fn components_mut(&mut self) -> &mut Vec<Component> { &mut self.components }
     fn connections_mut(&mut self) -> &mut Vec<Connection> { &mut self.connections }
@@ -536,6 +1278,8 @@ impl ConnectedComponents for Model {
 impl ConnectedComponents for ComponentIntermediateState {
     fn components(&self) -> &Vec<Component> { &self.components }
     fn connections(&self) -> &Vec<Connection> { &self.connections }
+    fn forced_pins(&self) -> &[(PinConnection, logic::Value)] { &self.forced_pins }
+    fn delayed_values(&self) -> &HashMap<usize, DelayedValue> { &self.delayed_values }
 
     fn components_mut(&mut self) -> &mut Vec<Component> { &mut self.components }
     fn connections_mut(&mut self) -> &mut Vec<Connection> { &mut self.connections }
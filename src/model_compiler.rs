@@ -1,4 +1,4 @@
-use std::{sync::Arc, collections::BinaryHeap, error::Error, fmt};
+use std::{sync::Arc, collections::{BinaryHeap, HashMap, HashSet}, error::Error, fmt, path::{Path, PathBuf}};
 
 use m::{ConnectedComponents, Variable, VariableDefinition};
 use se::Object;
@@ -8,34 +8,207 @@ use crate::script_compiler as sc;
 use crate::script_engine as se;
 use crate::parser as p;
 use crate::logic as l;
+use crate::stimulus;
+use crate::external;
+use crate::library;
+use crate::native::{NativeRegistry, NativeInstance};
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct ModelCompilerError {
     description: String,
+
+    /// The file which the error came from, if it is known.
+    path: Option<String>,
 }
 
 impl ModelCompilerError {
     fn new<S>(description: S) -> ModelCompilerError where S : Into<String> {
-        ModelCompilerError { description: description.into() }
+        ModelCompilerError { description: description.into(), path: None }
     }
 }
 
 impl fmt::Display for ModelCompilerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Model compiler error: {}", self.description)
+        match &self.path {
+            Some(path) => writeln!(f, "Model compiler error: {}: {}", path, self.description),
+            None => writeln!(f, "Model compiler error: {}", self.description),
+        }
+    }
+}
+
+/// Names the file which an error from compiling a model came from, unless it
+/// already names one, because it came from a file which that file imports.
+pub fn in_file(e: Box<dyn Error>, path: &str) -> Box<dyn Error> {
+    match e.downcast::<ModelCompilerError>() {
+        Ok(e) if e.path.is_some() => e,
+        Ok(e) => Box::new(ModelCompilerError { path: Some(path.into()), ..*e }),
+        Err(e) => Box::new(ModelCompilerError { description: e.to_string(), path: Some(path.into()) }),
     }
 }
 
 impl Error for ModelCompilerError {}
 
-fn compile_constant_object(node: &p::Node) -> Result<se::Object, Box<dyn Error>> {
+/// Everything which is needed while a model is being compiled, but not once
+/// it has been.
+struct ModelCompilationContext<'a> {
+    natives: &'a NativeRegistry,
+
+    /// Definitions with generic parameters, and their bodies. These are only
+    /// compiled when they are instantiated, once for each set of arguments,
+    /// since the arguments can change their pins.
+    generic_definitions: HashMap<String, (Vec<String>, p::Node)>,
+
+    /// The variables of the generate loops which are being expanded, with
+    /// the innermost last.
+    constants: Vec<(String, i64)>,
+
+    /// The libraries which have been imported, since importing one again
+    /// does nothing.
+    imported_libraries: HashSet<String>,
+
+    /// The sub-instances and connections inside each component definition,
+    /// by the name of the definition. These are compiled again for every
+    /// instance of it.
+    definition_structures: HashMap<String, Vec<p::Node>>,
+
+    /// The instance whose sub-instances are being compiled, if any. Their
+    /// names start with its name, and connections in its definition can name
+    /// its own pins without an instance.
+    enclosing_instance: Option<String>,
+
+    /// The definitions of the instances whose sub-instances are being
+    /// compiled, with the innermost last, so that a definition can't contain
+    /// itself.
+    enclosing_definitions: Vec<String>,
+
+    /// The directory of the file being compiled, if it was read from one.
+    /// Captures for stimulus sources are found relative to it, like imports.
+    directory: Option<PathBuf>,
+}
+
+/// Evaluates an integer expression which must be known when the model is
+/// compiled, like the size of a pin array.
+fn evaluate_constant(node: &p::Node, constants: &[(String, i64)]) -> Result<i64, Box<dyn Error>> {
+    let binary = |a: &p::Node, b: &p::Node, op: fn(i64, i64) -> Option<i64>| -> Result<i64, Box<dyn Error>> {
+        op(evaluate_constant(a, constants)?, evaluate_constant(b, constants)?)
+            .ok_or_else(|| ModelCompilerError::new("integer overflow when compiling").into())
+    };
+
+    match node {
+        p::Node::Constant(se::Object::Integer(i)) => Ok(*i),
+        p::Node::Identifier(name) => constants.iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| ModelCompilerError::new(format!("no constant named {}", name)).into()),
+        p::Node::Add(a, b) => binary(a, b, i64::checked_add),
+        p::Node::Subtract(a, b) => binary(a, b, i64::checked_sub),
+        p::Node::Multiply(a, b) => binary(a, b, i64::checked_mul),
+        p::Node::Divide(a, b) => {
+            if evaluate_constant(b, constants)? == 0 {
+                return Err(ModelCompilerError::new("division by zero").into());
+            }
+            binary(a, b, i64::checked_div)
+        }
+        _ => Err(ModelCompilerError::new(
+            format!("cannot evaluate {:?} when compiling", node)
+        ).into()),
+    }
+}
+
+/// The name of something which may be an element of an array, like `d` or
+/// `d[2]`.
+fn compile_name(node: &p::Node, constants: &[(String, i64)]) -> Result<String, Box<dyn Error>> {
+    match node {
+        p::Node::Identifier(name) => Ok(name.clone()),
+        p::Node::Index { target, index } =>
+            Ok(format!("{}[{}]", compile_name(target, constants)?, evaluate_constant(index, constants)?)),
+        _ => Err(ModelCompilerError::new(format!("expected a name, not {:?}", node)).into()),
+    }
+}
+
+fn compile_constant_object(node: &p::Node, constants: &[(String, i64)]) -> Result<se::Object, Box<dyn Error>> {
     if let p::Node::Constant(c) = node {
         Ok(c.clone())
     } else {
-        Err(Box::new(ModelCompilerError::new(
-            format!("cannot evaluate {:?} in this context", node)
-        )))
+        Ok(se::Object::Integer(evaluate_constant(node, constants)?))
+    }
+}
+
+fn is_defined(name: &str, model: &m::Model, context: &ModelCompilationContext) -> bool {
+    model.component_definitions.iter().any(|def| def.name == name)
+        || context.generic_definitions.contains_key(name)
+}
+
+/// Splits the body of a component definition into the items which make up the
+/// definition itself, and its sub-instances and the connections between them,
+/// which are compiled for each instance.
+fn split_definition_body(body: &p::Node) -> (p::Node, Vec<p::Node>) {
+    let items = match body {
+        p::Node::Body(items) => items.clone(),
+        _ => vec![body.clone()],
+    };
+    let (structure, own): (Vec<_>, Vec<_>) = items.into_iter().partition(|n| matches!(n,
+        p::Node::ComponentInstantiation { .. }
+        | p::Node::Connect(_)
+        | p::Node::DelayedConnect { .. }
+        | p::Node::GenerateLoop { .. }
+    ));
+    (p::Node::Body(own), structure)
+}
+
+/// Compiles a definition with generic parameters for one set of arguments,
+/// unless it has been already.
+fn instantiate_generic_definition(
+    name: &str,
+    parameters: &[String],
+    body: &p::Node,
+    arguments: &[p::Node],
+    constants: &[(String, i64)],
+    model: &mut m::Model,
+    definition_structures: &mut HashMap<String, Vec<p::Node>>,
+) -> Result<Arc<m::ComponentDefinition>, Box<dyn Error>> {
+    if arguments.len() != parameters.len() {
+        return Err(ModelCompilerError::new(
+            format!("{} takes {} generic argument(s), but got {}", name, parameters.len(), arguments.len())
+        ).into());
     }
+    let arguments = arguments.iter()
+        .map(|a| evaluate_constant(a, constants))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Instantiations with the same arguments share a definition
+    let instance_name = format!("{}<{}>", name, arguments.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", "));
+    if let Some(definition) = model.component_definitions.iter().find(|def| def.name == instance_name) {
+        return Ok(definition.clone());
+    }
+
+    let mut component_definition = m::ComponentDefinition {
+        name: instance_name,
+        constructor: None,
+        functions: vec![],
+        pins: vec![],
+        script: None,
+        variables: vec![],
+        timing_checks: vec![],
+        generic_arguments: parameters.iter().cloned().zip(arguments).collect(),
+    };
+    let component_idx = model.component_definitions.len();
+    let (body, structure) = split_definition_body(body);
+    compile_component_definition(&body, model, component_idx, &mut component_definition)?;
+    definition_structures.insert(component_definition.name.clone(), structure);
+
+    let definition = Arc::new(component_definition);
+    model.component_definitions.push(definition.clone());
+    Ok(definition)
+}
+
+/// The index of a pin named in a timing check, which must have been defined
+/// before it.
+fn check_pin_idx(component_definition: &m::ComponentDefinition, name: &String) -> Result<usize, Box<dyn Error>> {
+    component_definition.pin_idx(name).ok_or_else(|| ModelCompilerError::new(
+        format!("no pin named {} for timing check", name)
+    ).into())
 }
 
 fn compile_component_definition(
@@ -63,6 +236,21 @@ fn compile_component_definition(
             }))
         }
 
+        p::Node::PinArrayDefinition { name, size } => {
+            let size = evaluate_constant(size, &component_definition.generic_arguments)?;
+            if size < 0 {
+                return Err(ModelCompilerError::new(
+                    format!("pin array {} cannot have a negative size, {}", name, size)
+                ).into());
+            }
+            for i in 0..size {
+                compile_component_definition(
+                    &p::Node::PinDefinition(format!("{}[{}]", name, i)),
+                    model, component_idx, component_definition,
+                )?;
+            }
+        }
+
         p::Node::ScriptDefinition(script_body) => {
             if component_definition.script.is_some() {
                 return Err(ModelCompilerError::new(
@@ -82,6 +270,22 @@ fn compile_component_definition(
             }));
         }
 
+        p::Node::SetupCheck { data, clock, edge, time } | p::Node::HoldCheck { data, clock, edge, time } => {
+            let data = check_pin_idx(component_definition, data)?;
+            let clock = check_pin_idx(component_definition, clock)?;
+            let (edge, time) = (*edge, *time);
+
+            component_definition.timing_checks.push(match node {
+                p::Node::SetupCheck { .. } => m::TimingCheck::Setup { data, clock, edge, time },
+                _ => m::TimingCheck::Hold { data, clock, edge, time },
+            });
+        }
+
+        p::Node::WidthCheck { pin, time } => {
+            let pin = check_pin_idx(component_definition, pin)?;
+            component_definition.timing_checks.push(m::TimingCheck::Width { pin, time: *time });
+        }
+
         p::Node::ConstructorDefinition { parameters, body } => {
             if component_definition.script.is_some() {
                 return Err(ModelCompilerError::new(
@@ -113,35 +317,65 @@ fn compile_component_definition(
     Ok(())
 }
 
+/// The name of an instance, which is inside the enclosing instance if there
+/// is one.
+fn instance_name(name: String, enclosing_instance: Option<&str>) -> String {
+    match enclosing_instance {
+        Some(enclosing) => format!("{}.{}", enclosing, name),
+        None => name,
+    }
+}
+
 fn compile_connection(
     nodes: &Vec<p::Node>,
     model: &m::Model,
+    constants: &[(String, i64)],
+    enclosing_instance: Option<&str>,
 ) -> Result<Vec<m::PinConnection>, Box<dyn Error>> {
-    nodes.iter().map(|node| match node {
-        p::Node::Accessor { target: box p::Node::Identifier(component_name), name: box p::Node::Identifier(pin_name) } => {
-            let component_idx = model.component_idx(component_name)
-                .ok_or(Box::new(ModelCompilerError::new("missing component")))?;
-            let pin_idx = model.components[component_idx].definition.pin_idx(pin_name)
-                .ok_or(Box::new(ModelCompilerError::new("missing pin")))?;
-
-            Ok(m::PinConnection { component_idx, pin_idx })
-        }
-        
-        _ => Err(ModelCompilerError::new(
-            "connection parameters must be of form: instance.pin"
-        ).into())
+    nodes.iter().map(|node| {
+        let (component_name, pin_name) = match (node, enclosing_instance) {
+            (p::Node::Accessor { target, name }, _) =>
+                (instance_name(compile_name(target, constants)?, enclosing_instance), compile_name(name, constants)?),
+
+            // Inside a definition, a pin on its own is one of the enclosing
+            // instance's pins
+            (p::Node::Identifier(_) | p::Node::Index { .. }, Some(enclosing)) =>
+                (enclosing.to_string(), compile_name(node, constants)?),
+
+            _ => return Err(ModelCompilerError::new(
+                "connection parameters must be of form: instance.pin"
+            ).into()),
+        };
+
+        let component_idx = model.component_idx(&component_name)
+            .ok_or_else(|| ModelCompilerError::new(format!("missing component {}", component_name)))?;
+        let pin_idx = model.components[component_idx].definition.pin_idx(&pin_name)
+            .ok_or(Box::new(ModelCompilerError::new("missing pin")))?;
+
+        Ok(m::PinConnection { component_idx, pin_idx })
     }).collect()
 }
 
-fn compile_model_(node: &p::Node, model: &mut m::Model) -> Result<(), Box<dyn Error>> {
+fn compile_model_(node: &p::Node, model: &mut m::Model, context: &mut ModelCompilationContext) -> Result<(), Box<dyn Error>> {
     match node {
         p::Node::Body(nodes) => {
             for child in nodes {
-                compile_model_(child, model)?;
+                compile_model_(child, model, context)?;
             }
         }
 
-        p::Node::ComponentDefinition { name, body } => {
+        p::Node::ComponentDefinition { name, generic_parameters, body } => {
+            if is_defined(name, model, context) {
+                return Err(ModelCompilerError::new(
+                    format!("component {} is already defined", name)
+                ).into());
+            }
+
+            if !generic_parameters.is_empty() {
+                context.generic_definitions.insert(name.clone(), (generic_parameters.clone(), (**body).clone()));
+                return Ok(());
+            }
+
             let mut component_definition = m::ComponentDefinition {
                 name: name.clone(),
                 constructor: None,
@@ -149,35 +383,71 @@ fn compile_model_(node: &p::Node, model: &mut m::Model) -> Result<(), Box<dyn Er
                 pins: vec![],
                 script: None,
                 variables: vec![],
+                timing_checks: vec![],
+                generic_arguments: vec![],
             };
 
             // The index of a new component will be the length of the
             // current list
             let component_idx = model.component_definitions.len();
 
+            let (body, structure) = split_definition_body(body);
             compile_component_definition(
-                body, &model, component_idx, &mut component_definition
+                &body, &model, component_idx, &mut component_definition
             )?;
+            context.definition_structures.insert(name.clone(), structure);
     
             model.component_definitions.push(Arc::new(component_definition));
         }
 
-        p::Node::ComponentInstantiation { instance_name, component_name, arguments } => {
-            let definition = model.component_definitions
-                .iter()
-                .find(|def| &def.name == component_name);
+        p::Node::ComponentInstantiation { instance_name, component_name, generic_arguments, arguments } => {
+            let definition = if let Some((parameters, body)) = context.generic_definitions.get(component_name).cloned() {
+                Some(instantiate_generic_definition(
+                    component_name, &parameters, &body, generic_arguments,
+                    &context.constants, model, &mut context.definition_structures,
+                )?)
+            } else if !generic_arguments.is_empty() {
+                return Err(ModelCompilerError::new(
+                    format!("{} has no generic parameters", component_name)
+                ).into());
+            } else {
+                model.component_definitions
+                    .iter()
+                    .find(|def| &def.name == component_name)
+                    .cloned()
+            };
 
+            let mut native = None;
             let definition = if let Some(x) = definition {
                 x
             } else {
-                return Err(ModelCompilerError::new(
-                    format!("no component named {}", component_name)
-                ).into());
+                let constant_arguments = arguments.iter()
+                    .map(|a| compile_constant_object(a, &context.constants))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if let Some(registration) = context.natives.get(component_name) {
+                    native = Some((registration.factory)(&constant_arguments)
+                        .map_err(|e| ModelCompilerError::new(
+                            format!("could not create {}: {}", component_name, e)
+                        ))?);
+                    Arc::new(registration.definition(component_name))
+                } else if let Some(external) = external::builtin_component(component_name, &constant_arguments) {
+                    let (definition, component) = external.map_err(ModelCompilerError::new)?;
+                    native = Some(component);
+                    Arc::new(definition)
+                } else {
+                    match stimulus::builtin_component(component_name, &constant_arguments, context.directory.as_deref()) {
+                        Some(definition) => Arc::new(definition?),
+                        None => return Err(ModelCompilerError::new(
+                            format!("no component named {}", component_name)
+                        ).into()),
+                    }
+                }
             };
 
             // TODO: Might be good to make a Model::instantiate_component(&mut self, ...) to do all this
             model.components.push(m::Component {
-                instance_name: instance_name.clone(),
+                instance_name: self::instance_name(compile_name(instance_name, &context.constants)?, context.enclosing_instance.as_deref()),
                 definition: definition.clone(),
                 pins: definition.pins.iter().map(|pin_def| m::Pin {
                     definition: pin_def.clone(),
@@ -188,12 +458,11 @@ fn compile_model_(node: &p::Node, model: &mut m::Model) -> Result<(), Box<dyn Er
                     definition: var_def.clone(),
                     value: se::Object::Null,
                 }).collect(),
-                dumps: vec![],
             });
 
             if let Some(function) = definition.constructor.clone() {
                 let constructor_arguments = arguments.iter()
-                    .map(|a| compile_constant_object(a))
+                    .map(|a| compile_constant_object(a, &context.constants))
                     .collect::<Result<Vec<_>, _>>()?;
 
                 model.constructor_interpreters.push(se::Interpreter {
@@ -209,6 +478,7 @@ fn compile_model_(node: &p::Node, model: &mut m::Model) -> Result<(), Box<dyn Er
                         }
                     ],
                     status: se::InterpreterStatus::Normal,
+                    native: None,
                 })
             }
 
@@ -226,15 +496,126 @@ fn compile_model_(node: &p::Node, model: &mut m::Model) -> Result<(), Box<dyn Er
                         }
                     ],
                     status: se::InterpreterStatus::Normal,
+                    native: None,
+                });
+            }
+
+            if let Some(native) = native {
+                model.interpreters.push(se::Interpreter {
+                    component_idx: Some(model.components.len() - 1),
+                    frames: vec![],
+                    status: se::InterpreterStatus::Normal,
+                    native: Some(NativeInstance { name: component_name.clone(), component: native }),
                 });
             }
+
+            // Compile the instance's sub-instances and their connections, with
+            // its generic arguments as constants
+            if let Some(structure) = context.definition_structures.get(&definition.name).cloned() {
+                if context.enclosing_definitions.contains(component_name) {
+                    return Err(ModelCompilerError::new(
+                        format!("component {} cannot contain an instance of itself", component_name)
+                    ).into());
+                }
+
+                let instance_name = model.components.last().unwrap().instance_name.clone();
+                let constants = std::mem::replace(&mut context.constants, definition.generic_arguments.clone());
+                let enclosing_instance = context.enclosing_instance.replace(instance_name);
+                context.enclosing_definitions.push(component_name.clone());
+
+                let result = compile_model_(&p::Node::Body(structure), model, context);
+
+                context.enclosing_definitions.pop();
+                context.enclosing_instance = enclosing_instance;
+                context.constants = constants;
+                result?;
+            }
         }
 
         p::Node::Connect(nodes) => {
-            let pins = compile_connection(nodes, &model)?;
+            let pins = compile_connection(nodes, &model, &context.constants, context.enclosing_instance.as_deref())?;
+
+            // Joining more pins onto a delayed connection would delay them
+            // too, which is unlikely to be what was meant
+            if pins.iter().any(|pin| model.pin_connection(pin).map(|c| model.connections[c].delay.is_some()) == Some(true)) {
+                return Err(ModelCompilerError::new(
+                    "cannot connect to pins which are on a delayed connection"
+                ).into());
+            }
+            model.connect_pins(&pins[..]);
+        }
+
+        p::Node::DelayedConnect { pins, delay, transport } => {
+            let pins = compile_connection(pins, &model, &context.constants, context.enclosing_instance.as_deref())?;
+
+            // The delay would apply to the whole of the net which this joins,
+            // not just these pins
+            if pins.iter().any(|pin| model.pin_connection(pin).is_some()) {
+                return Err(ModelCompilerError::new(
+                    "a delayed connection cannot join pins which are already connected"
+                ).into());
+            }
             model.connect_pins(&pins[..]);
+
+            let kind = if *transport { m::DelayKind::Transport } else { m::DelayKind::Inertial };
+            let connection_idx = pins.first().and_then(|pin| model.pin_connection(pin))
+                .ok_or_else(|| ModelCompilerError::new("a delayed connection needs at least one pin"))?;
+            model.connections[connection_idx].delay = Some(m::Delay { time: *delay, kind });
+        }
+
+        p::Node::GenerateLoop { variable, start, end, body } => {
+            let start = evaluate_constant(start, &context.constants)?;
+            let end = evaluate_constant(end, &context.constants)?;
+            for i in start..end {
+                context.constants.push((variable.clone(), i));
+                let result = compile_model_(body, model, context);
+                context.constants.pop();
+                result?;
+            }
+        }
+
+        p::Node::Trace(pattern) => {
+            model.trace_patterns.push(pattern.clone());
+        }
+
+        p::Node::Seed(seed) => {
+            model.seed_random(*seed);
         }
 
+        p::Node::Import(name) => {
+            let sources = library::sources(name).ok_or_else(|| ModelCompilerError::new(
+                format!("no library named {}", name)
+            ))?;
+
+            // Like any other definitions, the library's clash with ones of the
+            // same name, whether they come before or after the import
+            if context.imported_libraries.insert(name.clone()) {
+                for source in sources {
+                    compile_model_(&p::parse(source)?, model, context)?;
+                }
+            }
+        }
+
+        p::Node::ImportFile(path) => {
+            return Err(ModelCompilerError::new(
+                format!("cannot import {}, since the model wasn't read from a file", path)
+            ).into());
+        }
+
+        p::Node::Imported { path, body } => {
+            let directory = std::mem::replace(&mut context.directory, Path::new(path).parent().map(|d| d.to_path_buf()));
+            let result = compile_model_(body, model, context).map_err(|e| in_file(e, path));
+            context.directory = directory;
+            result?;
+        }
+
+        p::Node::SourceFile(path) => {
+            context.directory = Path::new(path).parent().map(|d| d.to_path_buf());
+        }
+
+        // Tests are only compiled when they are run, by the testbench
+        p::Node::Test { .. } => (),
+
         p::Node::EndOfInput => (),
 
         _ => unimplemented!("compile model child {:?}", node),
@@ -244,6 +625,10 @@ fn compile_model_(node: &p::Node, model: &mut m::Model) -> Result<(), Box<dyn Er
 }
 
 pub fn compile_model(node: &p::Node) -> Result<m::Model, Box<dyn Error>> {
+    compile_model_with_natives(node, &NativeRegistry::default())
+}
+
+pub fn compile_model_with_natives(node: &p::Node, natives: &NativeRegistry) -> Result<m::Model, Box<dyn Error>> {
     let mut model = m::Model {
         component_definitions: vec![],
         components: vec![],
@@ -253,9 +638,30 @@ pub fn compile_model(node: &p::Node) -> Result<m::Model, Box<dyn Error>> {
 
         suspended_timing_queue: BinaryHeap::new(),
         suspended_trigger_list: vec![],
+        pending_pin_writes: vec![],
+        delayed_values: HashMap::new(),
+        pin_histories: HashMap::new(),
         time_elapsed: 0,
+
+        trace_patterns: vec![],
+        failures: vec![],
+        log_messages: vec![],
+        paused_step: None,
+        forced_pins: vec![],
+        shuffle_rng: None,
+        seed: 0,
+        component_rngs: vec![],
     };
 
-    compile_model_(node, &mut model)?;    
+    compile_model_(node, &mut model, &mut ModelCompilationContext {
+        natives,
+        generic_definitions: HashMap::new(),
+        constants: vec![],
+        imported_libraries: HashSet::new(),
+        definition_structures: HashMap::new(),
+        enclosing_instance: None,
+        enclosing_definitions: vec![],
+        directory: None,
+    })?;    
     Ok(model)
 }
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::model::{
+    self as m,
+    ComponentIntermediateState,
+    ComponentStateModification,
+    ComponentStateModificationDescription,
+    ConnectedComponents,
+};
+use crate::script_engine as se;
+use crate::logic as l;
+use crate::log::LogLevel;
+
+/// What a native component wants to do after it has been woken.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Wait {
+    /// Wake again after this many time units, like `sleep`.
+    Sleep(u64),
+
+    /// Wake again when any pin connected to this component changes, like
+    /// `trigger`.
+    Trigger,
+
+    /// Never wake again.
+    Halt,
+}
+
+/// A component whose behaviour is written in Rust rather than as a script.
+///
+/// Native components are run alongside script interpreters, and see the
+/// model in the same way: pins written while awake take effect at the end of
+/// the step, and pins read while awake have the values from the start of it.
+pub trait NativeComponent: NativeComponentClone + fmt::Debug {
+    /// Called once before simulation starts, when constructors run.
+    fn on_construct(&mut self, _context: &mut NativeContext) {}
+
+    /// Called when the component first runs, and then each time it wakes
+    /// from a sleep or trigger.
+    fn on_wake(&mut self, context: &mut NativeContext) -> Wait;
+}
+
+/// Allows boxed native components to be cloned along with the model. This
+/// is implemented for every native component which implements `Clone`.
+pub trait NativeComponentClone {
+    fn clone_box(&self) -> Box<dyn NativeComponent>;
+}
+
+impl<T> NativeComponentClone for T where T : 'static + NativeComponent + Clone {
+    fn clone_box(&self) -> Box<dyn NativeComponent> {
+        Box::new(self.clone())
+    }
+}
+
+/// The instance of a native component which belongs to an interpreter.
+#[derive(Debug)]
+pub struct NativeInstance {
+    /// The name the component was registered with.
+    pub name: String,
+    pub component: Box<dyn NativeComponent>,
+}
+
+impl Clone for NativeInstance {
+    fn clone(&self) -> Self {
+        NativeInstance { name: self.name.clone(), component: self.component.clone_box() }
+    }
+}
+
+// The state of a native component is its own business, and may not even live
+// in Hiloz, so instances are only compared by what they were created as
+impl PartialEq for NativeInstance {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+impl Eq for NativeInstance {}
+
+/// Gives a native component access to its own pins while it is running.
+pub struct NativeContext<'a> {
+    state: &'a mut ComponentIntermediateState,
+}
+
+impl<'a> NativeContext<'a> {
+    pub fn new(state: &'a mut ComponentIntermediateState) -> NativeContext<'a> {
+        NativeContext { state }
+    }
+
+    pub fn component_idx(&self) -> usize {
+        self.state.current_component_idx.expect("not running in component")
+    }
+
+    pub fn time(&self) -> u64 {
+        self.state.time_elapsed
+    }
+
+    /// Finds one of this component's pins by name, recording a failure if
+    /// it doesn't have one.
+    fn pin_idx(&mut self, name: &str) -> Option<usize> {
+        let idx = self.state.components[self.component_idx()].definition.pin_idx(&name.to_string());
+        if idx.is_none() {
+            self.fail(&format!("native component has no pin named {}", name));
+        }
+        idx
+    }
+
+    /// Reads the value of one of this component's pins. If it is connected
+    /// to others, this is the value of the whole connection. Reading a pin
+    /// which the component doesn't have records a failure, and gives X.
+    pub fn read_pin(&mut self, name: &str) -> l::Value {
+        match self.pin_idx(name) {
+            Some(pin_idx) => self.state.pin_value(&m::PinConnection { component_idx: self.component_idx(), pin_idx }),
+            None => l::Value::Unknown,
+        }
+    }
+
+    /// Assigns one of this component's pins. Assigning a pin which the
+    /// component doesn't have records a failure, and does nothing else.
+    pub fn write_pin(&mut self, name: &str, value: l::Value) {
+        if let Some(idx) = self.pin_idx(name) {
+            self.modify(ComponentStateModificationDescription::Pin { idx, value });
+        }
+    }
+
+    pub fn log(&mut self, level: LogLevel, message: &str) {
+        self.modify(ComponentStateModificationDescription::Log { level, message: message.into() });
+    }
+
+    /// Records a failure, in the same way as a failed `assert` or `expect`.
+    pub fn fail(&mut self, message: &str) {
+        self.modify(ComponentStateModificationDescription::Failure(message.into()));
+    }
+
+    fn modify(&mut self, description: ComponentStateModificationDescription) {
+        let component_idx = self.component_idx();
+        self.state.modify(ComponentStateModification { component_idx, description });
+    }
+}
+
+/// Creates a native component from the arguments given where it is
+/// instantiated.
+pub type NativeFactory = Arc<dyn Fn(&[se::Object]) -> Result<Box<dyn NativeComponent>, String> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct NativeRegistration {
+    pub pins: Vec<String>,
+    pub factory: NativeFactory,
+}
+
+/// The native components which a model can instantiate, by name.
+#[derive(Clone, Default)]
+pub struct NativeRegistry {
+    components: HashMap<String, NativeRegistration>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> NativeRegistry {
+        NativeRegistry::default()
+    }
+
+    /// Registers a native component, so that models can instantiate it with
+    /// `component x = Name(...);`. Its pins are given here, so that they can
+    /// be connected to before it is created.
+    pub fn register<F>(&mut self, name: &str, pins: &[&str], factory: F)
+    where F : Fn(&[se::Object]) -> Result<Box<dyn NativeComponent>, String> + Send + Sync + 'static
+    {
+        self.components.insert(name.into(), NativeRegistration {
+            pins: pins.iter().map(|p| p.to_string()).collect(),
+            factory: Arc::new(factory),
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeRegistration> {
+        self.components.get(name)
+    }
+}
+
+impl NativeRegistration {
+    /// The component definition given to instances of a native component.
+    /// It has pins, but no script - the native component replaces it.
+    pub fn definition(&self, name: &str) -> m::ComponentDefinition {
+        m::ComponentDefinition {
+            name: name.into(),
+            pins: self.pins.iter().map(|p| Arc::new(m::PinDefinition { name: p.clone() })).collect(),
+            variables: vec![],
+            constructor: None,
+            functions: vec![],
+            script: None,
+            timing_checks: vec![],
+            generic_arguments: vec![],
+        }
+    }
+}
@@ -1,35 +1,60 @@
 use super::script_engine as se;
 use super::logic;
+use super::log;
 
 use pest::{Parser, iterators::Pair};
 
 use std::str::{FromStr, from_utf8};
 use std::error::Error;
+use std::{collections::HashSet, fs, path::{Path, PathBuf}};
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Node {
     Constant(se::Object),
     Identifier(String),
     PinAssignment { target: Box<Node>, value: Box<Node> },
+    DelayedPinAssignment { target: Box<Node>, value: Box<Node>, delay: Box<Node>, transport: bool },
     Accessor { target: Box<Node>, name: Box<Node> },
+    Index { target: Box<Node>, index: Box<Node> },
     Sleep(Box<Node>),
     Trigger,
-    Dump(Box<Node>),
+    Assert { condition: Box<Node>, message: Box<Node> },
+    Expect { condition: Box<Node>, source: String },
+    Log { level: log::LogLevel, arguments: Vec<Node> },
     Return(Box<Node>),
     LocalVariableDefinition { name: String, value: Option<Box<Node>> },
     LocalVariableAssignment { name: String, value: Box<Node> },
 
     PinDefinition(String),
+    PinArrayDefinition { name: String, size: Box<Node> },
     VariableDefinition(String),
-    ComponentDefinition { name: String, body: Box<Node> },
+    ComponentDefinition { name: String, generic_parameters: Vec<String>, body: Box<Node> },
     ConstructorDefinition { parameters: Vec<String>, body: Box<Node> },
     FunctionDefinition { name: String, parameters: Vec<String>, body: Box<Node> },
     ScriptDefinition(Box<Node>),
     ComponentVariableDefinition { name: String },
+    SetupCheck { data: String, clock: String, edge: logic::Edge, time: u64 },
+    HoldCheck { data: String, clock: String, edge: logic::Edge, time: u64 },
+    WidthCheck { pin: String, time: u64 },
 
-    ComponentInstantiation { instance_name: String, component_name: String, arguments: Vec<Node> },
+    ComponentInstantiation { instance_name: Box<Node>, component_name: String, generic_arguments: Vec<Node>, arguments: Vec<Node> },
     Connect(Vec<Node>),
+    DelayedConnect { pins: Vec<Node>, delay: u64, transport: bool },
     Pull { component: Vec<Node>, pull: logic::Value },
+    Trace(String),
+    Seed(u64),
+    GenerateLoop { variable: String, start: Box<Node>, end: Box<Node>, body: Box<Node> },
+    Import(String),
+    ImportFile(String),
+
+    /// The contents of a file which was imported, with the path it was found
+    /// at. Imports are resolved by `parse_file`.
+    Imported { path: String, body: Box<Node> },
+
+    /// The path of the model file, which `parse_file` puts before its
+    /// contents, so that files named in the model can be found relative to it.
+    SourceFile(String),
+    Test { name: String, body: Box<Node> },
 
     LogicAnd(Box<Node>, Box<Node>),
     LogicOr(Box<Node>, Box<Node>),
@@ -40,6 +65,9 @@ pub enum Node {
     Divide(Box<Node>, Box<Node>),
     Equal(Box<Node>, Box<Node>),
 
+    Random { low: Box<Node>, high: Box<Node> },
+    RandomBit,
+
     Loop(Box<Node>),
     If { condition: Box<Node>, body: Box<Node> },
     Break,
@@ -47,15 +75,21 @@ pub enum Node {
     Body(Vec<Node>),
     NodeList(Vec<Node>),
 
+    /// A statement, and the line of the source which it starts on. These are
+    /// only produced by `parse_with_source_lines`.
+    Located { line: usize, statement: Box<Node> },
+
     EndOfInput,
 }
 
 use Node::*;
-use se::Object::{*, self};
+use se::Object::{Integer, LogicValue, self};
 
 #[derive(Parser)]
 #[grammar="model.pest"]
-struct ModelParser;
+struct ModelParser {
+    source_lines: bool,
+}
 
 impl ModelParser {
     fn node_list_to_identifiers(node: Node) -> Result<Vec<String>, Box<dyn Error>> {
@@ -73,7 +107,34 @@ impl ModelParser {
         }
     }
 
-    fn pest_to_node(pest: Pair<Rule>) -> Result<Node, Box<dyn Error>> {
+    fn node_list(node: Node) -> Vec<Node> {
+        if let NodeList(nodes) = node {
+            nodes
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn unescape_string(contents: &str) -> String {
+        let mut result = String::new();
+        let mut chars = contents.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(other) => result.push(other),
+                None => (),
+            }
+        }
+        result
+    }
+
+    fn pest_to_node(&self, pest: Pair<Rule>) -> Result<Node, Box<dyn Error>> {
         match pest.as_rule() {
             Rule::integer =>
                 Ok(Constant(Integer(i64::from_str(pest.as_str())?))),
@@ -86,11 +147,15 @@ impl ModelParser {
                     "X" => logic::Value::Unknown,
                     _ => unreachable!(),
                 }))),
+            Rule::string =>
+                Ok(Constant(Object::String(
+                    Self::unescape_string(pest.into_inner().next().unwrap().as_str())
+                ))),
 
             Rule::accessor => {
                 let mut inner = pest.into_inner();
-                let target = Self::pest_to_node(inner.next().unwrap())?;
-                let name = Self::pest_to_node(inner.next().unwrap())?;
+                let target = self.pest_to_node(inner.next().unwrap())?;
+                let name = self.pest_to_node(inner.next().unwrap())?;
 
                 Ok(Accessor {
                     target: Box::new(target),
@@ -98,20 +163,53 @@ impl ModelParser {
                 })
             },
 
-            Rule::pin_definition =>
-                Ok(PinDefinition(pest.into_inner().next().unwrap().as_str().into())),
+            Rule::indexed => {
+                let mut inner = pest.into_inner();
+                let target = self.pest_to_node(inner.next().unwrap())?;
+                let index = self.pest_to_node(inner.next().unwrap())?;
+
+                Ok(Index {
+                    target: Box::new(target),
+                    index: Box::new(index),
+                })
+            },
+
+            Rule::pin_definition => {
+                let mut inner = pest.into_inner();
+                let name = inner.next().unwrap().as_str().into();
+                match inner.next() {
+                    Some(size) => Ok(PinArrayDefinition { name, size: Box::new(self.pest_to_node(size)?) }),
+                    None => Ok(PinDefinition(name)),
+                }
+            },
             Rule::connect_definition => {
-                let node_list = Self::pest_to_node(pest.into_inner().next().unwrap())?;
-                
-                if let NodeList(nodes) = node_list {
-                    Ok(Connect(nodes))
+                let mut inner = pest.into_inner();
+                let node_list = self.pest_to_node(inner.next().unwrap())?;
+                let nodes = if let NodeList(nodes) = node_list {
+                    nodes
                 } else {
                     unreachable!();
+                };
+
+                match inner.next() {
+                    Some(delay) => {
+                        let mut delay = delay.into_inner().peekable();
+                        let transport = delay.peek().unwrap().as_rule() == Rule::transport_keyword;
+                        if transport {
+                            delay.next();
+                        }
+                        Ok(DelayedConnect {
+                            pins: nodes,
+                            delay: u64::from_str(delay.next().unwrap().as_str())?,
+                            transport,
+                        })
+                    }
+                    None => Ok(Connect(nodes)),
                 }
             },
             Rule::script_definition =>
                 Ok(ScriptDefinition(Box::new(
-                    Self::pest_to_node(pest.into_inner().next().unwrap())?
+                    self.pest_to_node(pest.into_inner().next().unwrap())?
                 ))),
             Rule::component_variable_definition_statement =>
                 Ok(ComponentVariableDefinition {
@@ -119,31 +217,43 @@ impl ModelParser {
                 }),
             Rule::constructor_definition => {
                 let mut inner = pest.into_inner();
-                let parameter_list = Self::pest_to_node(inner.next().unwrap())?;
+                let parameter_list = self.pest_to_node(inner.next().unwrap())?;
                 let parameters = Self::node_list_to_identifiers(parameter_list)?;
-                let body = Self::pest_to_node(inner.next().unwrap())?;
+                let body = self.pest_to_node(inner.next().unwrap())?;
                 Ok(ConstructorDefinition {
                     parameters,
                     body: Box::new(body),
                 })
             },
             Rule::component_definition => {
-                let mut inner = pest.into_inner();
+                let mut inner = pest.into_inner().peekable();
                 let name = inner.next().unwrap().as_str();
+                let generic_parameters = match inner.peek().map(|p| p.as_rule()) {
+                    Some(Rule::generic_parameters) =>
+                        inner.next().unwrap().into_inner().map(|p| p.as_str().into()).collect(),
+                    _ => vec![],
+                };
                 let mut body = vec![];
-                while let Some(node) = inner.next() {
-                    body.push(Self::pest_to_node(node)?);
+                for node in inner {
+                    body.push(self.pest_to_node(node)?);
                 }
                 Ok(ComponentDefinition {
                     name: name.into(),
+                    generic_parameters,
                     body: Box::new(Body(body)),
                 })
             },
             Rule::component_instantiation => {
-                let mut inner = pest.into_inner();
-                let instance_name = inner.next().unwrap().as_str().into();
+                let mut inner = pest.into_inner().peekable();
+                let instance_name = Box::new(self.pest_to_node(inner.next().unwrap())?);
                 let component_name = inner.next().unwrap().as_str().into();
-                let argument_list = Self::pest_to_node(inner.next().unwrap())?;
+                let generic_arguments = match inner.peek().unwrap().as_rule() {
+                    Rule::generic_arguments => inner.next().unwrap().into_inner()
+                        .map(|p| self.pest_to_node(p))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    _ => vec![],
+                };
+                let argument_list = self.pest_to_node(inner.next().unwrap())?;
                 let arguments = if let NodeList(nodes) = argument_list {
                     nodes
                 } else {
@@ -152,16 +262,85 @@ impl ModelParser {
                 Ok(ComponentInstantiation {
                     instance_name,
                     component_name,
+                    generic_arguments,
                     arguments,
                 })
             }
 
+            Rule::test_definition => {
+                let mut inner = pest.into_inner();
+                let name = match self.pest_to_node(inner.next().unwrap())? {
+                    Constant(Object::String(name)) => name,
+                    _ => unreachable!(),
+                };
+                let mut body = vec![];
+                for node in inner {
+                    body.push(self.pest_to_node(node)?);
+                }
+                Ok(Test {
+                    name,
+                    body: Box::new(Body(body)),
+                })
+            }
+
+            Rule::check_definition =>
+                self.pest_to_node(pest.into_inner().next().unwrap()),
+            Rule::edge_check => {
+                let mut inner = pest.into_inner();
+                let kind = inner.next().unwrap().as_str();
+                let data = inner.next().unwrap().as_str().into();
+                let edge = match inner.next().unwrap().as_str() {
+                    "posedge" => logic::Edge::Rising,
+                    "negedge" => logic::Edge::Falling,
+                    _ => unreachable!(),
+                };
+                let clock = inner.next().unwrap().as_str().into();
+                let time = u64::from_str(inner.next().unwrap().as_str())?;
+
+                Ok(match kind {
+                    "setup" => SetupCheck { data, clock, edge, time },
+                    "hold" => HoldCheck { data, clock, edge, time },
+                    _ => unreachable!(),
+                })
+            },
+            Rule::width_check => {
+                let mut inner = pest.into_inner();
+                let pin = inner.next().unwrap().as_str().into();
+                let time = u64::from_str(inner.next().unwrap().as_str())?;
+                Ok(WidthCheck { pin, time })
+            },
+            Rule::generate_loop => {
+                let mut inner = pest.into_inner();
+                let variable = inner.next().unwrap().as_str().into();
+                let start = self.pest_to_node(inner.next().unwrap())?;
+                let end = self.pest_to_node(inner.next().unwrap())?;
+                let body = inner.map(|n| self.pest_to_node(n)).collect::<Result<Vec<_>, _>>()?;
+
+                Ok(GenerateLoop {
+                    variable,
+                    start: Box::new(start),
+                    end: Box::new(end),
+                    body: Box::new(Body(body)),
+                })
+            },
+            Rule::trace_definition =>
+                Ok(Trace(pest.into_inner().next().unwrap().as_str().into())),
+            Rule::seed_definition =>
+                Ok(Seed(u64::from_str(pest.into_inner().next().unwrap().as_str())?)),
+            Rule::import_definition => {
+                let inner = pest.into_inner().next().unwrap();
+                match inner.as_rule() {
+                    Rule::string => Ok(ImportFile(inner.into_inner().next().unwrap().as_str().into())),
+                    _ => Ok(Import(inner.as_str().into())),
+                }
+            },
+
             Rule::argument_list => {
                 let mut inner = pest.into_inner();
                 let mut nodes = vec![];
 
                 while let Some(head_pair) = inner.next() {
-                    nodes.push(Self::pest_to_node(head_pair)?);
+                    nodes.push(self.pest_to_node(head_pair)?);
 
                     // Set inner to the iterator for the child argument_list, if there is one
                     if let Some(pair) = inner.next() {
@@ -174,34 +353,91 @@ impl ModelParser {
                 Ok(NodeList(nodes))
             },
 
-            Rule::statement =>
-                Self::pest_to_node(pest.into_inner().next().unwrap()),
+            Rule::statement => {
+                let (line, _) = pest.as_span().start_pos().line_col();
+                let statement = self.pest_to_node(pest.into_inner().next().unwrap())?;
+                if self.source_lines {
+                    Ok(Located { line, statement: Box::new(statement) })
+                } else {
+                    Ok(statement)
+                }
+            }
             Rule::pin_assignment => {
-                let mut inner = pest.into_inner();
-                let target = Self::pest_to_node(inner.next().unwrap())?;
-                let value = Self::pest_to_node(inner.next().unwrap())?;
-                Ok(PinAssignment {
-                    target: Box::new(target),
-                    value: Box::new(value),
-                })
+                let mut inner = pest.into_inner().peekable();
+                let target = self.pest_to_node(inner.next().unwrap())?;
+                let transport = inner.peek().unwrap().as_rule() == Rule::transport_keyword;
+                if transport {
+                    inner.next();
+                }
+                let value = self.pest_to_node(inner.next().unwrap())?;
+
+                match inner.next() {
+                    Some(delay) => Ok(DelayedPinAssignment {
+                        target: Box::new(target),
+                        value: Box::new(value),
+                        delay: Box::new(self.pest_to_node(delay)?),
+                        transport,
+                    }),
+                    None if transport => Err("a transport assignment needs a delay, given with `after`".into()),
+                    None => Ok(PinAssignment {
+                        target: Box::new(target),
+                        value: Box::new(value),
+                    }),
+                }
             },
             Rule::break_statement =>
                 Ok(Break),
             Rule::sleep_statement =>
                 Ok(Sleep(Box::new(
-                    Self::pest_to_node(pest.into_inner().next().unwrap())?
+                    self.pest_to_node(pest.into_inner().next().unwrap())?
                 ))),
             Rule::trigger_statement =>
                 Ok(Trigger),
-            Rule::dump_statement =>
-                Ok(Dump(Box::new(
-                    Self::pest_to_node(pest.into_inner().next().unwrap())?
-                ))),
+            Rule::random_call => {
+                let mut inner = pest.into_inner();
+                let low = self.pest_to_node(inner.next().unwrap())?;
+                let high = self.pest_to_node(inner.next().unwrap())?;
+                Ok(Random { low: Box::new(low), high: Box::new(high) })
+            }
+            Rule::random_bit_call =>
+                Ok(RandomBit),
+            Rule::assert_statement => {
+                let mut inner = pest.into_inner();
+                let condition = self.pest_to_node(inner.next().unwrap())?;
+                let message = self.pest_to_node(inner.next().unwrap())?;
+                Ok(Assert {
+                    condition: Box::new(condition),
+                    message: Box::new(message),
+                })
+            },
+            Rule::expect_statement => {
+                let condition = pest.into_inner().next().unwrap();
+                Ok(Expect {
+                    source: condition.as_str().into(),
+                    condition: Box::new(self.pest_to_node(condition)?),
+                })
+            },
+            Rule::print_statement => {
+                let argument_list = self.pest_to_node(pest.into_inner().next().unwrap())?;
+                Ok(Log {
+                    level: log::LogLevel::Info,
+                    arguments: Self::node_list(argument_list),
+                })
+            },
+            Rule::log_statement => {
+                let mut inner = pest.into_inner();
+                let level = log::LogLevel::from_str(inner.next().unwrap().as_str())?;
+                let argument_list = self.pest_to_node(inner.next().unwrap())?;
+                Ok(Log {
+                    level,
+                    arguments: Self::node_list(argument_list),
+                })
+            },
             Rule::local_variable_definition_statement => {
                 let mut inner = pest.into_inner();
                 let name = inner.next().unwrap().as_str();
                 let value = if let Some(pair) = inner.next() {
-                    Some(Box::new(Self::pest_to_node(pair)?))
+                    Some(Box::new(self.pest_to_node(pair)?))
                 } else {
                     None
                 };
@@ -213,7 +449,7 @@ impl ModelParser {
             Rule::local_variable_assignment_statement => {
                 let mut inner = pest.into_inner();
                 let name = inner.next().unwrap().as_str();
-                let value = Self::pest_to_node(inner.next().unwrap())?;
+                let value = self.pest_to_node(inner.next().unwrap())?;
                 Ok(LocalVariableAssignment {
                     name: name.into(),
                     value: Box::new(value),  
@@ -222,24 +458,24 @@ impl ModelParser {
             Rule::statement_block | Rule::top =>
                 Ok(Body(
                     pest.into_inner()
-                        .map(|x| Self::pest_to_node(x))
+                        .map(|x| self.pest_to_node(x))
                         .collect::<Result<Vec<_>, _>>()?
                 )),
 
             Rule::expression =>
-                Self::pest_to_node(pest.into_inner().next().unwrap()),
+                self.pest_to_node(pest.into_inner().next().unwrap()),
 
             Rule::unop_not => {
                 Ok(LogicNot(Box::new(
-                    Self::pest_to_node(pest.into_inner().next().unwrap())?
+                    self.pest_to_node(pest.into_inner().next().unwrap())?
                 )))
             }
             Rule::binop_addsub | Rule::binop_muldiv | Rule::binop_eq | Rule::binop_andor => {
                 let mut inner = pest.into_inner();
-                let mut result = Self::pest_to_node(inner.next().unwrap())?;
+                let mut result = self.pest_to_node(inner.next().unwrap())?;
 
                 while let Some(operator) = inner.next() {
-                    let operand = Box::new(Self::pest_to_node(inner.next().unwrap())?);
+                    let operand = Box::new(self.pest_to_node(inner.next().unwrap())?);
 
                     result = match operator.as_rule() {
                         Rule::operator_and => LogicAnd(Box::new(result), operand),
@@ -259,12 +495,12 @@ impl ModelParser {
 
             Rule::loop_statement =>
                 Ok(Loop(Box::new(
-                    Self::pest_to_node(pest.into_inner().next().unwrap())?
+                    self.pest_to_node(pest.into_inner().next().unwrap())?
                 ))),
             Rule::if_statement => {
                 let mut inner = pest.into_inner();
-                let condition = Self::pest_to_node(inner.next().unwrap())?;
-                let body = Self::pest_to_node(inner.next().unwrap())?;
+                let condition = self.pest_to_node(inner.next().unwrap())?;
+                let body = self.pest_to_node(inner.next().unwrap())?;
                 Ok(If {
                     condition: Box::new(condition),
                     body: Box::new(body),
@@ -283,13 +519,93 @@ impl ModelParser {
 
 pub fn parse_rule(input: &str, rule: Rule) -> Result<Node, Box<dyn Error>> {
     let mut pairs = ModelParser::parse(rule, input)?;
-    ModelParser::pest_to_node(pairs.next().unwrap())
+    ModelParser { source_lines: false }.pest_to_node(pairs.next().unwrap())
 }
 
-pub fn parse(model: &str) -> Result<Node, Box<dyn Error>> {
+fn parse_top(model: &str, parser: ModelParser) -> Result<Node, Box<dyn Error>> {
     Ok(Body(
         ModelParser::parse(Rule::top, model)?
-            .map(|n| ModelParser::pest_to_node(n))
+            .map(|n| parser.pest_to_node(n))
             .collect::<Result<Vec<_>, _>>()?
     ))
-}
\ No newline at end of file
+}
+
+pub fn parse(model: &str) -> Result<Node, Box<dyn Error>> {
+    parse_top(model, ModelParser { source_lines: false })
+}
+
+/// Parses a model like `parse`, but wraps every statement in a `Located`
+/// node, so that compiled scripts know which line each instruction came from.
+pub fn parse_with_source_lines(model: &str) -> Result<Node, Box<dyn Error>> {
+    parse_top(model, ModelParser { source_lines: true })
+}
+
+/// Reads and parses a model file, along with every file which it imports
+/// with `import "path";`. Imports are looked for relative to the file which
+/// imports them, and then in each of the search paths in order. A file is
+/// only imported once, however many files import it.
+pub fn parse_file(path: &Path, search_paths: &[PathBuf]) -> Result<Node, Box<dyn Error>> {
+    parse_file_with(path, search_paths, parse)
+}
+
+/// Parses a model file like `parse_file`, recording source lines like
+/// `parse_with_source_lines`. Only the lines of the file itself are
+/// recorded, not those of the files it imports.
+pub fn parse_file_with_source_lines(path: &Path, search_paths: &[PathBuf]) -> Result<Node, Box<dyn Error>> {
+    parse_file_with(path, search_paths, parse_with_source_lines)
+}
+
+fn parse_file_with(path: &Path, search_paths: &[PathBuf], parse_source: fn(&str) -> Result<Node, Box<dyn Error>>) -> Result<Node, Box<dyn Error>> {
+    let name_errors = |e: Box<dyn Error>| -> Box<dyn Error> { format!("{}: {}", path.display(), e).into() };
+    let source = fs::read_to_string(path).map_err(|e| name_errors(e.into()))?;
+    let parsed = parse_source(&source).map_err(name_errors)?;
+
+    let mut imported = HashSet::new();
+    imported.insert(fs::canonicalize(path)?);
+
+    let mut nodes = vec![SourceFile(path.display().to_string())];
+    resolve_imports(parsed, directory_of(path), search_paths, &mut imported, &mut nodes)?;
+    Ok(Body(nodes))
+}
+
+fn directory_of(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+/// Pushes the nodes of a parsed file onto `nodes`, replacing its imports with
+/// `Imported` nodes for the files they import. These are flattened, and each
+/// comes before anything which imports it.
+fn resolve_imports(node: Node, directory: &Path, search_paths: &[PathBuf], imported: &mut HashSet<PathBuf>, nodes: &mut Vec<Node>) -> Result<(), Box<dyn Error>> {
+    match node {
+        Body(children) => {
+            for child in children {
+                resolve_imports(child, directory, search_paths, imported, nodes)?;
+            }
+        }
+
+        ImportFile(name) => {
+            let path = std::iter::once(directory)
+                .chain(search_paths.iter().map(|p| p.as_path()))
+                .map(|d| d.join(&name))
+                .find(|p| p.is_file())
+                .ok_or_else(|| format!("could not find {} to import", name))?;
+            if !imported.insert(fs::canonicalize(&path)?) {
+                return Ok(());
+            }
+
+            let name_errors = |e: Box<dyn Error>| -> Box<dyn Error> { format!("{}: {}", path.display(), e).into() };
+            let source = fs::read_to_string(&path).map_err(|e| name_errors(e.into()))?;
+            let parsed = parse(&source).map_err(name_errors)?;
+
+            let mut body = vec![];
+            resolve_imports(parsed, directory_of(&path), search_paths, imported, &mut body)?;
+            let (imports, body): (Vec<_>, Vec<_>) = body.into_iter().partition(|n| matches!(n, Imported { .. }));
+            nodes.extend(imports);
+            nodes.push(Imported { path: path.display().to_string(), body: Box::new(Body(body)) });
+        }
+
+        _ => nodes.push(node),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! A small seeded pseudo-random number generator, so that anything random in
+//! a simulation can be reproduced from its seed.
+
+use serde::{Serialize, Deserialize};
+
+/// A SplitMix64 generator. This isn't suitable for cryptography, but it is
+/// fast, has a tiny state which can be saved in checkpoints, and gives the
+/// same sequence for the same seed on every platform.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// One of many independent generators which come from the same seed,
+    /// such as one for each component in a model.
+    pub fn stream(seed: u64, stream: u64) -> Rng {
+        let mut mixer = Rng::new(seed ^ stream.wrapping_mul(0xd1342543de82ef95));
+        Rng::new(mixer.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to, but not including, `bound`, which must not be
+    /// zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+
+    /// Puts the items in a random order.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
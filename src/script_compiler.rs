@@ -5,6 +5,7 @@ use se::Instruction;
 use crate::model as m;
 use crate::script_engine as se;
 use crate::parser as p;
+use crate::log as lg;
 
 fn replace_instruction<F>(
     instructions: &Vec<se::Instruction>,
@@ -117,8 +118,11 @@ fn compile(node: &p::Node, context: &mut CompilationContext) -> Result<Vec<se::I
             let local_defined = context.defined_local(i);
             let component_variable_defined = context.defined_component_variable(i);
             let parameter_idx = context.parameter_idx(i);
+            let generic_argument = context.component_definition().generic_arguments.iter()
+                .find(|(name, _)| name == i)
+                .map(|(_, value)| *value);
 
-            if [pin_idx.is_some(), local_defined, component_variable_defined, parameter_idx.is_some()].iter()
+            if [pin_idx.is_some(), local_defined, component_variable_defined, parameter_idx.is_some(), generic_argument.is_some()].iter()
                 .filter(|x| **x)
                 .count() > 1
             {
@@ -139,40 +143,118 @@ fn compile(node: &p::Node, context: &mut CompilationContext) -> Result<Vec<se::I
                 Ok(vec![
                     se::Instruction::GetVariable(i.clone()),
                 ])
+            } else if let Some(value) = generic_argument {
+                Ok(vec![
+                    se::Instruction::Push(se::Object::Integer(value)),
+                ])
             } else {
                 Err(format!("nothing named {}", i))
             }
         },
 
         p::Node::PinAssignment { target, value } => {
-            let pin_idx = if let p::Node::Identifier(i) = &**target {
-                if let Some(idx) = context.component_definition().pin_idx(&i) {
-                    idx
-                } else {
-                    return Err(format!("no pin named {}", i))
-                }
-            } else {
-                return Err("can only assign to pin".into())
-            };
-
             Ok([
                 compile(value, context)?,
+                compile_pin_idx(target, context)?,
                 vec![
-                    se::Instruction::Push(se::Object::Integer(pin_idx as i64)),
                     se::Instruction::GetOwnComponentIdx,
                     se::Instruction::ModifyComponentPin,
                 ]
             ].concat())
         },
 
-        p::Node::Dump(node) =>
+        p::Node::DelayedPinAssignment { target, value, delay, transport } => {
+            let kind = if *transport { m::DelayKind::Transport } else { m::DelayKind::Inertial };
+
+            Ok([
+                compile(value, context)?,
+                compile(delay, context)?,
+                compile_pin_idx(target, context)?,
+                vec![
+                    se::Instruction::GetOwnComponentIdx,
+                    se::Instruction::ModifyComponentPinAfter(kind),
+                ]
+            ].concat())
+        },
+
+        p::Node::Index { .. } => {
+            Ok([
+                compile_pin_idx(node, context)?,
+                vec![
+                    se::Instruction::GetOwnComponentIdx,
+                    se::Instruction::ReadComponentPin,
+                ]
+            ].concat())
+        },
+
+        p::Node::Random { low, high } =>
             Ok([
-                compile(node, context)?,
+                compile(low, context)?,
+                compile(high, context)?,
                 vec![
-                    se::Instruction::Dump,
+                    se::Instruction::Random,
                 ]
             ].concat()),
 
+        p::Node::RandomBit => Ok(vec![se::Instruction::RandomBit]),
+
+        p::Node::Assert { condition, message } => {
+            // A failed assertion stops this script, since whatever it goes on
+            // to do probably relies on the assertion holding
+            let failure_instructions = [
+                compile(message, context)?,
+                vec![
+                    se::Instruction::Fail,
+                    se::Instruction::Halt,
+                ]
+            ].concat();
+
+            Ok([
+                compile(condition, context)?,
+                vec![se::Instruction::JumpConditional(failure_instructions.len() as i64 + 1)],
+                failure_instructions,
+            ].concat())
+        }
+
+        p::Node::Expect { condition, source } => {
+            Ok([
+                compile(condition, context)?,
+                vec![
+                    se::Instruction::JumpConditional(3),
+                    se::Instruction::Push(se::Object::String(format!("expected {}", source))),
+                    se::Instruction::Fail,
+                ]
+            ].concat())
+        }
+
+        p::Node::Log { level, arguments } => {
+            let (format, arguments) = match arguments.split_first() {
+                Some(x) => x,
+                None => return Err("print and log need something to print".into()),
+            };
+
+            if let p::Node::Constant(se::Object::String(format)) = format {
+                let placeholders = lg::placeholder_count(format);
+                if placeholders != arguments.len() {
+                    return Err(format!(
+                        "format string has {} placeholders, but {} arguments were given",
+                        placeholders, arguments.len(),
+                    ));
+                }
+            }
+
+            // Arguments are pushed last first, so that the first is popped
+            // first after the format string
+            Ok([
+                arguments.iter().rev()
+                    .map(|a| compile(a, context))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .concat(),
+                compile(format, context)?,
+                vec![se::Instruction::Log { level: *level, argument_count: arguments.len() }],
+            ].concat())
+        }
+
         p::Node::LocalVariableDefinition { name, value } => {
             if context.locals.contains(name) {
                 return Err(format!("local named {} is already defined here", name))
@@ -302,6 +384,12 @@ fn compile(node: &p::Node, context: &mut CompilationContext) -> Result<Vec<se::I
                 body_instructions,
             ].concat())
         }
+        p::Node::Located { line, statement } => {
+            Ok([
+                vec![se::Instruction::Line(*line)],
+                compile(statement, context)?,
+            ].concat())
+        }
         p::Node::Break => {
             Ok(vec![se::Instruction::MagicBreak])
         }
@@ -310,6 +398,32 @@ fn compile(node: &p::Node, context: &mut CompilationContext) -> Result<Vec<se::I
     }
 }
 
+/// Pushes the index of a pin, which may be an element of a pin array, such
+/// as `d[i]`. Indexes into arrays are checked when the script runs.
+fn compile_pin_idx(target: &p::Node, context: &mut CompilationContext) -> Result<Vec<se::Instruction>, String> {
+    match target {
+        p::Node::Identifier(i) => {
+            let pin_idx = context.component_definition().pin_idx(i).ok_or_else(|| format!("no pin named {}", i))?;
+            Ok(vec![se::Instruction::Push(se::Object::Integer(pin_idx as i64))])
+        }
+
+        p::Node::Index { target: box p::Node::Identifier(i), index } => {
+            let (first, size) = context.component_definition().pin_array(i)
+                .ok_or_else(|| format!("no pin array named {}", i))?;
+            Ok([
+                compile(index, context)?,
+                vec![
+                    se::Instruction::CheckBounds(size as i64),
+                    se::Instruction::Push(se::Object::Integer(first as i64)),
+                    se::Instruction::Add,
+                ]
+            ].concat())
+        }
+
+        _ => Err("can only assign to pin".into()),
+    }
+}
+
 pub fn compile_script(node: &p::Node, model: Option<&m::Model>, component_definition: Option<&m::ComponentDefinition>, parameters: Vec<String>) -> Result<Vec<se::Instruction>, String> {
     let mut result = compile(node, &mut CompilationContext {
         parent: None,
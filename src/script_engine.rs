@@ -1,22 +1,41 @@
 use super::logic::{self, Value};
+use super::log::{self as lg, LogLevel};
+use super::native::{NativeInstance, NativeContext, Wait};
 use super::model::{
     ComponentStateModification,
     ComponentIntermediateState,
     ComponentStateModificationDescription,
     PinConnection,
     ConnectedComponents,
+    DelayKind,
 };
 
-use std::{borrow::Borrow, collections::HashMap, sync::Arc, usize};
+use std::{borrow::Borrow, collections::HashMap, fmt, sync::Arc, usize};
+use serde::{Serialize, Deserialize};
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum Object {
     Null,
     LogicValue(logic::Value),
     Integer(i64),
+    String(String),
     Function(Arc<Function>),
 }
 
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Null => write!(f, "null"),
+            Object::LogicValue(Value::High) => write!(f, "H"),
+            Object::LogicValue(Value::Low) => write!(f, "L"),
+            Object::LogicValue(Value::Unknown) => write!(f, "X"),
+            Object::Integer(i) => write!(f, "{}", i),
+            Object::String(s) => write!(f, "{}", s),
+            Object::Function(_) => write!(f, "<function>"),
+        }
+    }
+}
+
 impl Object {
     fn is_truthy(&self) -> bool {
         match self {
@@ -29,18 +48,17 @@ impl Object {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Function {
     pub parameters: Vec<String>,
     pub body: Vec<Instruction>,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum Instruction {
     Push(Object),
     Pop,
     Duplicate,
-    Dump,
     DefineLocal(String),
     SetVariable(String),
     GetVariable(String),
@@ -74,10 +92,41 @@ pub enum Instruction {
     //   - New pin value, logic value
     ModifyComponentPin,
 
+    // Like ModifyComponentPin, but the pin changes after a delay, without
+    // suspending the script.
+    //   - Component index, integer
+    //   - Pin index, integer
+    //   - Delay, integer
+    //   - New pin value, logic value
+    ModifyComponentPinAfter(DelayKind),
+
     //   - Component index, integer
     //   - Pin index, integer
     ReadComponentPin,
 
+    // Fails unless the integer on top of the stack, which is left there, is
+    // from zero up to but not including the given length.
+    CheckBounds(i64),
+
+    //   - Failure message
+    Fail,
+
+    // Pushes a random integer from the lowest to the highest value, inclusive.
+    //   - Highest value, integer
+    //   - Lowest value, integer
+    Random,
+
+    // Pushes a random logic value, either high or low.
+    RandomBit,
+
+    //   - Format string, usually a string
+    //   - Each argument to format with, first argument first
+    Log { level: LogLevel, argument_count: usize },
+
+    // Marks the start of a statement on this line of the source, so that
+    // debuggers can tell where a script is. Does nothing when executed.
+    Line(usize),
+
     // Magic instructions will never actually be executed by the interpreter.
     // They exist only as helpers during the compilation stage.
     // For example, the compiler may emit a MagicBreak instruction, which is
@@ -85,14 +134,14 @@ pub enum Instruction {
     MagicBreak,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum InterpreterFrameKind {
     Normal,
     FunctionTopLevel,
     ScriptTopLevel,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct InterpreterFrame {
     pub kind: InterpreterFrameKind,
     pub function: Arc<Function>,
@@ -113,9 +162,21 @@ impl InterpreterFrame {
             ip: 0,
         }
     }
+
+    /// The line of the source which the next instruction belongs to, if the
+    /// function was compiled with source lines.
+    pub fn line(&self) -> Option<usize> {
+        self.function.body.iter()
+            .take(self.ip + 1)
+            .rev()
+            .find_map(|instruction| match instruction {
+                Instruction::Line(line) => Some(*line),
+                _ => None,
+            })
+    }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum SuspensionMode {
     Sleep(u64),
     Trigger,
@@ -173,11 +234,35 @@ impl InterpreterFrame {
                 InstructionExecutionResult::Ok
             }
 
-            Instruction::Dump => {
-                let dumped = self.stack.pop().expect("stack empty");
+            Instruction::Fail => {
+                let message = self.stack.pop().expect("stack empty");
                 state.modify(ComponentStateModification {
                     component_idx: state.current_component_idx.unwrap(),
-                    description: ComponentStateModificationDescription::Dump(dumped),
+                    description: ComponentStateModificationDescription::Failure(message.to_string()),
+                });
+                InstructionExecutionResult::Ok
+            }
+
+            Instruction::Log { level, argument_count } => {
+                let format = self.stack.pop().expect("stack empty");
+                let mut arguments = vec![];
+                for _ in 0..argument_count {
+                    arguments.push(self.stack.pop().expect("not enough stack items for log arguments"));
+                }
+
+                // If the first argument isn't a format string, print all of
+                // the arguments one after another instead
+                let message = match format {
+                    Object::String(format) => lg::format_message(&format, &arguments),
+                    other => std::iter::once(other).chain(arguments)
+                        .map(|o| o.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                };
+
+                state.modify(ComponentStateModification {
+                    component_idx: state.current_component_idx.unwrap(),
+                    description: ComponentStateModificationDescription::Log { level, message },
                 });
                 InstructionExecutionResult::Ok
             }
@@ -231,6 +316,29 @@ impl InterpreterFrame {
                 InstructionExecutionResult::Ok
             }
 
+            Instruction::Random => {
+                let high = self.pop_integer();
+                let low = self.pop_integer();
+                if low > high {
+                    InstructionExecutionResult::Err(format!("random range {} to {} is empty", low, high))
+                } else {
+                    // Every i64 is in range if the span doesn't fit in a u64
+                    let span = (high as i128 - low as i128 + 1) as u128;
+                    let offset = if span > u64::MAX as u128 {
+                        state.rng.next_u64()
+                    } else {
+                        state.rng.below(span as u64)
+                    };
+                    self.stack.push(Object::Integer((low as i128 + offset as i128) as i64));
+                    InstructionExecutionResult::Ok
+                }
+            },
+            Instruction::RandomBit => {
+                let bit = state.rng.next_u64() & 1 == 1;
+                self.stack.push(Object::LogicValue(bit.into()));
+                InstructionExecutionResult::Ok
+            },
+
             Instruction::Add => {
                 let a = self.pop_integer();
                 let b = self.pop_integer();
@@ -282,6 +390,44 @@ impl InterpreterFrame {
                 InstructionExecutionResult::Ok
             }
 
+            Instruction::ModifyComponentPinAfter(kind) => {
+                let component_idx = self.pop_integer();
+                let pin_idx = self.pop_integer();
+                let delay = self.pop_integer();
+                let value = self.pop_logic_value();
+
+                if delay < 0 {
+                    InstructionExecutionResult::Err(format!("pin assignment delay {} is negative", delay))
+                } else {
+                    // With no delay, this is just an ordinary assignment
+                    let description = if delay == 0 {
+                        ComponentStateModificationDescription::Pin { idx: pin_idx as usize, value }
+                    } else {
+                        ComponentStateModificationDescription::DelayedPin {
+                            idx: pin_idx as usize,
+                            value,
+                            delay: delay as u64,
+                            kind,
+                        }
+                    };
+                    state.modify(ComponentStateModification {
+                        component_idx: component_idx as usize,
+                        description,
+                    });
+
+                    InstructionExecutionResult::Ok
+                }
+            }
+
+            Instruction::CheckBounds(length) => {
+                match self.stack.last() {
+                    Some(Object::Integer(i)) if (0..length).contains(i) => InstructionExecutionResult::Ok,
+                    Some(Object::Integer(i)) =>
+                        InstructionExecutionResult::Err(format!("index {} is out of bounds for length {}", i, length)),
+                    _ => panic!("expected integer on stack"),
+                }
+            }
+
             Instruction::ReadComponentPin => {
                 let component_idx = self.pop_integer(); 
                 let pin_idx = self.pop_integer();
@@ -341,6 +487,8 @@ impl InterpreterFrame {
                 InstructionExecutionResult::Ok
             }
 
+            Instruction::Line(_) => InstructionExecutionResult::Ok,
+
             Instruction::MagicBreak =>
                 unreachable!("magic instructions are never supposed to be executed, this is a bug"),
         };
@@ -367,7 +515,7 @@ impl InterpreterFrame {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum InterpreterStatus {
     Normal,
     Suspended,
@@ -380,11 +528,16 @@ impl Default for InterpreterStatus {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Interpreter {
     pub frames: Vec<InterpreterFrame>,
     pub status: InterpreterStatus,
     pub component_idx: Option<usize>,
+
+    /// If this is set, the interpreter runs this native component instead
+    /// of its frames. Native components can't be saved in checkpoints.
+    #[serde(skip)]
+    pub native: Option<NativeInstance>,
 }
 
 impl Interpreter {
@@ -396,6 +549,19 @@ impl Interpreter {
         self.status == InterpreterStatus::Normal
     }
 
+    /// The instruction which this interpreter will execute next, if it is
+    /// running a script.
+    pub fn next_instruction(&self) -> Option<&Instruction> {
+        let frame = self.frames.last()?;
+        frame.function.body.get(frame.ip)
+    }
+
+    /// The line of the source which this interpreter's script is on, if it
+    /// was compiled with source lines.
+    pub fn current_line(&self) -> Option<usize> {
+        self.frames.last()?.line()
+    }
+
     pub fn resume(&mut self) {
         self.status = InterpreterStatus::Normal;
     }
@@ -510,21 +676,64 @@ impl Interpreter {
         }
     }
 
+    pub fn construct_native(&mut self, state: &mut ComponentIntermediateState) {
+        if let Some(native) = &mut self.native {
+            native.component.on_construct(&mut NativeContext::new(state));
+        }
+    }
+
     pub fn execute_until_done(&mut self, state: &mut ComponentIntermediateState) -> InterpreterExecutionResult {
+        self.execute_until_paused(state, false, &mut |_| false).unwrap()
+    }
+
+    /// Like `execute_until_done`, but calls `pause` before each instruction,
+    /// and stops without executing it if that returns true. This returns None
+    /// if it paused, and the interpreter can then be resumed by calling this
+    /// again with `resuming` set, which skips the check before the first
+    /// instruction. Native components are never paused.
+    pub fn execute_until_paused(
+        &mut self,
+        state: &mut ComponentIntermediateState,
+        resuming: bool,
+        pause: &mut dyn FnMut(&Interpreter) -> bool,
+    ) -> Option<InterpreterExecutionResult> {
+        if let Some(native) = &mut self.native {
+            return Some(match native.component.on_wake(&mut NativeContext::new(state)) {
+                Wait::Sleep(time) => {
+                    self.status = InterpreterStatus::Suspended;
+                    InterpreterExecutionResult::Suspend(SuspensionMode::Sleep(time))
+                }
+                Wait::Trigger => {
+                    self.status = InterpreterStatus::Suspended;
+                    InterpreterExecutionResult::Suspend(SuspensionMode::Trigger)
+                }
+                Wait::Halt => {
+                    self.status = InterpreterStatus::Halted;
+                    InterpreterExecutionResult::Halt
+                }
+            });
+        }
+
+        let mut check_pause = !resuming;
         loop {
+            if check_pause && pause(self) {
+                return None;
+            }
+            check_pause = true;
+
             match self.execute_one_instruction(state) {
                 FrameExecutionResult::Ok => (),
                 FrameExecutionResult::OkSuspend(mode) => {
                     self.status = InterpreterStatus::Suspended;
-                    return InterpreterExecutionResult::Suspend(mode)
+                    return Some(InterpreterExecutionResult::Suspend(mode))
                 }
                 FrameExecutionResult::OkHalt => {
                     self.status = InterpreterStatus::Halted;
-                    return InterpreterExecutionResult::Halt
+                    return Some(InterpreterExecutionResult::Halt)
                 },
                 FrameExecutionResult::Err(s) => {
                     self.status = InterpreterStatus::Halted;
-                    return InterpreterExecutionResult::Err(s)
+                    return Some(InterpreterExecutionResult::Err(s))
                 },
             }
         }
@@ -0,0 +1,187 @@
+use std::{error::Error, fs::File, io::{BufReader, BufWriter, Write}, path::Path};
+
+use crate::model::{self as m, ConnectedComponents};
+use crate::logic as l;
+use crate::script_engine as se;
+use crate::log::LogMessage;
+use crate::native::NativeRegistry;
+use crate::checkpoint;
+
+type StepCallback = Box<dyn FnMut(&m::Model, &[m::ComponentStateModification])>;
+
+/// A compiled model and its progress through simulation. This is the main
+/// way to drive Hiloz from Rust.
+///
+/// Constructors are run automatically before the first step.
+pub struct Simulation {
+    model: m::Model,
+    constructed: bool,
+    callbacks: Vec<StepCallback>,
+}
+
+impl Simulation {
+    pub fn new(model: m::Model) -> Simulation {
+        Simulation { model, constructed: false, callbacks: vec![] }
+    }
+
+    /// Compiles a model from its source.
+    pub fn compile(source: &str) -> Result<Simulation, Box<dyn Error>> {
+        Ok(Simulation::new(m::Model::compile(source.into())?))
+    }
+
+    /// Compiles a model from its source, allowing it to instantiate native
+    /// components from the given registry.
+    pub fn compile_with_natives(source: &str, natives: &NativeRegistry) -> Result<Simulation, Box<dyn Error>> {
+        Ok(Simulation::new(m::Model::compile_with_natives(source.into(), natives)?))
+    }
+
+    /// Reads and compiles a model file, and any files it imports.
+    pub fn load<P>(path: P) -> Result<Simulation, Box<dyn Error>> where P : AsRef<Path> {
+        Ok(Simulation::new(m::Model::compile_file(path.as_ref(), &[])?))
+    }
+
+    /// Reads a checkpoint written by `save_checkpoint`, carrying on from
+    /// where it was saved.
+    pub fn load_checkpoint<P>(path: P) -> Result<Simulation, Box<dyn Error>> where P : AsRef<Path> {
+        let model = checkpoint::load(&mut BufReader::new(File::open(path)?))?;
+        Ok(Simulation { model, constructed: true, callbacks: vec![] })
+    }
+
+    /// Saves the whole state of the simulation to a file, running the
+    /// constructors first if they haven't been already. Step callbacks
+    /// aren't saved.
+    pub fn save_checkpoint<P>(&mut self, path: P) -> Result<(), Box<dyn Error>> where P : AsRef<Path> {
+        self.construct();
+        let mut output = BufWriter::new(File::create(path)?);
+        checkpoint::save(&self.model, &mut output)?;
+        Ok(output.flush()?)
+    }
+
+    pub fn model(&self) -> &m::Model {
+        &self.model
+    }
+
+    pub fn model_mut(&mut self) -> &mut m::Model {
+        &mut self.model
+    }
+
+    pub fn into_model(self) -> m::Model {
+        self.model
+    }
+
+    pub fn time(&self) -> u64 {
+        self.model.time_elapsed
+    }
+
+    /// Registers a function to be called after every step, with the model
+    /// and the modifications which that step made.
+    pub fn on_step<F>(&mut self, callback: F) where F : FnMut(&m::Model, &[m::ComponentStateModification]) + 'static {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Runs the model's constructors, if they haven't been run already. This
+    /// happens automatically before the first step.
+    pub fn construct(&mut self) {
+        if !self.constructed {
+            self.model.construct();
+            self.constructed = true;
+        }
+    }
+
+    /// Runs one step of the simulation. Returns false if the model has
+    /// halted, and will never change again.
+    pub fn step(&mut self) -> bool {
+        !matches!(self.step_with_pause(|_, _| false), m::StepResult::Halt)
+    }
+
+    /// Runs one step of the simulation, which may be paused part of the way
+    /// through a script, as described by `Model::step_with_pause`. Step
+    /// callbacks are only called once a step is complete.
+    pub fn step_with_pause<F>(&mut self, mut pause: F) -> m::StepResult where F : FnMut(usize, &se::Interpreter) -> bool {
+        self.construct();
+
+        let result = self.model.step_with_pause(&mut pause);
+        if let m::StepResult::Ok(modifications) = &result {
+            for callback in self.callbacks.iter_mut() {
+                callback(&self.model, modifications);
+            }
+        }
+        result
+    }
+
+    /// The index of the interpreter which has been paused part of the way
+    /// through a step, if any.
+    pub fn paused_interpreter(&self) -> Option<usize> {
+        self.model.paused_step.as_ref().map(|p| p.interpreter_idx)
+    }
+
+    /// Runs the simulation until the given time, or until the model halts.
+    /// Unlike `Model::run`, this never goes past the given time, so it can be
+    /// called repeatedly to move through a simulation in fixed increments.
+    pub fn run_until(&mut self, time: u64) {
+        self.construct();
+
+        while self.time() < time {
+            match self.model.idle_time() {
+                Some(idle_time) if self.time() + idle_time > time => {
+                    self.model.advance_time(time - self.time());
+                    break;
+                }
+                _ => (),
+            }
+
+            if !self.step() {
+                break;
+            }
+        }
+    }
+
+    /// Runs the simulation for the given number of time units from now, or
+    /// until the model halts.
+    pub fn run_for(&mut self, duration: u64) {
+        self.run_until(self.time() + duration)
+    }
+
+    /// Reads the value of a pin, given as `instance.pin`. If the pin is
+    /// connected to others, this is the value of the whole connection.
+    pub fn pin(&self, name: &str) -> Option<l::Value> {
+        self.model.pin_by_name(name).map(|pc| self.model.pin_value(&pc))
+    }
+
+    /// Drives a pin, given as `instance.pin`, from outside the simulation.
+    pub fn drive_pin(&mut self, name: &str, value: l::Value) -> Result<(), String> {
+        let pc = self.model.pin_by_name(name).ok_or_else(|| format!("no pin named {}", name))?;
+        self.model.drive_pin(&pc, value);
+        Ok(())
+    }
+
+    /// Forces a pin, given as `instance.pin`, and everything connected to it
+    /// to a value, overriding its drivers until it is released.
+    pub fn force_pin(&mut self, name: &str, value: l::Value) -> Result<(), String> {
+        let pc = self.model.pin_by_name(name).ok_or_else(|| format!("no pin named {}", name))?;
+        self.model.force_pin(&pc, value);
+        Ok(())
+    }
+
+    /// Releases a pin, given as `instance.pin`, which was forced. Returns
+    /// false if neither it nor anything connected to it was forced.
+    pub fn release_pin(&mut self, name: &str) -> Result<bool, String> {
+        let pc = self.model.pin_by_name(name).ok_or_else(|| format!("no pin named {}", name))?;
+        Ok(self.model.release_pin(&pc))
+    }
+
+    pub fn failures(&self) -> &[m::Failure] {
+        &self.model.failures
+    }
+
+    pub fn log_messages(&self) -> &[LogMessage] {
+        &self.model.log_messages
+    }
+
+    /// Removes and returns the messages logged so far. Long simulations
+    /// which write messages out as they go can call this so that they don't
+    /// keep every message in memory.
+    pub fn take_log_messages(&mut self) -> Vec<LogMessage> {
+        self.model.take_log_messages()
+    }
+}
@@ -0,0 +1,361 @@
+use std::{sync::Arc, convert::TryFrom, error::Error, fs, io::{Read, Seek}, path::{Path, PathBuf}};
+
+use crate::model as m;
+use crate::script_engine as se;
+use crate::logic as l;
+use crate::vcd;
+
+/// Returns the definition of a built-in component, if there is one with the
+/// given name. Built-in components are generated from their arguments, so
+/// each instance gets its own definition.
+///
+/// These are the stimulus sources, which replay a signal captured by a logic
+/// analyser onto their `out` pin. They are scheduled with the same sleeps as
+/// any other script, so they can drive a model just like a hand-written
+/// component would. Relative capture paths are found in `directory`, which is
+/// the directory of the model file, if it was read from one.
+pub fn builtin_component(name: &str, arguments: &[se::Object], directory: Option<&Path>) -> Option<Result<m::ComponentDefinition, Box<dyn Error>>> {
+    let arguments = || source_arguments(name, arguments, directory);
+    let changes = match name {
+        "VcdSource" => arguments().and_then(vcd_changes),
+        "CsvSource" => arguments().and_then(csv_changes),
+        "SigrokSource" => arguments().and_then(sigrok_changes),
+        _ => return None,
+    };
+
+    Some(changes.and_then(|changes| match changes.last() {
+        // Scripts sleep for a signed number of time units
+        Some((time, _)) if *time > i64::MAX as u64 =>
+            Err(format!("the last change, at {}, is too late to replay", time).into()),
+        _ => Ok(replay_component(name, &changes)),
+    }))
+}
+
+/// The length of a simulation time unit in femtoseconds. Traces are written
+/// with a timescale of 1ms, so captures which say how long their samples are
+/// replay in that unit.
+const TIME_UNIT_FEMTOSECONDS: u128 = 1_000_000_000_000;
+const SECOND_FEMTOSECONDS: u128 = 1_000_000_000_000_000;
+
+/// The arguments taken by every stimulus source: the path of a capture, the
+/// name of the signal to replay from it, and optionally a number which the
+/// capture's times are multiplied by (defaulting to 1).
+struct SourceArguments<'a> {
+    path: PathBuf,
+    signal_name: &'a str,
+    time_scale: u64,
+}
+
+fn source_arguments<'a>(component_name: &str, arguments: &'a [se::Object], directory: Option<&Path>) -> Result<SourceArguments<'a>, Box<dyn Error>> {
+    let resolve = |path: &str| match directory {
+        Some(directory) => directory.join(path),
+        None => PathBuf::from(path),
+    };
+
+    match arguments {
+        [se::Object::String(path), se::Object::String(signal_name)] =>
+            Ok(SourceArguments { path: resolve(path), signal_name, time_scale: 1 }),
+        [se::Object::String(path), se::Object::String(signal_name), se::Object::Integer(time_scale)] if *time_scale > 0 =>
+            Ok(SourceArguments { path: resolve(path), signal_name, time_scale: *time_scale as u64 }),
+
+        _ => Err(format!(
+            "{} takes a path, a signal name, and optionally a positive time scale, got {:?}",
+            component_name, arguments,
+        ).into()),
+    }
+}
+
+/// `VcdSource(path, signal)` replays one single-bit signal of a VCD file, such
+/// as `top.sda`. Times are converted from the file's timescale into time
+/// units, and then multiplied by the time scale. Files without a timescale
+/// are taken to be in time units already.
+fn vcd_changes(arguments: SourceArguments) -> Result<Vec<(u64, l::Value)>, Box<dyn Error>> {
+    let SourceArguments { path, signal_name, time_scale } = arguments;
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let file = vcd::parse_vcd(&contents)
+        .map_err(|e| format!("could not parse {}: {}", path.display(), e))?;
+    let signal = file.signal(signal_name)
+        .ok_or_else(|| format!("no signal named {} in {}", signal_name, path.display()))?;
+    if signal.width != 1 {
+        return Err(format!(
+            "signal {} is {} bits wide, but only single-bit signals can be replayed",
+            signal_name, signal.width,
+        ).into());
+    }
+
+    let unit = file.timescale_femtoseconds()
+        .map_err(|e| format!("could not parse {}: {}", path.display(), e))?
+        .map_or(TIME_UNIT_FEMTOSECONDS, |unit| unit as u128);
+    let changes = signal.changes.iter()
+        .map(|(time, value)| {
+            let time = scale_time(*time, unit * time_scale as u128, TIME_UNIT_FEMTOSECONDS)
+                .ok_or_else(|| format!("time {} in {} is too late", time, path.display()))?;
+            Ok((time, *value))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    check_sample_times(&changes).map_err(|e| format!("could not replay {}: {}", path.display(), e))?;
+    Ok(changes)
+}
+
+/// Multiplies a time by `numerator / denominator`, rounding to the nearest
+/// time unit. Returns None if it's too late to fit.
+fn scale_time(time: u64, numerator: u128, denominator: u128) -> Option<u64> {
+    let scaled = (time as u128).checked_mul(numerator)?;
+    u64::try_from((scaled + denominator / 2) / denominator).ok()
+}
+
+/// `CsvSource(path, column)` replays one column of a CSV file, as exported by
+/// most logic analysers. The first row names the columns; if one of them is
+/// called `time` then it gives the timestamp of each row, otherwise each row
+/// is one sample. Lines starting with `;` or `#` are comments. If the first
+/// timestamp is negative, as it is for samples from before a trigger, every
+/// timestamp is moved so that the first one is at 0.
+fn csv_changes(arguments: SourceArguments) -> Result<Vec<(u64, l::Value)>, Box<dyn Error>> {
+    let SourceArguments { path, signal_name, time_scale } = arguments;
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    parse_csv(&contents, signal_name, time_scale)
+        .map_err(|e| format!("could not parse {}: {}", path.display(), e).into())
+}
+
+pub fn parse_csv(contents: &str, column_name: &str, time_scale: u64) -> Result<Vec<(u64, l::Value)>, String> {
+    let mut rows = contents.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with(';') && !line.starts_with('#'))
+        .map(|line| line.split(',').map(|cell| cell.trim().trim_matches('"')).collect::<Vec<_>>());
+
+    let header = rows.next().ok_or("missing header row")?;
+    let column_idx = header.iter().position(|name| *name == column_name)
+        .ok_or_else(|| format!("no column named {}", column_name))?;
+    let time_column_idx = header.iter().position(|name|
+        name.to_ascii_lowercase().starts_with("time")
+    );
+
+    let mut samples = vec![];
+    let mut first_time = None;
+    for (row_idx, row) in rows.enumerate() {
+        let time = match time_column_idx {
+            Some(time_column_idx) => {
+                let cell = row.get(time_column_idx)
+                    .ok_or_else(|| format!("row {} has no time", row_idx + 1))?;
+                let time = cell.parse::<f64>().ok()
+                    .filter(|time| time.is_finite())
+                    .ok_or_else(|| format!("invalid time {}", cell))?;
+                let offset = *first_time.get_or_insert(time.min(0.0));
+
+                let scaled = ((time - offset) * time_scale as f64).round();
+                if scaled < 0.0 {
+                    return Err(format!("time {} is before the first row", cell));
+                }
+                if scaled >= u64::MAX as f64 {
+                    return Err(format!("time {} is too large", cell));
+                }
+                scaled as u64
+            }
+            None => (row_idx as u64).checked_mul(time_scale)
+                .ok_or_else(|| format!("row {} is too late", row_idx + 1))?,
+        };
+
+        let cell = row.get(column_idx)
+            .ok_or_else(|| format!("row {} has no value for {}", row_idx + 1, column_name))?;
+        samples.push((time, csv_value(cell)));
+    }
+
+    check_sample_times(&samples)?;
+    Ok(transitions(samples))
+}
+
+fn csv_value(cell: &str) -> l::Value {
+    match cell {
+        "1" | "H" | "h" => l::Value::High,
+        "0" | "L" | "l" => l::Value::Low,
+        _ => l::Value::Unknown,
+    }
+}
+
+/// `SigrokSource(path, probe)` replays one logic probe, such as `D0`, of a
+/// sigrok session file saved by PulseView or `sigrok-cli`. The time of each
+/// sample comes from the session's sample rate, converted into time units and
+/// multiplied by the time scale. Sessions without a sample rate use the
+/// number of each sample instead.
+fn sigrok_changes(arguments: SourceArguments) -> Result<Vec<(u64, l::Value)>, Box<dyn Error>> {
+    let SourceArguments { path, signal_name, time_scale } = arguments;
+
+    let file = fs::File::open(&path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    parse_sigrok(file, signal_name, time_scale)
+        .map_err(|e| format!("could not parse {}: {}", path.display(), e).into())
+}
+
+pub fn parse_sigrok<R>(reader: R, probe_name: &str, time_scale: u64) -> Result<Vec<(u64, l::Value)>, String> where R : Read + Seek {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| e.to_string())?;
+
+    let mut metadata = String::new();
+    archive.by_name("metadata")
+        .map_err(|_| "missing metadata")?
+        .read_to_string(&mut metadata)
+        .map_err(|e| e.to_string())?;
+
+    // The metadata is an INI file - we only need the keys for the logic device
+    let mut in_device = false;
+    let mut capture_file = "logic-1".to_string();
+    let mut unit_size = 1;
+    let mut sample_rate = None;
+    let mut probe_bit = None;
+    for line in metadata.lines().map(|line| line.trim()) {
+        if line.starts_with('[') {
+            in_device = line.starts_with("[device");
+            continue;
+        }
+        if !in_device {
+            continue;
+        }
+
+        let (key, value) = match line.find('=') {
+            Some(idx) => (&line[..idx], &line[idx + 1..]),
+            None => continue,
+        };
+        match key {
+            "capturefile" => capture_file = value.into(),
+            "unitsize" => unit_size = value.parse::<usize>().map_err(|_| "invalid unitsize")?,
+            "samplerate" => sample_rate = Some(parse_sample_rate(value)?),
+            _ if key.starts_with("probe") && value == probe_name => {
+                let probe_number = key["probe".len()..].parse::<usize>().ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid probe key {}", key))?;
+                probe_bit = Some(probe_number - 1);
+            }
+            _ => (),
+        }
+    }
+    let probe_bit = probe_bit.ok_or_else(|| format!("no probe named {}", probe_name))?;
+    if unit_size == 0 || probe_bit >= unit_size * 8 {
+        return Err(format!("probe {} does not fit in samples of {} bytes", probe_name, unit_size));
+    }
+
+    // Samples are split across `logic-1-1`, `logic-1-2`, ..., or stored in a
+    // single `logic-1` file by older versions
+    let mut chunk_names = archive.file_names()
+        .filter_map(|name| {
+            if name == capture_file {
+                Some((0, name.to_string()))
+            } else {
+                name.strip_prefix(&format!("{}-", capture_file))
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .map(|n| (n, name.to_string()))
+            }
+        })
+        .collect::<Vec<_>>();
+    chunk_names.sort();
+
+    let mut data = vec![];
+    for (_, name) in chunk_names {
+        archive.by_name(&name)
+            .map_err(|e| e.to_string())?
+            .read_to_end(&mut data)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let (byte, bit) = (probe_bit / 8, probe_bit % 8);
+    let samples = data.chunks_exact(unit_size)
+        .enumerate()
+        .map(|(idx, sample)| {
+            let time = match sample_rate {
+                Some(rate) => scale_time(idx as u64, time_scale as u128 * SECOND_FEMTOSECONDS, rate as u128 * TIME_UNIT_FEMTOSECONDS),
+                None => (idx as u64).checked_mul(time_scale),
+            };
+            let time = time.ok_or_else(|| format!("sample {} is too late", idx))?;
+            Ok((time, if sample[byte] & (1 << bit) != 0 { l::Value::High } else { l::Value::Low }))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    check_sample_times(&samples)?;
+    Ok(transitions(samples))
+}
+
+/// Parses a sample rate from a sigrok session, like `24 MHz`, into hertz.
+fn parse_sample_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier = match unit.trim() {
+        "" | "Hz" => 1,
+        "kHz" => 1_000,
+        "MHz" => 1_000_000,
+        "GHz" => 1_000_000_000,
+        _ => return Err(format!("invalid samplerate {}", value)),
+    };
+
+    number.parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|rate| *rate > 0)
+        .ok_or_else(|| format!("invalid samplerate {}", value))
+}
+
+/// Checks that the times of a capture's samples never go backwards, and that
+/// no two samples with different values were scaled to the same time, since
+/// one of them would be lost.
+fn check_sample_times(samples: &[(u64, l::Value)]) -> Result<(), String> {
+    for pair in samples.windows(2) {
+        let ((last_time, last_value), (time, value)) = (pair[0], pair[1]);
+        if time < last_time {
+            return Err(format!("time goes backwards from {} to {}", last_time, time));
+        }
+        if time == last_time && value != last_value {
+            return Err(format!(
+                "samples with different values are both at time {}, so a larger time scale is needed",
+                time,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reduces a list of samples to only those where the value changes.
+pub fn transitions(samples: Vec<(u64, l::Value)>) -> Vec<(u64, l::Value)> {
+    let mut result: Vec<(u64, l::Value)> = vec![];
+    for (time, value) in samples {
+        if result.last().is_none_or(|(_, last_value)| *last_value != value) {
+            result.push((time, value));
+        }
+    }
+    result
+}
+
+/// Builds a component with a single `out` pin, whose script drives the given
+/// changes onto that pin. The changes must be sorted by time.
+pub fn replay_component(name: &str, changes: &[(u64, l::Value)]) -> m::ComponentDefinition {
+    let mut body = vec![];
+    let mut current_time = 0;
+    for (time, value) in changes {
+        if *time > current_time {
+            body.push(se::Instruction::Push(se::Object::Integer((time - current_time) as i64)));
+            body.push(se::Instruction::SuspendSleep);
+            current_time = *time;
+        }
+
+        body.push(se::Instruction::Push(se::Object::LogicValue(*value)));
+        body.push(se::Instruction::Push(se::Object::Integer(0)));
+        body.push(se::Instruction::GetOwnComponentIdx);
+        body.push(se::Instruction::ModifyComponentPin);
+    }
+    body.push(se::Instruction::Halt);
+
+    m::ComponentDefinition {
+        name: name.into(),
+        constructor: None,
+        functions: vec![],
+        pins: vec![Arc::new(m::PinDefinition { name: "out".into() })],
+        script: Some(Arc::new(se::Function {
+            parameters: vec![],
+            body,
+        })),
+        variables: vec![],
+        timing_checks: vec![],
+        generic_arguments: vec![],
+    }
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+
+use crate::model::{self as m, ConnectedComponents};
+use crate::model_compiler as mc;
+use crate::script_engine as se;
+use crate::parser as p;
+use crate::log::LogMessage;
+
+/// The instance name given to the component made from the pins, variables
+/// and script of a test block, so that the test can connect to it.
+pub const TESTBENCH_INSTANCE_NAME: &str = "test";
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub failures: Vec<m::Failure>,
+    pub log_messages: Vec<LogMessage>,
+    pub time_elapsed: u64,
+
+    /// Whether the test's script ran to completion. A test without a script
+    /// is always finished.
+    pub finished: bool,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.finished && self.failures.is_empty()
+    }
+}
+
+/// Returns the name and body of every test block in a parsed model.
+pub fn tests(node: &p::Node) -> Vec<(&str, &p::Node)> {
+    match node {
+        p::Node::Body(nodes) => nodes.iter().flat_map(tests).collect(),
+        p::Node::Test { name, body } => vec![(name.as_str(), body.as_ref())],
+        _ => vec![],
+    }
+}
+
+/// Builds the model for one test. This has everything from the top level of
+/// the model, and then a testbench component made from the test's pins,
+/// variables and script, followed by the test's own components and
+/// connections.
+pub fn compile_test(node: &p::Node, name: &str) -> Result<m::Model, Box<dyn Error>> {
+    let top_level = match node {
+        p::Node::Body(nodes) => nodes,
+        _ => return Err("expected a model".into()),
+    };
+    let test_body = match tests(node).into_iter().find(|(n, _)| *n == name) {
+        Some((_, p::Node::Body(nodes))) => nodes,
+        _ => return Err(format!("no test named {}", name).into()),
+    };
+
+    let (testbench_items, model_items): (Vec<_>, Vec<_>) = test_body.iter()
+        .cloned()
+        .partition(|n| matches!(n,
+            p::Node::PinDefinition(_)
+            | p::Node::ScriptDefinition(_)
+            | p::Node::ConstructorDefinition { .. }
+            | p::Node::ComponentVariableDefinition { .. }
+        ));
+
+    // Quote the test name, so it can't clash with a real component
+    let testbench_name = format!("test {:?}", name);
+    let mut nodes = top_level.clone();
+    nodes.push(p::Node::ComponentDefinition {
+        name: testbench_name.clone(),
+        generic_parameters: vec![],
+        body: Box::new(p::Node::Body(testbench_items)),
+    });
+    nodes.push(p::Node::ComponentInstantiation {
+        instance_name: Box::new(p::Node::Identifier(TESTBENCH_INSTANCE_NAME.into())),
+        component_name: testbench_name,
+        generic_arguments: vec![],
+        arguments: vec![],
+    });
+    nodes.extend(model_items);
+
+    mc::compile_model(&p::Node::Body(nodes))
+}
+
+/// Compiles and runs a single test, until its script finishes or it has run
+/// for `max_time` time units.
+pub fn run_test(node: &p::Node, name: &str, max_time: u64) -> Result<TestResult, Box<dyn Error>> {
+    let mut model = compile_test(node, name)?;
+
+    let testbench_idx = model.component_idx(&TESTBENCH_INSTANCE_NAME.to_string());
+    let testbench_interpreter_idx = model.interpreters.iter()
+        .position(|i| i.component_idx.is_some() && i.component_idx == testbench_idx);
+    let testbench_finished = |model: &m::Model| match testbench_interpreter_idx {
+        Some(idx) => model.interpreters[idx].status == se::InterpreterStatus::Halted,
+        None => true,
+    };
+
+    model.construct();
+    while !testbench_finished(&model) && model.time_elapsed < max_time {
+        if let m::StepResult::Halt = model.step() {
+            break;
+        }
+    }
+
+    Ok(TestResult {
+        name: name.into(),
+        finished: testbench_finished(&model),
+        failures: model.failures,
+        log_messages: model.log_messages,
+        time_elapsed: model.time_elapsed,
+    })
+}
+
+/// Runs every test in a parsed model, in the order they are written.
+pub fn run_tests(node: &p::Node, max_time: u64) -> Result<Vec<TestResult>, Box<dyn Error>> {
+    let names = tests(node).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    for (i, name) in names.iter().enumerate() {
+        if names[..i].contains(name) {
+            return Err(format!("duplicate test name {}", name).into());
+        }
+    }
+
+    names.into_iter().map(|name| run_test(node, name, max_time)).collect()
+}
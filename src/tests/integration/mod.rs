@@ -1,7 +1,7 @@
-use super::utils::create_model;
-use crate::model::{ConnectedComponents, StepResult};
+use super::utils::{create_model, printed};
+use crate::model::{StepResult, Failure};
 use crate::logic::Value;
-use crate::script_engine as se;
+use crate::log::{LogLevel, LogMessage};
 
 #[test]
 fn empty_model() {
@@ -43,9 +43,9 @@ fn simple_model_with_connection() {
             pin in;
 
             script {
-                _dump(in);
+                print(\"{}\", in);
                 sleep(100);
-                _dump(in);
+                print(\"{}\", in);
             }
         }
 
@@ -56,10 +56,10 @@ fn simple_model_with_connection() {
     ");
     model.run(100000, |_, _| {});
     assert_eq!(
-        model.components[model.component_idx(&"s".to_string()).unwrap()].dumps,
+        printed(&model, "s"),
         vec![
-            se::Object::LogicValue(Value::Unknown),
-            se::Object::LogicValue(Value::High),
+            "X",
+            "H",
         ]
     )
 }
@@ -70,17 +70,17 @@ fn simple_model_with_locals_in_script() {
         define component Component {
             script {
                 var a;
-                _dump(a);
+                print(\"{}\", a);
 
                 var b = 3;
-                _dump(b);
+                print(\"{}\", b);
 
                 a = 10;
-                _dump(a);
+                print(\"{}\", a);
 
                 b = H;
                 b = L;
-                _dump(b);
+                print(\"{}\", b);
             }
         }
 
@@ -88,12 +88,12 @@ fn simple_model_with_locals_in_script() {
     ");
     model.run(100000, |_, _| {});
     assert_eq!(
-        model.components[model.component_idx(&"c".to_string()).unwrap()].dumps,
+        printed(&model, "c"),
         vec![
-            se::Object::Null,
-            se::Object::Integer(3),
-            se::Object::Integer(10),
-            se::Object::LogicValue(Value::Low),
+            "null",
+            "3",
+            "10",
+            "L",
         ]
     )
 }
@@ -104,9 +104,9 @@ fn simple_model_with_arithmetic() {
         define component Component {
             script {
                 var a = 5;
-                _dump(a + -1);
+                print(\"{}\", a + -1);
 
-                _dump(2 * 5 - 2 * 3 - (6 / 3));
+                print(\"{}\", 2 * 5 - 2 * 3 - (6 / 3));
             }
         }
 
@@ -114,10 +114,10 @@ fn simple_model_with_arithmetic() {
     ");
     model.run(100000, |_, _| {});
     assert_eq!(
-        model.components[model.component_idx(&"c".to_string()).unwrap()].dumps,
+        printed(&model, "c"),
         vec![
-            se::Object::Integer(4),
-            se::Object::Integer(2),
+            "4",
+            "2",
         ]
     )
 }
@@ -130,7 +130,7 @@ fn simple_model_with_flow_constructs() {
                 var i = 0;
 
                 loop {
-                    _dump(i);
+                    print(\"{}\", i);
 
                     if (i == 9) {
                         break;
@@ -145,18 +145,18 @@ fn simple_model_with_flow_constructs() {
     ");
     model.run(100000, |_, _| {});
     assert_eq!(
-        model.components[model.component_idx(&"c".to_string()).unwrap()].dumps,
+        printed(&model, "c"),
         vec![
-            se::Object::Integer(0),
-            se::Object::Integer(1),
-            se::Object::Integer(2),
-            se::Object::Integer(3),
-            se::Object::Integer(4),
-            se::Object::Integer(5),
-            se::Object::Integer(6),
-            se::Object::Integer(7),
-            se::Object::Integer(8),
-            se::Object::Integer(9),
+            "0",
+            "1",
+            "2",
+            "3",
+            "4",
+            "5",
+            "6",
+            "7",
+            "8",
+            "9",
         ]
     )
 }
@@ -166,14 +166,14 @@ fn simple_model_with_logic() {
     let mut model = create_model("
         define component Component {
             script {
-                _dump(H && H);
-                _dump(H && L);
-                _dump(L && L);
+                print(\"{}\", H && H);
+                print(\"{}\", H && L);
+                print(\"{}\", L && L);
 
-                _dump(H || L);
-                _dump(L || L);
+                print(\"{}\", H || L);
+                print(\"{}\", L || L);
 
-                _dump((L || H) && (H && H));
+                print(\"{}\", (L || H) && (H && H));
             }
         }
 
@@ -181,16 +181,16 @@ fn simple_model_with_logic() {
     ");
     model.run(100000, |_, _| {});
     assert_eq!(
-        model.components[model.component_idx(&"c".to_string()).unwrap()].dumps,
+        printed(&model, "c"),
         vec![
-            se::Object::LogicValue(Value::High),
-            se::Object::LogicValue(Value::Low),
-            se::Object::LogicValue(Value::Low),
+            "H",
+            "L",
+            "L",
 
-            se::Object::LogicValue(Value::High),
-            se::Object::LogicValue(Value::Low),
+            "H",
+            "L",
             
-            se::Object::LogicValue(Value::High),
+            "H",
         ]
     )
 }
@@ -205,7 +205,7 @@ fn simple_model_with_triggers() {
                 loop {
                     sleep(100);
                     out <- !out;
-                    _dump(out);
+                    print(\"{}\", out);
                 }
             }
         }
@@ -216,7 +216,7 @@ fn simple_model_with_triggers() {
             script {
                 loop {
                     trigger;
-                    _dump(in);
+                    print(\"{}\", in);
                     out <- !in;
                 }
             }
@@ -231,26 +231,26 @@ fn simple_model_with_triggers() {
     model.run(550, |_, _| {});
 
     assert_eq!(
-        model.components[model.component_idx(&"c".to_string()).unwrap()].dumps,
+        printed(&model, "c"),
         vec![
-            se::Object::LogicValue(Value::High),
-            se::Object::LogicValue(Value::Low),
-            se::Object::LogicValue(Value::High),
-            se::Object::LogicValue(Value::Low),
-            se::Object::LogicValue(Value::High),            
-            se::Object::LogicValue(Value::Low),
+            "H",
+            "L",
+            "H",
+            "L",
+            "H",            
+            "L",
         ]
     );
 
     assert_eq!(
-        model.components[model.component_idx(&"n".to_string()).unwrap()].dumps,
+        printed(&model, "n"),
         vec![
-            se::Object::LogicValue(Value::Low),
-            se::Object::LogicValue(Value::High),
-            se::Object::LogicValue(Value::Low),
-            se::Object::LogicValue(Value::High),
-            se::Object::LogicValue(Value::Low),
-            se::Object::LogicValue(Value::High),            
+            "L",
+            "H",
+            "L",
+            "H",
+            "L",
+            "H",            
         ]
     );
 }
@@ -266,7 +266,7 @@ fn simple_model_with_constructor() {
             }
 
             script {
-                _dump(out);
+                print(\"{}\", out);
             }
         }
 
@@ -278,16 +278,16 @@ fn simple_model_with_constructor() {
     model.run(10, |_, _| {});
 
     assert_eq!(
-        model.components[model.component_idx(&"ch".to_string()).unwrap()].dumps,
+        printed(&model, "ch"),
         vec![
-            se::Object::LogicValue(Value::High),
+            "H",
         ]
     );
 
     assert_eq!(
-        model.components[model.component_idx(&"cl".to_string()).unwrap()].dumps,
+        printed(&model, "cl"),
         vec![
-            se::Object::LogicValue(Value::Low),
+            "L",
         ]
     );
 }
@@ -305,8 +305,8 @@ fn simple_model_with_component_variables() {
             }
 
             script {
-                _dump(x);
-                _dump(y);
+                print(\"{}\", x);
+                print(\"{}\", y);
             }
         }
 
@@ -318,18 +318,98 @@ fn simple_model_with_component_variables() {
     model.run(10, |_, _| {});
 
     assert_eq!(
-        model.components[model.component_idx(&"a".to_string()).unwrap()].dumps,
+        printed(&model, "a"),
         vec![
-            se::Object::Integer(1),
-            se::Object::Integer(2),
+            "1",
+            "2",
         ]
     );
 
     assert_eq!(
-        model.components[model.component_idx(&"b".to_string()).unwrap()].dumps,
+        printed(&model, "b"),
         vec![
-            se::Object::Integer(3),
-            se::Object::Integer(4),
+            "3",
+            "4",
         ]
     );
 }
+
+#[test]
+fn simple_model_with_assertions() {
+    let mut model = create_model("
+        define component Checker {
+            pin in;
+
+            script {
+                expect(in == X);
+                sleep(10);
+                expect(in == H);
+                assert(in == L, \"in should be low\");
+                print(\"{}\", in);
+            }
+        }
+
+        component c = Checker();
+    ");
+    assert!(!model.failed());
+    model.run(100, |_, _| {});
+
+    assert_eq!(
+        model.failures,
+        vec![
+            Failure { time: 10, instance_name: "c".into(), message: "expected in == H".into() },
+            Failure { time: 10, instance_name: "c".into(), message: "in should be low".into() },
+        ]
+    );
+    assert!(model.failed());
+
+    // The assertion stopped the script
+    assert!(printed(&model, "c").is_empty());
+}
+
+#[test]
+fn simple_model_with_logging() {
+    let mut model = create_model("
+        define component Component {
+            pin out;
+
+            constructor() {
+                log(debug, \"constructed\");
+            }
+
+            script {
+                out <- H;
+                sleep(5);
+                print(\"out={} sum={}\", out, 1 + 2);
+                log(warn, out);
+            }
+        }
+
+        component c = Component();
+    ");
+    model.construct();
+    model.run(100, |_, _| {});
+
+    let message = |time, level, message: &str| LogMessage {
+        time, level, instance_name: "c".into(), message: message.into(),
+    };
+    assert_eq!(
+        model.log_messages,
+        vec![
+            message(0, LogLevel::Debug, "constructed"),
+            message(5, LogLevel::Info, "out=H sum=3"),
+            message(5, LogLevel::Warn, "H"),
+        ]
+    );
+}
+
+#[test]
+fn log_format_strings_are_checked() {
+    assert!(crate::model::Model::compile("
+        define component Component {
+            script {
+                print(\"{} {}\", 1);
+            }
+        }
+    ".into()).is_err());
+}
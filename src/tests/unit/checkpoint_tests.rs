@@ -0,0 +1,172 @@
+use crate::Simulation;
+use crate::checkpoint;
+use crate::logic::Value;
+use crate::native::{NativeComponent, NativeContext, NativeRegistry, Wait};
+
+const MODEL: &str = "
+    define component Clock {
+        pin out;
+
+        script {
+            out <- L;
+            loop {
+                sleep(10);
+                out <- !out;
+            }
+        }
+    }
+
+    define component Counter {
+        pin clk;
+        var count;
+
+        constructor() {
+            count = 0;
+        }
+
+        script {
+            var last = L;
+            loop {
+                trigger;
+                if (clk) {
+                    count = count + 1;
+                    print(\"count is {}, last was {}\", count, last);
+                }
+                last = clk;
+            }
+        }
+    }
+
+    component clk = Clock();
+    component counter = Counter();
+    connect(clk.out, counter.clk);
+";
+
+#[test]
+fn it_saves_and_loads_models() {
+    let mut simulation = Simulation::compile(MODEL).unwrap();
+    simulation.run_until(35);
+
+    let mut buffer = vec![];
+    checkpoint::save(simulation.model(), &mut buffer).unwrap();
+    let loaded = checkpoint::load(&mut &buffer[..]).unwrap();
+
+    assert_eq!(&loaded, simulation.model());
+    assert_eq!(loaded.log_messages, simulation.log_messages());
+}
+
+#[test]
+fn it_resumes_from_checkpoints() {
+    let path = std::env::temp_dir().join(format!("hiloz-checkpoint-{}.ckpt", std::process::id()));
+
+    let mut uninterrupted = Simulation::compile(MODEL).unwrap();
+    uninterrupted.run_until(100);
+
+    let mut simulation = Simulation::compile(MODEL).unwrap();
+    simulation.run_until(45);
+    simulation.save_checkpoint(&path).unwrap();
+
+    // Carrying on from the checkpoint ends up in the same place as never
+    // stopping
+    let mut resumed = Simulation::load_checkpoint(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(resumed.time(), 45);
+    resumed.run_until(100);
+
+    assert_eq!(resumed.model(), uninterrupted.model());
+    assert_eq!(resumed.log_messages(), uninterrupted.log_messages());
+    assert_eq!(resumed.pin("clk.out"), Some(Value::Low));
+}
+
+#[test]
+fn it_resumes_from_checkpoints_with_delayed_connections() {
+    let path = std::env::temp_dir().join(format!("hiloz-delay-checkpoint-{}.ckpt", std::process::id()));
+    let model = MODEL.replace("connect(clk.out, counter.clk);", "connect(clk.out, counter.clk) delay 5;");
+
+    let mut uninterrupted = Simulation::compile(&model).unwrap();
+    uninterrupted.run_until(100);
+
+    // Stop while a change is still on its way through the delay
+    let mut simulation = Simulation::compile(&model).unwrap();
+    simulation.run_until(12);
+    simulation.save_checkpoint(&path).unwrap();
+    let mut resumed = Simulation::load_checkpoint(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The connections only hold the model's structure, so they still match
+    // the ones of a freshly compiled model
+    assert_eq!(resumed.model().connections, Simulation::compile(&model).unwrap().model().connections);
+
+    resumed.run_until(100);
+    assert_eq!(resumed.model(), uninterrupted.model());
+    assert_eq!(resumed.log_messages(), uninterrupted.log_messages());
+}
+
+#[test]
+fn it_resumes_from_checkpoints_with_random_state() {
+    let path = std::env::temp_dir().join(format!("hiloz-random-checkpoint-{}.ckpt", std::process::id()));
+    let compile = || {
+        let mut simulation = Simulation::compile("
+            define component Noise {
+                pin out;
+
+                script {
+                    loop {
+                        out <- random_bit();
+                        print(\"{}\", random(0, 1000));
+                        sleep(random(1, 5));
+                    }
+                }
+            }
+
+            seed 11;
+            component a = Noise();
+            component b = Noise();
+            component c = Noise();
+        ").unwrap();
+        simulation.model_mut().shuffle_execution_order(3);
+        simulation.force_pin("b.out", Value::High).unwrap();
+        simulation
+    };
+
+    let mut uninterrupted = compile();
+    uninterrupted.run_until(100);
+
+    let mut simulation = compile();
+    simulation.run_until(45);
+    simulation.save_checkpoint(&path).unwrap();
+    let mut resumed = Simulation::load_checkpoint(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The random numbers, the execution order and the forced pin all carry
+    // on from where they were
+    resumed.run_until(100);
+    assert_eq!(resumed.model(), uninterrupted.model());
+    assert_eq!(resumed.log_messages(), uninterrupted.log_messages());
+    assert_eq!(resumed.pin("b.out"), Some(Value::High));
+}
+
+#[test]
+fn it_rejects_invalid_checkpoints() {
+    assert!(checkpoint::load(&mut &b"not a checkpoint"[..]).is_err());
+    assert!(checkpoint::load(&mut &b"HILOZ"[..]).is_err());
+    assert!(checkpoint::load(&mut &b"HILOZCKP\x63\0\0\0"[..]).is_err());
+}
+
+#[derive(Debug, Clone)]
+struct Idle;
+
+impl NativeComponent for Idle {
+    fn on_wake(&mut self, _context: &mut NativeContext) -> Wait {
+        Wait::Halt
+    }
+}
+
+#[test]
+fn it_does_not_checkpoint_native_components() {
+    let mut registry = NativeRegistry::new();
+    registry.register("Idle", &["out"], |_| Ok(Box::new(Idle)));
+    let simulation = Simulation::compile_with_natives("component n = Idle();", &registry).unwrap();
+
+    assert!(checkpoint::save(simulation.model(), &mut vec![]).is_err());
+}
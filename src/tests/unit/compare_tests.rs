@@ -0,0 +1,80 @@
+use crate::compare::*;
+use crate::vcd::{parse_vcd, VcdGenerator};
+use crate::trace::TraceFilter;
+use crate::logic::Value;
+use super::utils::create_model;
+
+#[test]
+fn it_compares_signals() {
+    let expected = [(0, Value::Low), (10, Value::High), (20, Value::Low)];
+
+    // Repeated values are not changes
+    assert_eq!(compare_signal("s", &expected, &[(0, Value::Low), (5, Value::Low), (10, Value::High), (20, Value::Low)], 0), None);
+
+    assert_eq!(
+        compare_signal("s", &expected, &[(0, Value::Low), (10, Value::Unknown), (20, Value::Low)], 0),
+        Some(Divergence { signal: "s".into(), time: 10, expected: Some(Value::High), actual: Some(Value::Unknown) }),
+    );
+    assert_eq!(
+        compare_signal("s", &expected, &[(0, Value::Low), (10, Value::High)], 0),
+        Some(Divergence { signal: "s".into(), time: 20, expected: Some(Value::Low), actual: Some(Value::High) }),
+    );
+    assert_eq!(
+        compare_signal("s", &expected, &[], 0),
+        Some(Divergence { signal: "s".into(), time: 0, expected: Some(Value::Low), actual: None }),
+    );
+}
+
+#[test]
+fn it_tolerates_skew() {
+    let expected = [(0, Value::Low), (10, Value::High)];
+    let actual = [(0, Value::Low), (12, Value::High)];
+
+    assert_eq!(
+        compare_signal("s", &expected, &actual, 1),
+        Some(Divergence { signal: "s".into(), time: 10, expected: Some(Value::High), actual: Some(Value::Low) }),
+    );
+    assert_eq!(compare_signal("s", &expected, &actual, 2), None);
+}
+
+#[test]
+fn it_compares_a_simulation_against_a_reference() {
+    let mut model = create_model("
+        define component Clock {
+            pin out;
+
+            script {
+                loop {
+                    out <- H;
+                    sleep(10);
+                    out <- L;
+                    sleep(10);
+                }
+            }
+        }
+
+        component clk = Clock();
+    ");
+    let mut trace = VcdGenerator::new(TraceFilter::default());
+    trace.generate_header(&model);
+    model.run(100, |a, b| trace.step(a, b));
+    let actual = parse_vcd(&trace.contents()).unwrap();
+
+    let reference = parse_vcd("
+        $scope module simulation $end
+        $scope module clk $end
+        $var wire 1 ! out $end
+        $upscope $end
+        $upscope $end
+        $enddefinitions $end
+        #0 1!
+        #10 0!
+        #20 1!
+        #30 0!
+    ").unwrap();
+
+    assert_eq!(
+        compare_traces(&reference, &actual, 0),
+        vec![Divergence { signal: "simulation.clk.out".into(), time: 40, expected: Some(Value::Low), actual: Some(Value::High) }],
+    );
+}
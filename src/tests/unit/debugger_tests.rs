@@ -0,0 +1,183 @@
+use crate::Simulation;
+use crate::model::Model;
+use crate::logic::Value;
+use crate::debugger::{Breakpoint, Command, Debugger, Edge, LineBreakpoint};
+
+const MODEL: &str = "
+    define component Clock {
+        pin out;
+
+        script {
+            out <- L;
+            loop {
+                sleep(10);
+                out <- !out;
+            }
+        }
+    }
+
+    define component Counter {
+        pin clk;
+        var count;
+
+        constructor() {
+            count = 0;
+        }
+
+        script {
+            loop {
+                trigger;
+                if (clk) {
+                    count = count + 1;
+                }
+            }
+        }
+    }
+
+    component clk = Clock();
+    component counter = Counter();
+    connect(clk.out, counter.clk);
+";
+
+fn debugger() -> Debugger {
+    Debugger::new(Simulation::new(Model::compile_with_source_lines(MODEL.into()).unwrap()), 1000)
+}
+
+fn run(debugger: &mut Debugger, command: &str) -> String {
+    debugger.execute(command.parse().unwrap()).unwrap()
+}
+
+#[test]
+fn it_parses_commands() {
+    assert_eq!("step".parse(), Ok(Command::Step(1)));
+    assert_eq!("s 5".parse(), Ok(Command::Step(5)));
+    assert_eq!("run 100".parse(), Ok(Command::Run(100)));
+    assert_eq!("print".parse(), Ok(Command::Print(None)));
+    assert_eq!("p clk.out".parse(), Ok(Command::Print(Some("clk.out".into()))));
+    assert_eq!("break clk.out falling".parse(), Ok(Command::Break(Breakpoint {
+        pin: "clk.out".into(),
+        edge: Edge::Falling,
+    })));
+    assert_eq!("set clk.out H".parse(), Ok(Command::Set("clk.out".into(), Value::High)));
+    assert_eq!("break 12".parse(), Ok(Command::BreakLine(LineBreakpoint { instance: None, line: 12 })));
+    assert_eq!("b cpu:12".parse(), Ok(Command::BreakLine(LineBreakpoint { instance: Some("cpu".into()), line: 12 })));
+    assert_eq!("stepi".parse(), Ok(Command::StepInstruction));
+    assert_eq!("force clk.out X".parse(), Ok(Command::Force("clk.out".into(), Value::Unknown)));
+    assert_eq!("release clk.out".parse(), Ok(Command::Release("clk.out".into())));
+
+    assert!("run".parse::<Command>().is_err());
+    assert!("break clk.out sideways".parse::<Command>().is_err());
+    assert!("set clk.out Z".parse::<Command>().is_err());
+    assert!("dance".parse::<Command>().is_err());
+}
+
+#[test]
+fn it_steps_and_runs_to_a_time() {
+    let mut debugger = debugger();
+
+    assert_eq!(run(&mut debugger, "step"), "Stopped at 0");
+    assert_eq!(debugger.simulation().pin("clk.out"), Some(Value::Low));
+
+    assert_eq!(run(&mut debugger, "run 25"), "Stopped at 25");
+    assert_eq!(debugger.simulation().time(), 25);
+    assert_eq!(run(&mut debugger, "print clk.out"), "clk.out = L");
+    assert_eq!(run(&mut debugger, "print counter"), "\
+counter (Counter)
+  pin clk = L
+  var count = 1
+  script suspended");
+    assert_eq!(run(&mut debugger, "nets"), "net 0: clk.out, counter.clk = L");
+}
+
+#[test]
+fn it_stops_at_breakpoints() {
+    let mut debugger = debugger();
+
+    assert_eq!(run(&mut debugger, "break clk.out rising"), "Breakpoint 1 set");
+    assert_eq!(run(&mut debugger, "continue"), "Breakpoint 1 hit at 10: clk.out L -> H");
+    assert_eq!(run(&mut debugger, "continue"), "Breakpoint 1 hit at 30: clk.out L -> H");
+
+    assert_eq!(run(&mut debugger, "until clk.out"), "Stopped at 40");
+    assert_eq!(run(&mut debugger, "delete 1"), "Breakpoint 1 deleted");
+    assert_eq!(run(&mut debugger, "continue"), "Stopped at 1000");
+
+    assert!(debugger.execute(Command::Delete(1)).is_err());
+    assert!(debugger.execute("break nothing.out".parse().unwrap()).is_err());
+}
+
+#[test]
+fn it_sets_pins() {
+    let mut debugger = debugger();
+
+    assert_eq!(run(&mut debugger, "set counter.clk H"), "counter.clk = H");
+    assert_eq!(debugger.simulation().pin("clk.out"), Some(Value::High));
+}
+
+#[test]
+fn it_forces_and_releases_pins() {
+    let mut debugger = debugger();
+
+    run(&mut debugger, "run 5");
+    assert_eq!(run(&mut debugger, "force counter.clk H"), "counter.clk = H (forced)");
+    assert_eq!(run(&mut debugger, "run 25"), "Stopped at 25");
+    assert_eq!(run(&mut debugger, "nets"), "net 0: clk.out, counter.clk = H (forced)");
+    assert!(run(&mut debugger, "print clk").contains("pin out = H (forced)"));
+
+    // The clock's own value comes back when released
+    assert_eq!(run(&mut debugger, "release clk.out"), "clk.out = L");
+    assert!(debugger.execute("release clk.out".parse().unwrap()).is_err());
+}
+
+#[test]
+fn it_stops_at_script_lines() {
+    let mut debugger = debugger();
+
+    // The line of `count = count + 1;`, counting from the start of MODEL
+    assert_eq!(run(&mut debugger, "break counter:26"), "Breakpoint 1 set");
+    assert_eq!(run(&mut debugger, "continue"), "Breakpoint 1 hit at 10: counter line 26");
+    assert!(run(&mut debugger, "print counter").ends_with("var count = 0\n  script paused at line 26"));
+
+    let paused = run(&mut debugger, "where");
+    assert!(paused.starts_with("counter (Counter) before Line(26)\n  frame 0: instruction "));
+    assert!(paused.ends_with(", line 26\n    stack []"));
+
+    // Step over the line marker, then into the statement
+    assert_eq!(run(&mut debugger, "stepi"), "Paused at 10: counter line 26");
+    assert_eq!(run(&mut debugger, "stepi"), "Paused at 10: counter line 26");
+    assert!(run(&mut debugger, "where").ends_with("stack [1]"));
+
+    assert_eq!(run(&mut debugger, "continue"), "Breakpoint 1 hit at 30: counter line 26");
+    assert!(run(&mut debugger, "print counter").contains("var count = 1"));
+
+    assert!(debugger.execute("break 99".parse().unwrap()).is_err());
+    assert!(debugger.execute("break nothing:26".parse().unwrap()).is_err());
+}
+
+#[test]
+fn it_only_stops_at_lines_of_the_main_file() {
+    let dir = std::env::temp_dir().join(format!("hiloz-debug-imports-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("model.hlzm"), "import std;\nimport \"ticker.hlzm\";\ncomponent t = Ticker();\ncomponent c = Clock(10);\n").unwrap();
+    std::fs::write(dir.join("ticker.hlzm"), "define component Ticker {\n    script {\n        loop {\n            sleep(5);\n        }\n    }\n}\n").unwrap();
+    let parsed = crate::parser::parse_file_with_source_lines(&dir.join("model.hlzm"), &[]).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let mut debugger = Debugger::new(Simulation::new(crate::model_compiler::compile_model(&parsed).unwrap()), 100);
+
+    // Neither the imported file nor std have lines, so they can't have
+    // breakpoints, rather than having ones which never stop
+    let error = debugger.execute("break t:4".parse().unwrap()).unwrap_err();
+    assert!(error.contains("scripts from imported files can't have breakpoints"));
+    assert!(debugger.execute("break c:4".parse().unwrap()).is_err());
+    let error = debugger.execute("break 4".parse().unwrap()).unwrap_err();
+    assert_eq!(error, "no script statement on line 4 of the main model file");
+}
+
+#[test]
+fn it_only_pauses_when_asked() {
+    let mut debugger = debugger();
+    assert!(debugger.execute(Command::Where).is_err());
+
+    assert_eq!(run(&mut debugger, "stepi"), "Paused at 0: clk line 6");
+    assert_eq!(run(&mut debugger, "step"), "Stopped at 0");
+    assert!(debugger.execute(Command::Where).is_err());
+}
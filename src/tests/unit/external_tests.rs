@@ -0,0 +1,205 @@
+use std::{env, fs, process};
+
+use crate::Simulation;
+use crate::logic::Value;
+
+// Toggles `led` every 10 time units, and copies `button` onto `echo`
+const BLINKY: &str = r#"
+    state=L
+    while read cmd name value; do
+        case $cmd in
+            construct) reply=ok ;;
+            wake) reply='sleep 10' ;;
+            pin) [ $name = button ] && button=$value ;;
+            end)
+                if [ "$reply" = ok ]; then
+                    echo set led L
+                else
+                    echo set led $state
+                    echo set echo $button
+                    [ $state = L ] && state=H || state=L
+                fi
+                echo "$reply" ;;
+            quit) exit 0 ;;
+        esac
+    done
+"#;
+
+#[test]
+fn it_runs_external_processes() {
+    let path = env::temp_dir().join(format!("hiloz-blinky-{}.sh", process::id()));
+    fs::write(&path, BLINKY).unwrap();
+    let mut simulation = Simulation::compile(&format!("
+        component mcu = External(\"sh {}\", \"led button echo\");
+    ", path.display())).unwrap();
+
+    simulation.drive_pin("mcu.button", Value::High).unwrap();
+    simulation.run_until(5);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::Low));
+    assert_eq!(simulation.pin("mcu.echo"), Some(Value::High));
+
+    simulation.drive_pin("mcu.button", Value::Low).unwrap();
+    simulation.run_until(15);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::High));
+    assert_eq!(simulation.pin("mcu.echo"), Some(Value::Low));
+
+    simulation.run_until(25);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::Low));
+    assert!(simulation.failures().is_empty());
+
+    drop(simulation);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn it_does_not_share_processes_between_copies() {
+    let path = env::temp_dir().join(format!("hiloz-blinky-copy-{}.sh", process::id()));
+    fs::write(&path, BLINKY).unwrap();
+    let mut simulation = Simulation::compile(&format!("
+        component mcu = External(\"sh {}\", \"led button echo\");
+    ", path.display())).unwrap();
+
+    // A copy made before the process starts gets its own
+    let mut before = Simulation::new(simulation.model().clone());
+    before.run_until(15);
+    assert_eq!(before.pin("mcu.led"), Some(Value::High));
+    assert!(before.failures().is_empty());
+
+    // A copy made afterwards can't carry on the original's process, so it
+    // fails, and the original is left alone
+    simulation.run_until(5);
+    let mut after = simulation.model().clone();
+    after.run(15, |_, _| ());
+    assert_eq!(after.failures.len(), 1);
+    assert!(after.failures[0].message.contains("cannot run a copy"));
+
+    simulation.run_until(15);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::High));
+    assert!(simulation.failures().is_empty());
+
+    drop((simulation, before, after));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn it_reports_external_process_errors() {
+    let mut simulation = Simulation::compile("
+        component mcu = External(\"read line; echo nonsense\", \"led\");
+    ").unwrap();
+
+    simulation.run_until(10);
+    assert_eq!(simulation.failures().len(), 1);
+    assert_eq!(simulation.failures()[0].instance_name, "mcu");
+    assert!(simulation.failures()[0].message.contains("expected ok, got nonsense"));
+}
+
+#[test]
+fn it_stops_processes_which_ignore_the_protocol() {
+    for command in ["cat", "yes"] {
+        let mut simulation = Simulation::compile(&format!("
+            component mcu = External(\"{}\", \"led\");
+        ", command)).unwrap();
+
+        simulation.run_until(10);
+        assert_eq!(simulation.failures().len(), 1);
+        assert!(simulation.failures()[0].message.contains("expected ok, got"));
+    }
+}
+
+#[test]
+fn it_rejects_bad_external_arguments() {
+    assert!(Simulation::compile("component mcu = External(\"true\");").is_err());
+}
+
+#[test]
+fn it_runs_firmware_built_with_the_hal() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let path = env::temp_dir().join(format!("hiloz-blink-{}", process::id()));
+    let built = process::Command::new("cc")
+        .arg(format!("-I{}/hal", root))
+        .arg("-o").arg(&path)
+        .arg(format!("{}/examples/blink.c", root))
+        .arg(format!("{}/hal/hiloz_hal.c", root))
+        .status();
+
+    // Not every machine running the tests has a C compiler
+    match built {
+        Ok(status) => assert!(status.success()),
+        Err(_) => return,
+    }
+
+    let mut simulation = Simulation::compile(&format!("
+        define component Button {{
+            pin out;
+        }}
+
+        component button = Button();
+        component mcu = External(\"{}\", \"led button\");
+        connect(button.out, mcu.button);
+    ", path.display())).unwrap();
+
+    simulation.drive_pin("button.out", Value::Low).unwrap();
+    simulation.run_until(10);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::Unknown));
+
+    simulation.drive_pin("button.out", Value::High).unwrap();
+    simulation.run_until(50);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::High));
+    simulation.run_until(150);
+    assert_eq!(simulation.pin("mcu.led"), Some(Value::Low));
+
+    // Keep the button held, so it blinks the rest of its five times
+    simulation.run_until(2000);
+    assert_eq!(simulation.log_messages().len(), 1);
+    assert_eq!(simulation.log_messages()[0].message, "done blinking");
+    assert_eq!(simulation.log_messages()[0].time, 1000);
+    assert!(simulation.failures().is_empty());
+
+    drop(simulation);
+    fs::remove_file(path).unwrap();
+}
+
+// Copies the last of 100 input pins to its output, which needs more pins than
+// the HAL first makes room for
+const WIDE_FIRMWARE: &str = r#"
+    #include "hiloz_hal.h"
+
+    int main(void) {
+        gpio_write("out", gpio_read("in[99]"));
+        return 0;
+    }
+"#;
+
+#[test]
+fn it_runs_firmware_with_many_pins() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let source = env::temp_dir().join(format!("hiloz-wide-{}.c", process::id()));
+    let path = env::temp_dir().join(format!("hiloz-wide-{}", process::id()));
+    fs::write(&source, WIDE_FIRMWARE).unwrap();
+    let built = process::Command::new("cc")
+        .arg(format!("-I{}/hal", root))
+        .arg("-o").arg(&path)
+        .arg(&source)
+        .arg(format!("{}/hal/hiloz_hal.c", root))
+        .status();
+    fs::remove_file(source).unwrap();
+
+    // Not every machine running the tests has a C compiler
+    match built {
+        Ok(status) => assert!(status.success()),
+        Err(_) => return,
+    }
+
+    let pins = (0..100).map(|i| format!("in[{}]", i)).collect::<Vec<_>>().join(" ");
+    let mut simulation = Simulation::compile(&format!("
+        component mcu = External(\"{}\", \"{} out\");
+    ", path.display(), pins)).unwrap();
+
+    simulation.drive_pin("mcu.in[99]", Value::High).unwrap();
+    simulation.run_until(10);
+    assert_eq!(simulation.pin("mcu.out"), Some(Value::High));
+    assert!(simulation.failures().is_empty());
+
+    drop(simulation);
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,61 @@
+use crate::fst::FstGenerator;
+use crate::trace::{TraceFilter, TraceFormat};
+use super::utils::create_model;
+
+use std::{convert::TryInto, path::Path};
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test]
+fn it_writes_fst_blocks() {
+    let mut model = create_model("
+        define component Toggle {
+            pin out;
+
+            script {
+                out <- L;
+                loop {
+                    sleep(10);
+                    out <- !out;
+                }
+            }
+        }
+
+        component t1 = Toggle();
+        component t2 = Toggle();
+
+        trace t2;
+    ");
+
+    let mut fst = FstGenerator::new(TraceFilter {
+        patterns: model.trace_patterns.clone(),
+        ..TraceFilter::default()
+    });
+    fst.generate_header(&model);
+    model.run(50, |m, mods| fst.step(m, mods));
+    let contents = fst.contents().unwrap();
+
+    // Walk the blocks, each of which is a type followed by a length
+    let mut block_types = vec![];
+    let mut offset = 0;
+    while offset < contents.len() {
+        block_types.push(contents[offset]);
+        offset += 1 + read_u64(&contents, offset + 1) as usize;
+    }
+    assert_eq!(offset, contents.len());
+    assert_eq!(block_types, vec![0, 8, 3, 4]);
+
+    // Header has start time, end time, and variable count
+    assert_eq!(read_u64(&contents, 9), 0);
+    assert_eq!(read_u64(&contents, 17), 50);
+    assert_eq!(read_u64(&contents, 49), 1);
+}
+
+#[test]
+fn it_picks_trace_formats_by_extension() {
+    assert_eq!(TraceFormat::from_path(Path::new("out.fst")), TraceFormat::Fst);
+    assert_eq!(TraceFormat::from_path(Path::new("out.vcd")), TraceFormat::Vcd);
+    assert_eq!(TraceFormat::from_path(Path::new("out")), TraceFormat::Vcd);
+}
@@ -0,0 +1,28 @@
+use crate::log::*;
+use crate::script_engine::Object;
+use crate::logic::Value;
+
+#[test]
+fn it_formats_messages() {
+    assert_eq!(
+        format_message("addr={} data={}", &[Object::Integer(3), Object::LogicValue(Value::High)]),
+        "addr=3 data=H",
+    );
+    assert_eq!(format_message("{{literal}} {}", &[Object::String("x".into())]), "{literal} x");
+    assert_eq!(format_message("{} {}", &[Object::Null]), "null {}");
+}
+
+#[test]
+fn it_counts_placeholders() {
+    assert_eq!(placeholder_count("addr={} data={}"), 2);
+    assert_eq!(placeholder_count("{{}} {}"), 1);
+    assert_eq!(placeholder_count("nothing"), 0);
+}
+
+#[test]
+fn it_orders_log_levels() {
+    assert!(LogLevel::Debug < LogLevel::Info);
+    assert!(LogLevel::Warn < LogLevel::Error);
+    assert_eq!("WARN".parse::<LogLevel>(), Ok(LogLevel::Warn));
+    assert!("verbose".parse::<LogLevel>().is_err());
+}
@@ -5,3 +5,15 @@ mod script_engine_tests;
 mod parser_tests;
 mod script_compiler_tests;
 mod model_compiler_tests;
+mod trace_tests;
+mod fst_tests;
+mod stimulus_tests;
+mod compare_tests;
+mod log_tests;
+mod testbench_tests;
+mod simulation_tests;
+mod native_tests;
+mod external_tests;
+mod debugger_tests;
+mod checkpoint_tests;
+mod random_tests;
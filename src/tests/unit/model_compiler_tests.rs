@@ -59,6 +59,8 @@ fn it_compiles_a_model() {
         ],
         script: Some(function.clone()),
         variables: vec![],
+        timing_checks: vec![],
+        generic_arguments: vec![],
     });
 
     assert_eq!(
@@ -101,7 +103,6 @@ fn it_compiles_a_model() {
                     ],
                     instance_name: "first_instance".into(),
                     variables: vec![],
-                    dumps: vec![],
                 },
 
                 m::Component {
@@ -120,7 +121,6 @@ fn it_compiles_a_model() {
                     ],
                     instance_name: "second_instance".into(),
                     variables: vec![],
-                    dumps: vec![],
                 }
             ],
             connections: vec![],
@@ -138,6 +138,7 @@ fn it_compiles_a_model() {
                         }
                     ],
                     status: se::InterpreterStatus::Normal,
+                    native: None,
                 },
                 se::Interpreter {
                     component_idx: Some(1),
@@ -152,9 +153,115 @@ fn it_compiles_a_model() {
                         }
                     ],
                     status: se::InterpreterStatus::Normal,
+                    native: None,
                 },
             ],
             ..Default::default()
         }
     )
 }
+
+#[test]
+fn it_rejects_delays_on_merged_connections() {
+    let model = |connections: &str| Model::compile(format!("
+        define component C {{ pin p; }}
+        component a = C();
+        component b = C();
+        component c = C();
+        {}
+    ", connections));
+
+    assert!(model("connect(a.p, b.p) delay 5; connect(c.p, a.p, b.p);").is_err());
+    assert!(model("connect(a.p, b.p) delay 5; connect(b.p, c.p) delay 3;").is_err());
+    assert!(model("connect(a.p, b.p); connect(b.p, c.p) delay 5;").is_err());
+
+    let model = model("connect(a.p, b.p) delay 5; connect(c.p, c.p);").unwrap();
+    assert_eq!(model.connections[0].delay, Some(Delay { time: 5, kind: DelayKind::Inertial }));
+    assert_eq!(model.connections[1].delay, None);
+}
+
+#[test]
+fn it_imports_the_standard_library() {
+    let model = Model::compile("
+        import std;
+        import std;
+        component clk = Clock(10);
+    ".into()).unwrap();
+    assert!(model.component_definitions.iter().any(|d| d.name == "DFlipFlop"));
+    assert_eq!(model.component_definitions.iter().filter(|d| d.name == "Clock").count(), 1);
+
+    assert!(Model::compile("import nothing;".into()).is_err());
+
+    // Defining a part of the library is an error, whichever comes first
+    let clock = "define component Clock { pin out; }";
+    for source in [format!("import std; {}", clock), format!("{} import std;", clock)] {
+        let error = Model::compile(source).unwrap_err();
+        assert_eq!(error.to_string().trim_end(), "Model compiler error: component Clock is already defined");
+    }
+}
+
+#[test]
+fn it_imports_model_files() {
+    let dir = std::env::temp_dir().join(format!("hiloz-imports-{}", std::process::id()));
+    let search_dir = dir.join("include");
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::create_dir_all(&search_dir).unwrap();
+    let write = |path: &str, source: &str| std::fs::write(dir.join(path), source).unwrap();
+
+    // lib/gates.hlzm imports relative to itself, and shared.hlzm is only
+    // found through the search path. Both import pins.hlzm, which must only
+    // be compiled once
+    write("model.hlzm", "
+        import \"lib/gates.hlzm\";
+        import \"shared.hlzm\";
+        component a = Inverter();
+        component b = Shared();
+    ");
+    write("lib/gates.hlzm", "
+        import \"../pins.hlzm\";
+        define component Inverter { pin in; pin out; }
+    ");
+    write("include/shared.hlzm", "
+        import \"../pins.hlzm\";
+        define component Shared { pin x; }
+    ");
+    write("pins.hlzm", "define component Pins { pin p; }");
+
+    let model = Model::compile_file(&dir.join("model.hlzm"), std::slice::from_ref(&search_dir)).unwrap();
+    let names = model.component_definitions.iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["Pins", "Inverter", "Shared"]);
+
+    // Without the search path, shared.hlzm can't be found
+    let error = Model::compile_file(&dir.join("model.hlzm"), &[]).unwrap_err();
+    assert_eq!(error.to_string(), "could not find shared.hlzm to import");
+
+    // Errors in imported files name the file
+    write("clash.hlzm", "
+        import \"lib/gates.hlzm\";
+        import \"dupe.hlzm\";
+    ");
+    write("dupe.hlzm", "define component Inverter { pin a; }");
+    let error = Model::compile_file(&dir.join("clash.hlzm"), &[]).unwrap_err();
+    assert_eq!(
+        error.to_string().trim_end(),
+        format!("Model compiler error: {}: component Inverter is already defined", dir.join("dupe.hlzm").display()),
+    );
+
+    // So do errors in the file itself, whether they are found while parsing
+    // or compiling it
+    write("twice.hlzm", "
+        define component A { pin a; }
+        define component A { pin a; }
+    ");
+    let error = Model::compile_file(&dir.join("twice.hlzm"), &[]).unwrap_err();
+    assert_eq!(
+        error.to_string().trim_end(),
+        format!("Model compiler error: {}: component A is already defined", dir.join("twice.hlzm").display()),
+    );
+
+    write("broken.hlzm", "define component {");
+    let error = Model::compile_file(&dir.join("broken.hlzm"), &[]).unwrap_err();
+    assert!(error.to_string().starts_with(&format!("{}: ", dir.join("broken.hlzm").display())));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
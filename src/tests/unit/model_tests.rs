@@ -187,11 +187,11 @@ fn it_can_make_connections() {
             Connection { pins: vec![
                 PinConnection { component_idx: 0, pin_idx: 0, },
                 PinConnection { component_idx: 1, pin_idx: 0, },        
-            ] },
+            ], delay: None },
             Connection { pins: vec![
                 PinConnection { component_idx: 2, pin_idx: 0, },
                 PinConnection { component_idx: 3, pin_idx: 0, },        
-            ] },
+            ], delay: None },
         ]
     )
 }
@@ -216,7 +216,7 @@ fn it_can_merge_overlapping_connections() {
             PinConnection { component_idx: 0, pin_idx: 0, },
             PinConnection { component_idx: 1, pin_idx: 0, },        
             PinConnection { component_idx: 2, pin_idx: 0, },
-        ] },
+        ], delay: None },
     ];
     
     assert_eq!(model.connections, expected);
@@ -228,3 +228,222 @@ fn it_can_merge_overlapping_connections() {
     ]);
     assert_eq!(model.connections, expected);
 }
+
+#[test]
+fn it_can_join_separate_connections() {
+    let mut model = utils::create_model_with_scripts(vec![
+        vec![], vec![], vec![], vec![], vec![]
+    ]);
+
+    model.connect_pins(&[
+        PinConnection { component_idx: 0, pin_idx: 0, },
+        PinConnection { component_idx: 1, pin_idx: 0, },
+    ]);
+    model.connect_pins(&[
+        PinConnection { component_idx: 2, pin_idx: 0, },
+        PinConnection { component_idx: 3, pin_idx: 0, },
+    ]);
+
+    // Both connections become one
+    model.connect_pins(&[
+        PinConnection { component_idx: 4, pin_idx: 0, },
+        PinConnection { component_idx: 1, pin_idx: 0, },
+        PinConnection { component_idx: 3, pin_idx: 0, },
+    ]);
+    assert_eq!(
+        model.connections,
+        vec![
+            Connection { pins: vec![
+                PinConnection { component_idx: 0, pin_idx: 0, },
+                PinConnection { component_idx: 1, pin_idx: 0, },
+                PinConnection { component_idx: 2, pin_idx: 0, },
+                PinConnection { component_idx: 3, pin_idx: 0, },
+                PinConnection { component_idx: 4, pin_idx: 0, },
+            ], delay: None },
+        ]
+    )
+}
+
+fn execution_order(seed: Option<u64>) -> Vec<String> {
+    let mut model = Model::compile("
+        define component Named {
+            script {
+                loop {
+                    print(\"tick\");
+                    sleep(10);
+                }
+            }
+        }
+
+        component a = Named();
+        component b = Named();
+        component c = Named();
+    ".into()).unwrap();
+    if let Some(seed) = seed {
+        model.shuffle_execution_order(seed);
+    }
+    model.construct();
+    model.run(50, |_, _| ());
+
+    model.log_messages.iter().map(|m| m.instance_name.clone()).collect()
+}
+
+#[test]
+fn it_runs_interpreters_in_declaration_order() {
+    assert_eq!(execution_order(None), ["a", "b", "c"].repeat(6));
+}
+
+#[test]
+fn it_can_shuffle_execution_order() {
+    let shuffled = execution_order(Some(1));
+    assert_ne!(shuffled, ["a", "b", "c"].repeat(6));
+    assert_eq!(shuffled, execution_order(Some(1)));
+
+    // Every interpreter still runs once per step
+    for step in shuffled.chunks(3) {
+        let mut step = step.to_vec();
+        step.sort();
+        assert_eq!(step, ["a", "b", "c"]);
+    }
+}
+
+#[test]
+fn it_does_not_change_pins_when_shuffled() {
+    // Each stage copies the one before it, so the pins would depend on the
+    // order scripts run in if they saw each other's changes within a step
+    let model = Model::compile("
+        define component Toggle {
+            pin out;
+
+            script {
+                out <- L;
+                loop {
+                    sleep(3);
+                    out <- !out;
+                }
+            }
+        }
+
+        define component Stage {
+            pin in;
+            pin out;
+
+            script {
+                loop {
+                    out <- in;
+                    trigger;
+                }
+            }
+        }
+
+        component c = Stage();
+        component b = Stage();
+        component a = Stage();
+        component t = Toggle();
+        connect(t.out, a.in);
+        connect(a.out, b.in);
+        connect(b.out, c.in);
+    ".into()).unwrap();
+
+    let pins = |seed: Option<u64>| {
+        let mut model = model.clone();
+        if let Some(seed) = seed {
+            model.shuffle_execution_order(seed);
+        }
+        model.construct();
+
+        let mut steps = vec![];
+        model.run(50, |model, _| steps.push((
+            model.time_elapsed,
+            model.components.iter().map(|c| c.pins.iter().map(|p| p.value).collect::<Vec<_>>()).collect::<Vec<_>>(),
+        )));
+        steps
+    };
+
+    let unshuffled = pins(None);
+    for seed in 0..10 {
+        assert_eq!(pins(Some(seed)), unshuffled);
+    }
+}
+
+fn random_values(seed: Option<u64>) -> Vec<(String, String)> {
+    let mut model = Model::compile("
+        define component Dice {
+            var first;
+
+            constructor() {
+                first = random(1, 6);
+            }
+
+            script {
+                print(\"{}\", first);
+                loop {
+                    print(\"{} {}\", random(1, 6), random_bit());
+                    sleep(random(5, 10));
+                }
+            }
+        }
+
+        seed 7;
+        component a = Dice();
+        component b = Dice();
+    ".into()).unwrap();
+    if let Some(seed) = seed {
+        model.seed_random(seed);
+    }
+    model.construct();
+    model.run(100, |_, _| ());
+
+    model.log_messages.iter().map(|m| (m.instance_name.clone(), m.message.clone())).collect()
+}
+
+#[test]
+fn it_generates_random_numbers_from_a_seed() {
+    let values = random_values(None);
+    assert_eq!(values, random_values(None));
+    assert_eq!(values, random_values(Some(7)));
+    assert_ne!(values, random_values(Some(8)));
+
+    for (_, message) in values.iter().filter(|(_, m)| m.contains(' ')) {
+        let (number, bit) = message.split_once(' ').unwrap();
+        assert!((1..=6).contains(&number.parse::<i64>().unwrap()));
+        assert!(bit == "H" || bit == "L");
+    }
+
+    // Each component gets its own numbers
+    let messages = |instance: &str| values.iter()
+        .filter(|(i, _)| i == instance)
+        .map(|(_, m)| m.clone())
+        .collect::<Vec<_>>();
+    assert_ne!(messages("a"), messages("b"));
+}
+
+#[test]
+fn it_does_not_change_random_numbers_when_shuffled() {
+    let mut model = Model::compile("
+        define component Noise {
+            script {
+                loop {
+                    print(\"{}\", random(0, 1000000));
+                    sleep(1);
+                }
+            }
+        }
+
+        component a = Noise();
+        component b = Noise();
+        component c = Noise();
+    ".into()).unwrap();
+    let mut shuffled = model.clone();
+    shuffled.shuffle_execution_order(3);
+
+    let messages = |model: &mut Model| {
+        model.run(20, |_, _| ());
+        let mut messages = model.log_messages.iter()
+            .map(|m| (m.time, m.instance_name.clone(), m.message.clone()))
+            .collect::<Vec<_>>();
+        messages.sort();
+        messages
+    };
+    assert_eq!(messages(&mut model), messages(&mut shuffled));
+}
@@ -0,0 +1,145 @@
+use crate::Simulation;
+use crate::logic::Value;
+use crate::native::{NativeComponent, NativeContext, NativeInstance, NativeRegistry, Wait};
+use crate::script_engine as se;
+
+/// Drives its output low for `period` time units, then high for `period`
+/// time units, forever.
+#[derive(Debug, Clone)]
+struct Clock {
+    period: u64,
+    value: Value,
+}
+
+impl NativeComponent for Clock {
+    fn on_construct(&mut self, context: &mut NativeContext) {
+        context.write_pin("out", Value::Low);
+    }
+
+    fn on_wake(&mut self, context: &mut NativeContext) -> Wait {
+        context.write_pin("out", self.value);
+        self.value = !self.value;
+        Wait::Sleep(self.period)
+    }
+}
+
+/// Counts the rising edges on its input, and fails when it has seen too many.
+#[derive(Debug, Clone)]
+struct EdgeCounter {
+    last: Value,
+    count: u64,
+    limit: u64,
+}
+
+impl NativeComponent for EdgeCounter {
+    fn on_wake(&mut self, context: &mut NativeContext) -> Wait {
+        let value = context.read_pin("in");
+        if self.last == Value::Low && value == Value::High {
+            self.count += 1;
+            if self.count > self.limit {
+                context.fail("too many edges");
+                return Wait::Halt;
+            }
+        }
+        self.last = value;
+        Wait::Trigger
+    }
+}
+
+/// Uses a pin which it doesn't have.
+#[derive(Debug, Clone)]
+struct Misnamed;
+
+impl NativeComponent for Misnamed {
+    fn on_wake(&mut self, context: &mut NativeContext) -> Wait {
+        let value = context.read_pin("nothing");
+        context.write_pin("nothing", value);
+        Wait::Halt
+    }
+}
+
+fn registry() -> NativeRegistry {
+    let mut registry = NativeRegistry::new();
+    registry.register("Clock", &["out"], |arguments| match arguments {
+        [se::Object::Integer(period)] => Ok(Box::new(Clock { period: *period as u64, value: Value::Low })),
+        _ => Err("expected a period".into()),
+    });
+    registry.register("EdgeCounter", &["in"], |_| {
+        Ok(Box::new(EdgeCounter { last: Value::Unknown, count: 0, limit: 2 }))
+    });
+    registry.register("Misnamed", &["out"], |_| Ok(Box::new(Misnamed)));
+    registry
+}
+
+#[test]
+fn it_runs_native_components() {
+    let mut simulation = Simulation::compile_with_natives("
+        define component Inverter {
+            pin in;
+            pin out;
+
+            script {
+                loop {
+                    out <- !in;
+                    trigger;
+                }
+            }
+        }
+
+        component clk = Clock(10);
+        component inv = Inverter();
+        connect(clk.out, inv.in);
+    ", &registry()).unwrap();
+
+    simulation.run_until(5);
+    assert_eq!(simulation.pin("clk.out"), Some(Value::Low));
+    assert_eq!(simulation.pin("inv.out"), Some(Value::High));
+
+    simulation.run_until(15);
+    assert_eq!(simulation.pin("clk.out"), Some(Value::High));
+    assert_eq!(simulation.pin("inv.out"), Some(Value::Low));
+
+    simulation.run_until(25);
+    assert_eq!(simulation.pin("clk.out"), Some(Value::Low));
+}
+
+#[test]
+fn it_reports_native_failures() {
+    let mut simulation = Simulation::compile_with_natives("
+        component clk = Clock(5);
+        component counter = EdgeCounter();
+        connect(clk.out, counter.in);
+    ", &registry()).unwrap();
+
+    simulation.run_until(100);
+    assert_eq!(simulation.failures().len(), 1);
+    assert_eq!(simulation.failures()[0].instance_name, "counter");
+    assert_eq!(simulation.failures()[0].message, "too many edges");
+    assert!(simulation.failures()[0].time < 30);
+}
+
+#[test]
+fn it_rejects_bad_native_arguments() {
+    assert!(Simulation::compile_with_natives("component clk = Clock();", &registry()).is_err());
+    assert!(Simulation::compile("component clk = Clock(10);").is_err());
+}
+
+#[test]
+fn it_reports_unknown_native_pins() {
+    let mut simulation = Simulation::compile_with_natives("component m = Misnamed();", &registry()).unwrap();
+
+    simulation.run_until(10);
+    let messages = simulation.failures().iter().map(|f| f.message.as_str()).collect::<Vec<_>>();
+    assert_eq!(messages, vec!["native component has no pin named nothing"; 2]);
+}
+
+#[test]
+fn it_compares_native_instances_by_name() {
+    let instance = |name: &str, value| NativeInstance {
+        name: name.into(),
+        component: Box::new(Clock { period: 10, value }),
+    };
+
+    assert_eq!(instance("Clock", Value::Low), instance("Clock", Value::High));
+    assert_ne!(instance("Clock", Value::Low), instance("OtherClock", Value::Low));
+}
@@ -36,6 +36,7 @@ fn it_parses_component_definitions() {
     }
     ", Rule::component_definition).unwrap(), ComponentDefinition {
         name: "Something".into(),
+        generic_parameters: vec![],
         body: Box::new(Body(vec![
             PinDefinition("a".into()),
             PinDefinition("b".into()),
@@ -113,3 +114,227 @@ fn it_parses_complex_expressions() {
         )
     )
 }
+
+#[test]
+fn it_parses_trace_definitions() {
+    assert_eq!(parse_rule("trace cpu.*;", Rule::trace_definition).unwrap(), Trace("cpu.*".into()));
+    assert_eq!(parse_rule("trace *.clk;", Rule::trace_definition).unwrap(), Trace("*.clk".into()));
+    assert_eq!(parse_rule("trace s.v[0];", Rule::trace_definition).unwrap(), Trace("s.v[0]".into()));
+    assert_eq!(parse_rule("trace ff[1].*;", Rule::trace_definition).unwrap(), Trace("ff[1].*".into()));
+}
+
+#[test]
+fn it_parses_strings() {
+    assert_eq!(parse_rule("\"capture.vcd\"", Rule::expression).unwrap(), Constant(String("capture.vcd".into())));
+    assert_eq!(parse_rule(r#""a \"b\"\n""#, Rule::expression).unwrap(), Constant(String("a \"b\"\n".into())));
+}
+
+#[test]
+fn it_parses_assertions() {
+    assert_eq!(
+        parse_rule("assert(a, \"a should be high\");", Rule::statement).unwrap(),
+        Assert {
+            condition: Box::new(Identifier("a".into())),
+            message: Box::new(Constant(String("a should be high".into()))),
+        },
+    );
+    assert_eq!(
+        parse_rule("expect(a == H);", Rule::statement).unwrap(),
+        Expect {
+            condition: Box::new(Equal(
+                Box::new(Identifier("a".into())),
+                Box::new(Constant(LogicValue(Value::High))),
+            )),
+            source: "a == H".into(),
+        },
+    );
+}
+
+#[test]
+fn it_parses_print_and_log() {
+    assert_eq!(
+        parse_rule("print(\"a={}\", a);", Rule::statement).unwrap(),
+        Log {
+            level: crate::log::LogLevel::Info,
+            arguments: vec![Constant(String("a={}".into())), Identifier("a".into())],
+        },
+    );
+    assert_eq!(
+        parse_rule("log(error, \"oops\");", Rule::statement).unwrap(),
+        Log {
+            level: crate::log::LogLevel::Error,
+            arguments: vec![Constant(String("oops".into()))],
+        },
+    );
+}
+
+#[test]
+fn it_parses_source_lines() {
+    assert_eq!(parse_with_source_lines("define component A {
+        script {
+            sleep(1);
+
+            trigger;
+        }
+    }").unwrap(), Body(vec![Body(vec![
+        ComponentDefinition {
+            name: "A".into(),
+            generic_parameters: vec![],
+            body: Box::new(Body(vec![
+                ScriptDefinition(Box::new(Body(vec![
+                    Located { line: 3, statement: Box::new(Sleep(Box::new(Constant(Integer(1))))) },
+                    Located { line: 5, statement: Box::new(Trigger) },
+                ]))),
+            ])),
+        },
+        EndOfInput,
+    ])]));
+}
+
+#[test]
+fn it_parses_randomness() {
+    assert_eq!(parse_rule("seed 42;", Rule::seed_definition).unwrap(), Seed(42));
+    assert_eq!(parse_rule("random(1, x + 1)", Rule::expression).unwrap(), Random {
+        low: Box::new(Constant(Integer(1))),
+        high: Box::new(Add(Box::new(Identifier("x".into())), Box::new(Constant(Integer(1))))),
+    });
+    assert_eq!(parse_rule("!random_bit()", Rule::expression).unwrap(), LogicNot(Box::new(RandomBit)));
+
+    // Only calls are random, so variables can still be called this
+    assert_eq!(parse_rule("randomness", Rule::expression).unwrap(), Identifier("randomness".into()));
+}
+
+#[test]
+fn it_parses_delayed_connections() {
+    let pins = vec![
+        Accessor {
+            target: Box::new(Identifier("a".into())),
+            name: Box::new(Identifier("b".into())),
+        },
+    ];
+    assert_eq!(
+        parse_rule("connect(a.b) delay 5;", Rule::connect_definition).unwrap(),
+        DelayedConnect { pins: pins.clone(), delay: 5, transport: false },
+    );
+    assert_eq!(
+        parse_rule("connect(a.b) transport delay 3;", Rule::connect_definition).unwrap(),
+        DelayedConnect { pins, delay: 3, transport: true },
+    );
+}
+
+#[test]
+fn it_parses_delayed_pin_assignments() {
+    assert_eq!(
+        parse_rule("q <- d after 2", Rule::pin_assignment).unwrap(),
+        DelayedPinAssignment {
+            target: Box::new(Identifier("q".into())),
+            value: Box::new(Identifier("d".into())),
+            delay: Box::new(Constant(Integer(2))),
+            transport: false,
+        },
+    );
+    assert_eq!(
+        parse_rule("q <- transport d after 2", Rule::pin_assignment).unwrap(),
+        DelayedPinAssignment {
+            target: Box::new(Identifier("q".into())),
+            value: Box::new(Identifier("d".into())),
+            delay: Box::new(Constant(Integer(2))),
+            transport: true,
+        },
+    );
+    assert!(parse_rule("q <- transport d", Rule::pin_assignment).is_err());
+}
+
+#[test]
+fn it_parses_timing_checks() {
+    assert_eq!(
+        parse_rule("check setup(d, posedge clk, 3);", Rule::check_definition).unwrap(),
+        SetupCheck { data: "d".into(), clock: "clk".into(), edge: Edge::Rising, time: 3 },
+    );
+    assert_eq!(
+        parse_rule("check hold(d, negedge clk, 2);", Rule::check_definition).unwrap(),
+        HoldCheck { data: "d".into(), clock: "clk".into(), edge: Edge::Falling, time: 2 },
+    );
+    assert_eq!(
+        parse_rule("check width(rst, 10);", Rule::check_definition).unwrap(),
+        WidthCheck { pin: "rst".into(), time: 10 },
+    );
+}
+
+#[test]
+fn it_parses_generics_and_pin_arrays() {
+    assert_eq!(
+        parse_rule("define component Register<WIDTH, DEPTH> { pin d[WIDTH]; }", Rule::component_definition).unwrap(),
+        ComponentDefinition {
+            name: "Register".into(),
+            generic_parameters: vec!["WIDTH".into(), "DEPTH".into()],
+            body: Box::new(Body(vec![
+                PinArrayDefinition { name: "d".into(), size: Box::new(Identifier("WIDTH".into())) },
+            ])),
+        },
+    );
+    assert_eq!(
+        parse_rule("component r = Register<8, 2 * 2>();", Rule::component_instantiation).unwrap(),
+        ComponentInstantiation {
+            instance_name: Box::new(Identifier("r".into())),
+            component_name: "Register".into(),
+            generic_arguments: vec![
+                Constant(Integer(8)),
+                Multiply(Box::new(Constant(Integer(2))), Box::new(Constant(Integer(2)))),
+            ],
+            arguments: vec![],
+        },
+    );
+    assert_eq!(
+        parse_rule("q[i] <- d[i + 1]", Rule::pin_assignment).unwrap(),
+        PinAssignment {
+            target: Box::new(Index {
+                target: Box::new(Identifier("q".into())),
+                index: Box::new(Identifier("i".into())),
+            }),
+            value: Box::new(Index {
+                target: Box::new(Identifier("d".into())),
+                index: Box::new(Add(Box::new(Identifier("i".into())), Box::new(Constant(Integer(1))))),
+            }),
+        },
+    );
+}
+
+#[test]
+fn it_parses_generate_loops() {
+    assert_eq!(
+        parse_rule("for i in 0..N { component ff[i] = DFlipFlop(); connect(ff[i].q, ff[i + 1].d); }", Rule::generate_loop).unwrap(),
+        GenerateLoop {
+            variable: "i".into(),
+            start: Box::new(Constant(Integer(0))),
+            end: Box::new(Identifier("N".into())),
+            body: Box::new(Body(vec![
+                ComponentInstantiation {
+                    instance_name: Box::new(Index {
+                        target: Box::new(Identifier("ff".into())),
+                        index: Box::new(Identifier("i".into())),
+                    }),
+                    component_name: "DFlipFlop".into(),
+                    generic_arguments: vec![],
+                    arguments: vec![],
+                },
+                Connect(vec![
+                    Accessor {
+                        target: Box::new(Index {
+                            target: Box::new(Identifier("ff".into())),
+                            index: Box::new(Identifier("i".into())),
+                        }),
+                        name: Box::new(Identifier("q".into())),
+                    },
+                    Accessor {
+                        target: Box::new(Index {
+                            target: Box::new(Identifier("ff".into())),
+                            index: Box::new(Add(Box::new(Identifier("i".into())), Box::new(Constant(Integer(1))))),
+                        }),
+                        name: Box::new(Identifier("d".into())),
+                    },
+                ]),
+            ])),
+        },
+    );
+}
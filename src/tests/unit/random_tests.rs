@@ -0,0 +1,32 @@
+use crate::random::Rng;
+
+#[test]
+fn it_generates_splitmix_sequences() {
+    let mut rng = Rng::new(0);
+    assert_eq!(rng.next_u64(), 0xe220a8397b1dcdaf);
+    assert_eq!(rng.next_u64(), 0x6e789e6aa1b965f4);
+
+    // The same seed gives the same sequence
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    assert_eq!((0..10).map(|_| a.next_u64()).collect::<Vec<_>>(), (0..10).map(|_| b.next_u64()).collect::<Vec<_>>());
+}
+
+#[test]
+fn it_generates_bounded_numbers() {
+    let mut rng = Rng::new(1);
+    let numbers = (0..1000).map(|_| rng.below(6)).collect::<Vec<_>>();
+    assert!(numbers.iter().all(|n| *n < 6));
+    assert!((0..6).all(|n| numbers.contains(&n)));
+}
+
+#[test]
+fn it_shuffles() {
+    let mut rng = Rng::new(7);
+    let mut items = (0..20).collect::<Vec<_>>();
+    rng.shuffle(&mut items);
+
+    assert_ne!(items, (0..20).collect::<Vec<_>>());
+    items.sort();
+    assert_eq!(items, (0..20).collect::<Vec<_>>());
+}
@@ -58,12 +58,12 @@ fn it_compiles_loop_statements() {
     assert_eq!(
         compile_script(&parse_block("{
             loop {
-                _dump(1);
+                sleep(1);
             }
         }"), Some(&model), Some(&model.component_definitions[0]), vec![]),
         Ok(vec![
             Instruction::Push(Integer(1)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
             Instruction::Jump(-2),
 
             Instruction::Halt
@@ -78,23 +78,23 @@ fn it_compiles_break_statements() {
     assert_eq!(
         compile_script(&parse_block("{
             loop {
-                _dump(1);
+                sleep(1);
                 break;
-                _dump(2);
-                _dump(3);
+                sleep(2);
+                sleep(3);
             }
         }"), Some(&model), Some(&model.component_definitions[0]), vec![]),
         Ok(vec![
             Instruction::Push(Integer(1)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
 
             Instruction::Jump(6),
 
             Instruction::Push(Integer(2)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
 
             Instruction::Push(Integer(3)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
 
             Instruction::Jump(-7),
 
@@ -110,11 +110,11 @@ fn it_compiles_if_statements() {
     assert_eq!(
         compile_script(&parse_block("{
             if (1) {
-                _dump(1);
-                _dump(2);
-                _dump(3);
+                sleep(1);
+                sleep(2);
+                sleep(3);
             }
-            _dump(0);
+            sleep(0);
         }"), Some(&model), Some(&model.component_definitions[0]), vec![]),
         Ok(vec![
             Instruction::Push(Integer(1)),
@@ -122,16 +122,38 @@ fn it_compiles_if_statements() {
             Instruction::JumpConditional(7),
 
             Instruction::Push(Integer(1)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
 
             Instruction::Push(Integer(2)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
 
             Instruction::Push(Integer(3)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
 
             Instruction::Push(Integer(0)),
-            Instruction::Dump,
+            Instruction::SuspendSleep,
+
+            Instruction::Halt
+        ])
+    );
+}
+
+#[test]
+fn it_compiles_source_lines() {
+    let model = utils::create_model_with_scripts(vec![vec![]]);
+
+    assert_eq!(
+        compile_script(&Node::Body(vec![
+            Node::Located { line: 4, statement: Box::new(Node::Trigger) },
+            Node::Located { line: 5, statement: Box::new(Node::Sleep(Box::new(Node::Constant(Integer(3))))) },
+        ]), Some(&model), Some(&model.component_definitions[0]), vec![]),
+        Ok(vec![
+            Instruction::Line(4),
+            Instruction::SuspendTrigger,
+
+            Instruction::Line(5),
+            Instruction::Push(Integer(3)),
+            Instruction::SuspendSleep,
 
             Instruction::Halt
         ])
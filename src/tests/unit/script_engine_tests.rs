@@ -1,4 +1,4 @@
-use crate::{model::ComponentIntermediateState, model::ComponentStateModificationDescription, script_engine::*};
+use crate::{model::ComponentIntermediateState, script_engine::*};
 use crate::logic::Value;
 
 use std::sync::Arc;
@@ -144,15 +144,6 @@ fn it_performs_comparisons_and_jumps() {
 
     assert_eq!(state.execute_until_done(&mut comp_state), InterpreterExecutionResult::Halt);
 
-    let dumps = comp_state
-        .modifications
-        .iter()
-        .map(|m| match m.description.clone() {
-            ComponentStateModificationDescription::Dump(obj) => obj,
-            _ => unreachable!(),
-        })
-        .collect::<Vec<_>>();
-
     assert_eq!(state.frames.len(), 1);
     assert_eq!(state.frames[0].stack, vec![
         Object::Integer(10),
@@ -0,0 +1,642 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::Simulation;
+use crate::logic::Value;
+
+const MODEL: &str = "
+    define component Inverter {
+        pin in;
+        pin out;
+
+        script {
+            loop {
+                out <- !in;
+                trigger;
+            }
+        }
+    }
+
+    define component Pulse {
+        pin out;
+
+        script {
+            out <- L;
+            sleep(10);
+            out <- H;
+        }
+    }
+
+    component inv = Inverter();
+    component p = Pulse();
+    connect(p.out, inv.in);
+";
+
+#[test]
+fn it_runs_and_reads_pins_by_name() {
+    let mut simulation = Simulation::compile(MODEL).unwrap();
+    simulation.run_until(5);
+    assert_eq!(simulation.time(), 5);
+    assert_eq!(simulation.pin("inv.out"), Some(Value::High));
+    assert_eq!(simulation.pin("inv.in"), Some(Value::Low));
+
+    simulation.run_for(10);
+    assert_eq!(simulation.time(), 10);
+    assert_eq!(simulation.pin("inv.out"), Some(Value::Low));
+
+    assert_eq!(simulation.pin("inv.nothing"), None);
+    assert_eq!(simulation.pin("nothing.out"), None);
+    assert_eq!(simulation.pin("inv"), None);
+}
+
+#[test]
+fn it_drives_pins_by_name() {
+    let mut simulation = Simulation::compile("
+        define component Inverter {
+            pin in;
+            pin out;
+
+            script {
+                loop {
+                    out <- !in;
+                    trigger;
+                }
+            }
+        }
+
+        define component Source {
+            pin out;
+        }
+
+        component inv = Inverter();
+        component s = Source();
+        connect(s.out, inv.in);
+    ").unwrap();
+    simulation.step();
+    assert_eq!(simulation.pin("inv.out"), Some(Value::High));
+
+    // Driving the pin triggers the inverter
+    simulation.drive_pin("s.out", Value::High).unwrap();
+    simulation.step();
+    assert_eq!(simulation.pin("inv.out"), Some(Value::Low));
+
+    assert!(simulation.drive_pin("s.nothing", Value::High).is_err());
+}
+
+#[test]
+fn it_calls_step_callbacks() {
+    let times = Rc::new(RefCell::new(vec![]));
+    let mut simulation = Simulation::compile(MODEL).unwrap();
+
+    let callback_times = times.clone();
+    simulation.on_step(move |model, _| callback_times.borrow_mut().push(model.time_elapsed));
+    simulation.run_until(100);
+
+    assert_eq!(*times.borrow(), vec![0, 0, 10, 10]);
+}
+
+#[test]
+fn it_takes_log_messages() {
+    let mut simulation = Simulation::compile("
+        define component Ticker {
+            script {
+                loop {
+                    print(\"tick\");
+                    sleep(10);
+                }
+            }
+        }
+
+        component t = Ticker();
+    ").unwrap();
+
+    simulation.run_until(15);
+    let times = |messages: Vec<crate::log::LogMessage>| messages.iter().map(|m| m.time).collect::<Vec<_>>();
+    assert_eq!(times(simulation.take_log_messages()), vec![0, 10]);
+    assert!(simulation.log_messages().is_empty());
+
+    // Only messages logged since are returned next time
+    simulation.run_until(25);
+    assert_eq!(times(simulation.take_log_messages()), vec![20]);
+}
+
+#[test]
+fn it_forces_and_releases_pins() {
+    let mut simulation = Simulation::compile("
+        define component Inverter {
+            pin in;
+            pin out;
+
+            script {
+                loop {
+                    out <- !in;
+                    trigger;
+                }
+            }
+        }
+
+        define component Source {
+            pin out;
+
+            script {
+                out <- L;
+            }
+        }
+
+        component inv = Inverter();
+        component s = Source();
+        connect(s.out, inv.in);
+    ").unwrap();
+    simulation.run_until(10);
+    assert_eq!(simulation.pin("inv.out"), Some(Value::High));
+
+    // Forcing overrides the source, and triggers the inverter
+    simulation.force_pin("inv.in", Value::High).unwrap();
+    assert_eq!(simulation.pin("s.out"), Some(Value::High));
+    simulation.step();
+    assert_eq!(simulation.pin("inv.out"), Some(Value::Low));
+
+    // Releasing any pin on the connection gives control back to the source
+    assert_eq!(simulation.release_pin("s.out"), Ok(true));
+    assert_eq!(simulation.pin("inv.in"), Some(Value::Low));
+    simulation.step();
+    assert_eq!(simulation.pin("inv.out"), Some(Value::High));
+
+    assert_eq!(simulation.release_pin("s.out"), Ok(false));
+    assert!(simulation.force_pin("s.nothing", Value::High).is_err());
+}
+
+#[test]
+fn it_delays_connections() {
+    let mut simulation = Simulation::compile("
+        define component Source {
+            pin a;
+            pin b;
+
+            script {
+                a <- L; b <- L;
+                sleep(10);
+                a <- H; b <- H;
+                sleep(2);
+                a <- L; b <- L;
+                sleep(8);
+                a <- H; b <- H;
+            }
+        }
+
+        define component Reader {
+            pin in;
+
+            script {
+                loop {
+                    trigger;
+                    print(\"{}\", in);
+                }
+            }
+        }
+
+        component s = Source();
+        component inertial = Reader();
+        component transport = Reader();
+        connect(s.a, inertial.in) delay 5;
+        connect(s.b, transport.in) transport delay 5;
+    ").unwrap();
+
+    simulation.run_until(22);
+    assert_eq!(simulation.pin("inertial.in"), Some(Value::Low));
+    simulation.run_until(100);
+    assert_eq!(simulation.pin("inertial.in"), Some(Value::High));
+
+    let changes = |instance: &str| simulation.log_messages().iter()
+        .filter(|m| m.instance_name == instance)
+        .map(|m| (m.time, m.message.clone()))
+        .collect::<Vec<_>>();
+
+    // The inertial delay swallows the pulse which is shorter than it
+    assert_eq!(changes("inertial"), vec![(5, "L".to_string()), (25, "H".to_string())]);
+    assert_eq!(changes("transport"), vec![
+        (5, "L".to_string()), (15, "H".to_string()), (17, "L".to_string()), (25, "H".to_string()),
+    ]);
+}
+
+#[test]
+fn it_assigns_pins_after_a_delay() {
+    let mut simulation = Simulation::compile("
+        define component Source {
+            pin a;
+            pin b;
+
+            script {
+                a <- L; b <- L;
+                sleep(10);
+                a <- H after 5;
+                b <- transport H after 5;
+                print(\"not blocked\");
+                sleep(2);
+                a <- L after 5;
+                b <- transport L after 5;
+            }
+        }
+
+        define component Reader {
+            pin in;
+
+            script {
+                loop {
+                    trigger;
+                    print(\"{}\", in);
+                }
+            }
+        }
+
+        component s = Source();
+        component inertial = Reader();
+        component transport = Reader();
+        connect(s.a, inertial.in);
+        connect(s.b, transport.in);
+    ").unwrap();
+
+    simulation.run_until(16);
+    assert_eq!(simulation.pin("s.a"), Some(Value::Low));
+    assert_eq!(simulation.pin("s.b"), Some(Value::High));
+    simulation.run_until(100);
+
+    let messages = |instance: &str| simulation.log_messages().iter()
+        .filter(|m| m.instance_name == instance)
+        .map(|m| (m.time, m.message.clone()))
+        .collect::<Vec<_>>();
+
+    // The script carries on straight after assigning
+    assert_eq!(messages("s"), vec![(10, "not blocked".to_string())]);
+
+    // The second inertial assignment cancels the first, but the transport one
+    // doesn't
+    assert_eq!(messages("inertial"), vec![(0, "L".to_string())]);
+    assert_eq!(messages("transport"), vec![
+        (0, "L".to_string()), (15, "H".to_string()), (17, "L".to_string()),
+    ]);
+}
+
+#[test]
+fn it_reports_timing_check_violations() {
+    let mut simulation = Simulation::compile("
+        define component Flop {
+            pin d;
+            pin clk;
+            pin rst;
+
+            check setup(d, posedge clk, 3);
+            check hold(d, posedge clk, 2);
+            check width(rst, 10);
+        }
+
+        define component Driver {
+            pin d;
+            pin clk;
+            pin rst;
+
+            script {
+                clk <- L; d <- L; rst <- L;
+                sleep(5);
+                rst <- H;
+                sleep(3);
+                rst <- L;
+                sleep(2);
+                d <- H;
+                sleep(2);
+                clk <- H;
+                sleep(1);
+                d <- L;
+                sleep(7);
+                clk <- L;
+                sleep(10);
+                d <- H;
+                sleep(10);
+                clk <- H;
+                sleep(10);
+                d <- L;
+            }
+        }
+
+        component f = Flop();
+        component driver = Driver();
+        connect(f.d, driver.d);
+        connect(f.clk, driver.clk);
+        connect(f.rst, driver.rst);
+    ").unwrap();
+    simulation.run_until(100);
+
+    let failures = simulation.failures().iter()
+        .map(|f| (f.time, f.instance_name.as_str(), f.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(failures, vec![
+        (8, "f", "width violation: rst was high for 3, needs 10"),
+        (12, "f", "setup violation: d changed 2 before rising edge of clk, needs 3"),
+        (13, "f", "hold violation: d changed 1 after rising edge of clk, needs 2"),
+    ]);
+}
+
+#[test]
+fn it_reports_script_errors_as_failures() {
+    let mut simulation = Simulation::compile("
+        define component Indexer {
+            pin d[2];
+
+            script {
+                var i = 5;
+                sleep(3);
+                d[i] <- H;
+                print(\"carried on\");
+            }
+        }
+
+        define component Dice {
+            var roll;
+
+            constructor() {
+                roll = random(6, 1);
+            }
+        }
+
+        define component Delayer {
+            pin out;
+
+            script {
+                var delay = 0 - 2;
+                sleep(7);
+                out <- H after delay;
+            }
+        }
+
+        define component Ticker {
+            script {
+                loop {
+                    sleep(10);
+                    print(\"tick\");
+                }
+            }
+        }
+
+        component indexer = Indexer();
+        component dice = Dice();
+        component delayer = Delayer();
+        component ticker = Ticker();
+    ").unwrap();
+    simulation.run_until(30);
+
+    let failures = simulation.failures().iter()
+        .map(|f| (f.time, f.instance_name.as_str(), f.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(failures, vec![
+        (0, "dice", "random range 6 to 1 is empty"),
+        (3, "indexer", "index 5 is out of bounds for length 2"),
+        (7, "delayer", "pin assignment delay -2 is negative"),
+    ]);
+
+    // Only the scripts with errors stop
+    let messages = simulation.log_messages().iter().map(|m| m.message.as_str()).collect::<Vec<_>>();
+    assert_eq!(messages, vec!["tick", "tick", "tick"]);
+}
+
+#[test]
+fn it_simulates_standard_library_components() {
+    let mut simulation = Simulation::compile("
+        import std;
+
+        component clk = Clock(10);
+        component reset = ResetGenerator(12);
+        component counter = Counter4();
+        component flop = DFlipFlop();
+        component inv = NotGate();
+        component chip = IC7400();
+        component latch = DLatch();
+        component dual = IC7474();
+
+        connect(clk.out, counter.clk, flop.clk);
+        connect(reset.rst, counter.rst);
+        connect(flop.nq, flop.d);
+        connect(counter.q0, inv.a, chip.a1, chip.b1);
+    ").unwrap();
+
+    // Flip-flops and latches start low, like the rest of the library
+    simulation.run_until(1);
+    assert_eq!(simulation.pin("latch.q"), Some(Value::Low));
+    assert_eq!(simulation.pin("latch.nq"), Some(Value::High));
+    assert_eq!(simulation.pin("dual.q2"), Some(Value::Low));
+    assert_eq!(simulation.pin("dual.nq2"), Some(Value::High));
+
+    // The clock rises at 5, 15, 25, ... and the counter is held in reset
+    // until 12
+    simulation.run_until(48);
+    assert_eq!(simulation.pin("counter.q0"), Some(Value::Low));
+    assert_eq!(simulation.pin("counter.q1"), Some(Value::Low));
+    assert_eq!(simulation.pin("counter.q2"), Some(Value::High));
+    assert_eq!(simulation.pin("inv.out"), Some(Value::High));
+    assert_eq!(simulation.pin("chip.y1"), Some(Value::High));
+
+    // The flip-flop starts low, and with its inverted output fed back it
+    // halves the clock
+    assert_eq!(simulation.pin("flop.q"), Some(Value::High));
+    simulation.run_until(58);
+    assert_eq!(simulation.pin("flop.q"), Some(Value::Low));
+    assert_eq!(simulation.pin("counter.q0"), Some(Value::High));
+    assert_eq!(simulation.pin("counter.q2"), Some(Value::High));
+    assert_eq!(simulation.pin("chip.y1"), Some(Value::Low));
+}
+
+#[test]
+fn it_instantiates_generic_components() {
+    let mut simulation = Simulation::compile("
+        define component Register<WIDTH> {
+            pin clk;
+            pin d[WIDTH];
+            pin q[WIDTH];
+            var last_clk;
+
+            script {
+                loop {
+                    if (clk == H && last_clk == L) {
+                        var i = 0;
+                        loop {
+                            if (i == WIDTH) {
+                                break;
+                            }
+                            q[i] <- d[i];
+                            i = i + 1;
+                        }
+                    }
+                    last_clk = clk;
+                    trigger;
+                }
+            }
+        }
+
+        define component Source {
+            pin clk;
+            pin v[3];
+
+            script {
+                v[0] <- H; v[1] <- L; v[2] <- H;
+                clk <- L;
+                sleep(5);
+                clk <- H;
+            }
+        }
+
+        component s = Source();
+        component wide = Register<1 + 2>();
+        component narrow = Register<2>();
+        component other = Register<3>();
+        connect(s.clk, wide.clk, narrow.clk);
+        connect(s.v[0], wide.d[0], narrow.d[0]);
+        connect(s.v[1], wide.d[1], narrow.d[1]);
+        connect(s.v[2], wide.d[2]);
+    ").unwrap();
+    simulation.run_until(10);
+
+    assert_eq!(simulation.pin("wide.q[0]"), Some(Value::High));
+    assert_eq!(simulation.pin("wide.q[1]"), Some(Value::Low));
+    assert_eq!(simulation.pin("wide.q[2]"), Some(Value::High));
+    assert_eq!(simulation.pin("narrow.q[0]"), Some(Value::High));
+    assert_eq!(simulation.pin("narrow.q[2]"), None);
+
+    // Instances with the same arguments share a definition
+    let names = simulation.model().component_definitions.iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["Source", "Register<3>", "Register<2>"]);
+
+    assert!(Simulation::compile("
+        define component A<N> { pin p[N]; }
+        component a = A();
+    ").is_err());
+    assert!(Simulation::compile("
+        define component B { pin p; }
+        component b = B<1>();
+    ").is_err());
+
+    // Sizes which overflow or are negative are rejected, rather than
+    // panicking or making no pins
+    let error = Simulation::compile("
+        define component A<N> { pin p[N]; }
+        component a = A<9223372036854775807 + 1>();
+    ").err().unwrap();
+    assert!(error.to_string().contains("integer overflow"));
+    let error = Simulation::compile("
+        define component A<N> { pin p[N - 5]; }
+        component a = A<2>();
+    ").err().unwrap();
+    assert!(error.to_string().contains("negative size"));
+}
+
+#[test]
+fn it_instantiates_components_inside_definitions() {
+    let mut simulation = Simulation::compile("
+        define component Inverter {
+            pin in;
+            pin out;
+
+            script {
+                loop {
+                    out <- !in;
+                    trigger;
+                }
+            }
+        }
+
+        define component Chain<SIZE> {
+            pin in;
+            pin out;
+
+            for i in 0..SIZE {
+                component inv[i] = Inverter();
+            }
+            for i in 0..SIZE - 1 {
+                connect(inv[i].out, inv[i + 1].in);
+            }
+            connect(in, inv[0].in);
+            connect(out, inv[SIZE - 1].out);
+        }
+
+        define component Source {
+            pin out;
+
+            script {
+                out <- H;
+            }
+        }
+
+        component s = Source();
+        component even = Chain<4>();
+        component odd = Chain<3>();
+        connect(s.out, even.in, odd.in);
+    ").unwrap();
+    simulation.run_until(10);
+
+    // The generic argument decides how many inverters each chain has
+    let instances = |prefix: &str| simulation.model().components.iter()
+        .filter(|c| c.instance_name.starts_with(prefix))
+        .count();
+    assert_eq!(instances("even.inv"), 4);
+    assert_eq!(instances("odd.inv"), 3);
+
+    assert_eq!(simulation.pin("even.out"), Some(Value::High));
+    assert_eq!(simulation.pin("odd.out"), Some(Value::Low));
+    assert_eq!(simulation.pin("odd.inv[1].out"), Some(Value::High));
+
+    let error = Simulation::compile("
+        define component Loop { pin p; component inner = Loop(); }
+        component l = Loop();
+    ").err().unwrap();
+    assert!(error.to_string().contains("cannot contain an instance of itself"));
+}
+
+#[test]
+fn it_expands_generate_loops() {
+    let mut simulation = Simulation::compile("
+        import std;
+
+        define component Source {
+            pin clk;
+            pin d;
+
+            script {
+                d <- H;
+                clk <- L;
+                loop {
+                    sleep(5);
+                    clk <- !clk;
+                }
+            }
+        }
+
+        define component Bus<WIDTH> {
+            pin p[WIDTH];
+        }
+
+        component s = Source();
+        for i in 0..4 {
+            component ff[i] = DFlipFlop();
+            connect(s.clk, ff[i].clk);
+        }
+        connect(s.d, ff[0].d);
+        for i in 0..3 {
+            connect(ff[i].q, ff[i + 1].d);
+        }
+
+        for row in 0..2 {
+            for col in 0..3 {
+                component bus[row * 3 + col] = Bus<col + 1>();
+            }
+        }
+    ").unwrap();
+
+    // The flip-flops make a shift register, so the input takes one clock
+    // edge to reach each one
+    simulation.run_until(20);
+    assert_eq!(simulation.pin("ff[1].q"), Some(Value::High));
+    assert_ne!(simulation.pin("ff[2].q"), Some(Value::High));
+    simulation.run_until(40);
+    assert_eq!(simulation.pin("ff[3].q"), Some(Value::High));
+
+    assert!(simulation.pin("bus[5].p[2]").is_some());
+    assert!(simulation.pin("bus[4].p[2]").is_none());
+    assert_eq!(simulation.model().components.iter().filter(|c| c.instance_name.starts_with("bus[")).count(), 6);
+}
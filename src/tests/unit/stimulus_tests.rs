@@ -0,0 +1,216 @@
+use std::{fs, io::{Cursor, Write}};
+
+use crate::vcd::parse_vcd;
+use crate::stimulus::{parse_csv, parse_sigrok};
+use crate::logic::Value;
+use super::utils::{create_model, printed};
+
+const CAPTURE: &str = "
+$date today $end
+$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! sda $end
+$var wire 1 \" scl $end
+$var wire 8 # data $end
+$upscope $end
+$enddefinitions $end
+$dumpvars
+x!
+0\"
+b00000000 #
+$end
+#10
+1!
+#25
+0!
+1\"
+b00001111 #
+";
+
+#[test]
+fn it_parses_vcd_files() {
+    let file = parse_vcd(CAPTURE).unwrap();
+    assert_eq!(file.timescale, Some("1ns".into()));
+    assert_eq!(file.timescale_femtoseconds(), Ok(Some(1_000_000)));
+    assert_eq!(file.signals.len(), 3);
+
+    let sda = file.signal("top.sda").unwrap();
+    assert_eq!(sda.changes, vec![(0, Value::Unknown), (10, Value::High), (25, Value::Low)]);
+    let scl = file.signal("top.scl").unwrap();
+    assert_eq!(scl.changes, vec![(0, Value::Low), (25, Value::High)]);
+    assert_eq!(file.signal("top.data").unwrap().width, 8);
+    assert!(file.signal("sda").is_none());
+}
+
+#[test]
+fn it_replays_vcd_signals() {
+    let path = std::env::temp_dir().join("hiloz_vcd_source_test.vcd");
+    fs::write(&path, CAPTURE).unwrap();
+
+    let mut model = create_model(format!("
+        define component Stub {{
+            pin in;
+
+            script {{
+                sleep(5);
+                print(\"{{}}\", in);
+                sleep(10);
+                print(\"{{}}\", in);
+                sleep(20);
+                print(\"{{}}\", in);
+            }}
+        }}
+
+        component la = VcdSource({:?}, \"top.sda\", 1000000);
+        component s = Stub();
+
+        connect(la.out, s.in);
+    ", path.to_str().unwrap()));
+    model.run(1000, |_, _| {});
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        printed(&model, "s"),
+        vec![
+            "X",
+            "H",
+            "L",
+        ],
+    );
+}
+
+#[test]
+fn it_rejects_wide_vcd_signals() {
+    let path = std::env::temp_dir().join("hiloz_vcd_source_wide_test.vcd");
+    fs::write(&path, CAPTURE).unwrap();
+    let result = crate::model::Model::compile(format!(
+        "component la = VcdSource({:?}, \"top.data\");", path.to_str().unwrap()
+    ));
+    fs::remove_file(&path).unwrap();
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn it_converts_vcd_timescales() {
+    let dir = std::env::temp_dir().join(format!("hiloz-vcd-timescale-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("capture.vcd"), CAPTURE).unwrap();
+    fs::write(dir.join("slow.vcd"), CAPTURE.replace("1ns", "10ms")).unwrap();
+    fs::write(dir.join("late.vcd"), CAPTURE.replace("#25", "#18446744073709551615")).unwrap();
+    let compile = |source: &str| {
+        let path = dir.join("model.hlzm");
+        fs::write(&path, source).unwrap();
+        crate::model::Model::compile_file(&path, &[])
+    };
+
+    // Captures are found next to the model file, like imports, and their
+    // times are converted into time units of a millisecond
+    let model = compile("component la = VcdSource(\"slow.vcd\", \"top.sda\");").unwrap();
+    let replayed = |model: &crate::model::Model| {
+        let mut model = model.clone();
+        let mut changes = vec![];
+        model.run(1_000_000, |model, _| changes.push((model.time_elapsed, model.components[0].pins[0].value)));
+        changes
+    };
+    assert_eq!(replayed(&model), vec![(0, Value::Unknown), (100, Value::High), (250, Value::Low)]);
+
+    // Nanoseconds are lost unless the time scale is large enough to keep them
+    let error = compile("component la = VcdSource(\"capture.vcd\", \"top.sda\");").unwrap_err();
+    assert!(error.to_string().contains("larger time scale"));
+    let model = compile("component la = VcdSource(\"capture.vcd\", \"top.sda\", 1000000);").unwrap();
+    assert_eq!(replayed(&model), vec![(0, Value::Unknown), (10, Value::High), (25, Value::Low)]);
+
+    // Times too late to scale or replay are rejected rather than overflowing
+    let error = compile("component la = VcdSource(\"late.vcd\", \"top.sda\", 10000000);").unwrap_err();
+    assert!(error.to_string().contains("too late"));
+    let error = compile("component la = VcdSource(\"late.vcd\", \"top.sda\", 1000000);").unwrap_err();
+    assert!(error.to_string().contains("too late"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn it_parses_csv_captures() {
+    let capture = "
+        ; Exported by a logic analyser
+        Time,D0,D1
+        0.0,0,1
+        0.5,1,1
+        1.0,1,0
+        1.5,X,0
+    ";
+    assert_eq!(
+        parse_csv(capture, "D0", 10).unwrap(),
+        vec![(0, Value::Low), (5, Value::High), (15, Value::Unknown)],
+    );
+    assert_eq!(
+        parse_csv(capture, "D1", 10).unwrap(),
+        vec![(0, Value::High), (10, Value::Low)],
+    );
+    assert!(parse_csv(capture, "D2", 10).is_err());
+
+    // Without a time column, each row is one sample
+    assert_eq!(
+        parse_csv("D0\n1\n1\n0\n", "D0", 3).unwrap(),
+        vec![(0, Value::High), (6, Value::Low)],
+    );
+
+    // Samples from before the trigger move the whole capture forward
+    assert_eq!(
+        parse_csv("Time,D0\n-0.2,0\n-0.1,1\n0.0,0\n0.3,1\n", "D0", 10).unwrap(),
+        vec![(0, Value::Low), (1, Value::High), (2, Value::Low), (5, Value::High)],
+    );
+
+    // Changes which would be lost by rounding are rejected, as are times
+    // which go backwards
+    let error = parse_csv("Time,D0\n0.0,0\n0.2,1\n0.4,0\n", "D0", 1).unwrap_err();
+    assert!(error.contains("larger time scale"));
+    assert!(parse_csv("Time,D0\n0,0\n2,1\n1,0\n", "D0", 1).is_err());
+}
+
+#[test]
+fn it_parses_sigrok_sessions() {
+    let mut archive = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = zip::write::FileOptions::default();
+    archive.start_file("version", options).unwrap();
+    archive.write_all(b"2").unwrap();
+    archive.start_file("metadata", options).unwrap();
+    archive.write_all(b"[global]\nsigrok version=0.5.2\n\n[device 1]\ncapturefile=logic-1\ntotal probes=2\nsamplerate=1 MHz\nprobe1=sda\nprobe2=scl\nunitsize=1\n").unwrap();
+    archive.start_file("logic-1-1", options).unwrap();
+    archive.write_all(&[0b00, 0b01, 0b11]).unwrap();
+    archive.start_file("logic-1-2", options).unwrap();
+    archive.write_all(&[0b11, 0b10]).unwrap();
+    let archive = archive.finish().unwrap();
+
+    // Samples are a microsecond apart at 1 MHz, and a scale of 1000 makes
+    // each time unit a microsecond
+    assert_eq!(
+        parse_sigrok(archive.clone(), "sda", 1000).unwrap(),
+        vec![(0, Value::Low), (1, Value::High), (4, Value::Low)],
+    );
+    assert_eq!(
+        parse_sigrok(archive.clone(), "scl", 100_000).unwrap(),
+        vec![(0, Value::Low), (200, Value::High)],
+    );
+    assert!(parse_sigrok(archive.clone(), "D7", 1000).is_err());
+
+    // Time units of a millisecond are too coarse for these samples
+    let error = parse_sigrok(archive, "sda", 1).unwrap_err();
+    assert!(error.contains("larger time scale"));
+}
+
+#[test]
+fn it_replays_csv_captures() {
+    let path = std::env::temp_dir().join("hiloz_csv_source_test.csv");
+    fs::write(&path, "Time,D0\n0,0\n10,1\n").unwrap();
+
+    let mut model = create_model(format!(
+        "component la = CsvSource({:?}, \"D0\", 2);", path.to_str().unwrap()
+    ));
+    model.run(1000, |_, _| {});
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(model.time_elapsed, 20);
+    assert_eq!(model.components[0].pins[0].value, Value::High);
+}
@@ -0,0 +1,95 @@
+use crate::testbench::*;
+use crate::parser;
+use crate::model::Failure;
+
+const MODEL: &str = "
+    define component Inverter {
+        pin in;
+        pin out;
+
+        script {
+            loop {
+                out <- !in;
+                trigger;
+            }
+        }
+    }
+
+    test \"inverts high\" {
+        pin a;
+        pin b;
+
+        component inv = Inverter();
+        connect(test.a, inv.in);
+        connect(test.b, inv.out);
+
+        script {
+            a <- H;
+            sleep(1);
+            expect(b == L);
+        }
+    }
+
+    test \"inverts low\" {
+        pin a;
+        pin b;
+
+        component inv = Inverter();
+        connect(test.a, inv.in);
+        connect(test.b, inv.out);
+
+        script {
+            a <- L;
+            sleep(1);
+            assert(b == L, \"wrong on purpose\");
+        }
+    }
+
+    test \"never finishes\" {
+        script {
+            trigger;
+        }
+    }
+";
+
+#[test]
+fn it_finds_tests() {
+    let parsed = parser::parse(MODEL).unwrap();
+    assert_eq!(
+        tests(&parsed).iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        vec!["inverts high", "inverts low", "never finishes"],
+    );
+}
+
+#[test]
+fn it_runs_tests() {
+    let parsed = parser::parse(MODEL).unwrap();
+    let results = run_tests(&parsed, 100).unwrap();
+
+    assert!(results[0].passed());
+    assert_eq!(results[0].time_elapsed, 1);
+
+    assert!(!results[1].passed());
+    assert_eq!(results[1].failures, vec![
+        Failure { time: 1, instance_name: "test".into(), message: "wrong on purpose".into() },
+    ]);
+
+    assert!(!results[2].passed());
+    assert!(!results[2].finished);
+    assert!(results[2].failures.is_empty());
+}
+
+#[test]
+fn tests_are_ignored_by_normal_compilation() {
+    let model = crate::model::Model::compile(MODEL.into()).unwrap();
+    assert!(model.components.is_empty());
+}
+
+#[test]
+fn it_rejects_duplicate_test_names() {
+    let parsed = parser::parse("
+        test \"a\" {}
+        test \"a\" {}
+    ").unwrap();
+    assert!(run_tests(&parsed, 100).is_err());
+}
@@ -0,0 +1,79 @@
+use crate::trace::*;
+use crate::vcd::VcdGenerator;
+use super::utils::create_model;
+
+#[test]
+fn it_matches_globs() {
+    assert!(glob_match("cpu.*", "cpu.clk"));
+    assert!(glob_match("*.clk", "cpu.clk"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("c?u.*k", "cpu.clk"));
+    assert!(glob_match("*.*.*", "a.b.c"));
+    assert!(!glob_match("cpu.*", "gpu.clk"));
+    assert!(!glob_match("*.clk", "cpu.clk2"));
+    assert!(!glob_match("c?u", "cu"));
+
+    // Brackets are matched literally, for arrays of pins and instances
+    assert!(glob_match("ff[1].*", "ff[1].q"));
+    assert!(glob_match("s.v[?]", "s.v[0]"));
+    assert!(!glob_match("ff[1].*", "ff1.q"));
+}
+
+#[test]
+fn it_filters_traced_pins() {
+    let model = create_model("
+        define component Clock {
+            pin clk;
+            pin out;
+        }
+
+        component cpu = Clock();
+        component gpu = Clock();
+
+        trace cpu;
+        trace *.clk;
+    ");
+
+    let filter = TraceFilter {
+        patterns: model.trace_patterns.clone(),
+        ..TraceFilter::default()
+    };
+    assert!(filter.includes_pin("cpu", "out"));
+    assert!(filter.includes_pin("gpu", "clk"));
+    assert!(!filter.includes_pin("gpu", "out"));
+    assert_eq!(filter.traced_pins(&model).len(), 3);
+
+    assert!(TraceFilter::default().includes_pin("gpu", "out"));
+}
+
+#[test]
+fn it_only_traces_within_the_time_window() {
+    let mut model = create_model("
+        define component Toggle {
+            pin out;
+
+            script {
+                out <- L;
+                loop {
+                    sleep(10);
+                    out <- !out;
+                }
+            }
+        }
+
+        component t = Toggle();
+    ");
+
+    let mut vcd = VcdGenerator::new(TraceFilter {
+        from: Some(15),
+        to: Some(30),
+        ..TraceFilter::default()
+    });
+    vcd.generate_header(&model);
+    model.run(50, |m, mods| vcd.step(m, mods));
+
+    let contents = vcd.contents();
+    assert!(contents.contains("$comment random seed 0 $end"));
+    let body = contents.split("$enddefinitions $end\n").nth(1).unwrap();
+    assert_eq!(body, "#15\n1c0p0\n#20\n0c0p0\n#30\n1c0p0");
+}
@@ -25,6 +25,8 @@ pub fn create_model_with_scripts(scripts: Vec<Vec<Instruction>>) -> Model {
             pins: vec![pin.clone()],
             script: Some(function.clone()),
             variables: vec![],
+            timing_checks: vec![],
+            generic_arguments: vec![],
         }))
     }
 
@@ -41,7 +43,6 @@ pub fn create_model_with_scripts(scripts: Vec<Vec<Instruction>>) -> Model {
                 }
             ],
             variables: vec![],
-            dumps: vec![],
         });
     }
     
@@ -53,6 +54,7 @@ pub fn create_model_with_scripts(scripts: Vec<Vec<Instruction>>) -> Model {
                 frames: vec![InterpreterFrame::new(func)],
                 status: InterpreterStatus::Normal,
                 component_idx: Some(i),
+                native: None,
             }
         ).collect(),
 
@@ -63,3 +65,10 @@ pub fn create_model_with_scripts(scripts: Vec<Vec<Instruction>>) -> Model {
 pub fn create_model<S>(contents: S) -> Model where S : Into<String> {
     Model::compile(contents.into()).unwrap()
 }
+
+pub fn printed(model: &Model, instance_name: &str) -> Vec<String> {
+    model.log_messages.iter()
+        .filter(|m| m.instance_name == instance_name)
+        .map(|m| m.message.clone())
+        .collect()
+}
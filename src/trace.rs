@@ -0,0 +1,168 @@
+use std::{io::{self, Write}, path::Path, str::FromStr};
+
+use crate::model as m;
+use crate::vcd::VcdGenerator;
+use crate::fst::FstGenerator;
+
+/// Something which records the pins of a model as it is simulated, and can
+/// then write out a waveform file.
+pub trait TraceWriter {
+    fn generate_header(&mut self, model: &m::Model);
+    fn step(&mut self, model: &m::Model, modifications: &[m::ComponentStateModification]);
+    fn write(&mut self, output: &mut dyn Write) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Vcd,
+    Fst,
+}
+
+impl TraceFormat {
+    /// Picks a format from the extension of an output path, defaulting to VCD.
+    pub fn from_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("fst") => TraceFormat::Fst,
+            _ => TraceFormat::Vcd,
+        }
+    }
+
+    pub fn writer(self, filter: TraceFilter) -> Box<dyn TraceWriter> {
+        match self {
+            TraceFormat::Vcd => Box::new(VcdGenerator::new(filter)),
+            TraceFormat::Fst => Box::new(FstGenerator::new(filter)),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vcd" => Ok(TraceFormat::Vcd),
+            "fst" => Ok(TraceFormat::Fst),
+            _ => Err(format!("unknown trace format {}", s)),
+        }
+    }
+}
+
+/// Selects which pins end up in a trace, and over which window of time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Glob patterns matched against `instance.pin` names, such as `cpu.*`
+    /// or `*.clk`. A pattern which matches an instance name on its own
+    /// selects every pin of that instance. If there are no patterns, every
+    /// pin is traced.
+    pub patterns: Vec<String>,
+
+    /// Changes before this time are not written to the trace.
+    pub from: Option<u64>,
+
+    /// Changes after this time are not written to the trace.
+    pub to: Option<u64>,
+}
+
+impl TraceFilter {
+    pub fn includes_pin(&self, instance_name: &str, pin_name: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+
+        let full_name = format!("{}.{}", instance_name, pin_name);
+        self.patterns.iter().any(|pattern|
+            glob_match(pattern, &full_name) || glob_match(pattern, instance_name)
+        )
+    }
+
+    pub fn includes_time(&self, time: u64) -> bool {
+        self.from.is_none_or(|from| time >= from)
+        && self.to.is_none_or(|to| time <= to)
+    }
+
+    /// Returns every pin of the model which this filter selects, in the order
+    /// in which they should appear in a trace.
+    pub fn traced_pins(&self, model: &m::Model) -> Vec<m::PinConnection> {
+        let mut result = vec![];
+        for (component_idx, component) in model.components.iter().enumerate() {
+            for (pin_idx, pin_def) in component.definition.pins.iter().enumerate() {
+                if self.includes_pin(&component.instance_name, &pin_def.name) {
+                    result.push(m::PinConnection { component_idx, pin_idx });
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Matches text against a glob pattern, where `*` matches any run of
+/// characters (including none) and `?` matches exactly one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    // Classic backtracking matcher - remember the position of the last star
+    // so that we can retry with it consuming one more character
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Applies the time window of a filter to the values recorded at each step,
+/// so that every trace format handles the window in the same way.
+#[derive(Debug, Clone)]
+pub struct TraceWindow<T> {
+    // Values from the last step before the window opened, which are written
+    // out at the start of the window
+    held_values: Option<T>,
+    opened: bool,
+}
+
+impl<T> Default for TraceWindow<T> {
+    fn default() -> Self {
+        TraceWindow { held_values: None, opened: false }
+    }
+}
+
+impl<T> TraceWindow<T> {
+    /// Given the values of the traced pins after a step, returns the times
+    /// and values which should be written to the trace.
+    pub fn admit(&mut self, filter: &TraceFilter, time: u64, values: T) -> Vec<(u64, T)> {
+        if !filter.includes_time(time) {
+            if filter.from.is_some_and(|from| time < from) {
+                self.held_values = Some(values);
+            }
+            return vec![];
+        }
+
+        // If the window opened between two steps, the values from before it
+        // opened were still in effect at its start
+        let mut result = vec![];
+        if !self.opened {
+            self.opened = true;
+            if let (Some(from), Some(held_values)) = (filter.from, self.held_values.take()) {
+                if time > from {
+                    result.push((from, held_values));
+                }
+            }
+        }
+        result.push((time, values));
+        result
+    }
+}
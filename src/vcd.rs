@@ -1,14 +1,27 @@
+use std::io::{self, Write};
+
 use m::ConnectedComponents;
 
 use crate::model as m;
 use crate::logic as l;
+use crate::trace::{TraceFilter, TraceWindow, TraceWriter};
 
 #[derive(Default)]
 pub struct VcdGenerator {
     lines: Vec<String>,
+    filter: TraceFilter,
+    window: TraceWindow<Vec<String>>,
+    traced_pins: Vec<m::PinConnection>,
 }
 
 impl VcdGenerator {
+    pub fn new(filter: TraceFilter) -> VcdGenerator {
+        VcdGenerator {
+            filter,
+            ..VcdGenerator::default()
+        }
+    }
+
     fn to_var_identifier(&self, component_idx: usize, pin_idx: usize) -> String {
         format!("c{}p{}", component_idx, pin_idx)
     }
@@ -26,13 +39,24 @@ impl VcdGenerator {
     }
 
     pub fn generate_header(&mut self, model: &m::Model) {
+        self.traced_pins = self.filter.traced_pins(model);
+
         // TODO: customisable time unit, maybe in model?
         self.add("$timescale 1ms $end");
+        self.add(format!("$comment random seed {} $end", model.seed));
 
         self.add("$scope module simulation $end");
         for (component_idx, component) in model.components.iter().enumerate() {
+            let traced_pin_idxs = self.traced_pins.iter()
+                .filter(|pc| pc.component_idx == component_idx)
+                .map(|pc| pc.pin_idx)
+                .collect::<Vec<_>>();
+            if traced_pin_idxs.is_empty() {
+                continue;
+            }
+
             self.add(format!("$scope module {} $end", component.instance_name));
-            for (pin_idx, pin) in component.definition.pins.iter().enumerate() {
+            for pin_idx in traced_pin_idxs {
                 self.add(format!(
                     "$var wire 1 {} {} $end",
                     self.to_var_identifier(component_idx, pin_idx),
@@ -45,16 +69,19 @@ impl VcdGenerator {
         self.add("$enddefinitions $end");
     }
 
-    pub fn step(&mut self, model: &m::Model, modifications: &Vec<m::ComponentStateModification>) {
-        self.add(format!("#{}", model.time_elapsed));
-        for (component_idx, component) in model.components().iter().enumerate() {
-            for (pin_idx, pin) in component.pins.iter().enumerate() {
-                let value = model.pin_value(&m::PinConnection { component_idx, pin_idx });
-                self.add(format!(
-                    "{}{}",
-                    self.logic_to_symbol(value),
-                    self.to_var_identifier(component_idx, pin_idx)
-                ))
+    pub fn step(&mut self, model: &m::Model, _modifications: &[m::ComponentStateModification]) {
+        let values = self.traced_pins.iter()
+            .map(|pc| format!(
+                "{}{}",
+                self.logic_to_symbol(model.pin_value(pc)),
+                self.to_var_identifier(pc.component_idx, pc.pin_idx)
+            ))
+            .collect::<Vec<_>>();
+
+        for (time, values) in self.window.admit(&self.filter, model.time_elapsed, values) {
+            self.add(format!("#{}", time));
+            for line in values {
+                self.add(line);
             }
         }
     }
@@ -63,3 +90,168 @@ impl VcdGenerator {
         self.lines.join("\n")
     }
 }
+
+impl TraceWriter for VcdGenerator {
+    fn generate_header(&mut self, model: &m::Model) {
+        VcdGenerator::generate_header(self, model)
+    }
+
+    fn step(&mut self, model: &m::Model, modifications: &[m::ComponentStateModification]) {
+        VcdGenerator::step(self, model, modifications)
+    }
+
+    fn write(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        output.write_all(self.contents().as_bytes())
+    }
+}
+
+/// A single-bit signal read from a VCD file, with its full hierarchical name
+/// (such as `top.sda`) and every value it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VcdSignal {
+    pub name: String,
+    pub width: usize,
+    pub changes: Vec<(u64, l::Value)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VcdFile {
+    pub timescale: Option<String>,
+    pub signals: Vec<VcdSignal>,
+}
+
+impl VcdFile {
+    pub fn signal(&self, name: &str) -> Option<&VcdSignal> {
+        self.signals.iter().find(|s| s.name == name)
+    }
+
+    /// The length of each unit of the file's time in femtoseconds, if it has
+    /// a timescale.
+    pub fn timescale_femtoseconds(&self) -> Result<Option<u64>, String> {
+        let timescale = match &self.timescale {
+            Some(timescale) => timescale,
+            None => return Ok(None),
+        };
+
+        let split = timescale.find(|c: char| !c.is_ascii_digit()).unwrap_or(timescale.len());
+        let (number, unit) = timescale.split_at(split);
+        let femtoseconds = match unit {
+            "s" => 1_000_000_000_000_000,
+            "ms" => 1_000_000_000_000,
+            "us" => 1_000_000_000,
+            "ns" => 1_000_000,
+            "ps" => 1_000,
+            "fs" => 1,
+            _ => return Err(format!("invalid timescale {}", timescale)),
+        };
+        match number {
+            "1" | "10" | "100" => Ok(Some(number.parse::<u64>().unwrap() * femtoseconds)),
+            _ => Err(format!("invalid timescale {}", timescale)),
+        }
+    }
+}
+
+fn symbol_to_logic(symbol: char) -> l::Value {
+    match symbol {
+        '1' => l::Value::High,
+        '0' => l::Value::Low,
+        _ => l::Value::Unknown,
+    }
+}
+
+/// Parses the contents of a VCD file. Multi-bit signals are listed, but only
+/// the changes of single-bit signals are recorded.
+pub fn parse_vcd(contents: &str) -> Result<VcdFile, String> {
+    let mut tokens = contents.split_whitespace();
+    let mut file = VcdFile::default();
+
+    // Many variables can share an identifier code, so map each code to all
+    // of the signals using it
+    let mut signal_idxs_by_code: std::collections::HashMap<String, Vec<usize>> = Default::default();
+    let mut scopes: Vec<String> = vec![];
+    let mut time = 0;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "$scope" => {
+                let _scope_type = tokens.next();
+                scopes.push(tokens.next().ok_or("unterminated $scope")?.into());
+                skip_to_end(&mut tokens)?;
+            }
+
+            "$upscope" => {
+                scopes.pop();
+                skip_to_end(&mut tokens)?;
+            }
+
+            "$var" => {
+                let _var_type = tokens.next();
+                let width = tokens.next()
+                    .and_then(|w| w.parse::<usize>().ok())
+                    .ok_or("invalid $var width")?;
+                let code = tokens.next().ok_or("unterminated $var")?;
+                let name = tokens.next().ok_or("unterminated $var")?;
+                skip_to_end(&mut tokens)?;
+
+                let full_name = scopes.iter()
+                    .map(|s| s.as_str())
+                    .chain(std::iter::once(name))
+                    .collect::<Vec<_>>()
+                    .join(".");
+                signal_idxs_by_code.entry(code.into()).or_default().push(file.signals.len());
+                file.signals.push(VcdSignal { name: full_name, width, changes: vec![] });
+            }
+
+            "$timescale" => {
+                let mut timescale = vec![];
+                for token in &mut tokens {
+                    if token == "$end" {
+                        break;
+                    }
+                    timescale.push(token);
+                }
+                file.timescale = Some(timescale.concat());
+            }
+
+            // Value changes inside these are treated just like any others
+            "$dumpvars" | "$dumpall" | "$dumpon" | "$dumpoff" | "$end" => (),
+
+            _ if token.starts_with('$') => skip_to_end(&mut tokens)?,
+
+            _ if token.starts_with('#') => {
+                time = token[1..].parse::<u64>()
+                    .map_err(|_| format!("invalid timestamp {}", token))?;
+            }
+
+            // Vector and real changes have the identifier code as a separate
+            // token; we don't record them
+            _ if token.starts_with(['b', 'B', 'r', 'R']) => {
+                tokens.next().ok_or("value change without identifier")?;
+            }
+
+            _ => {
+                let mut chars = token.chars();
+                let value = symbol_to_logic(chars.next().unwrap());
+                let code = chars.as_str();
+                let signal_idxs = signal_idxs_by_code.get(code)
+                    .ok_or_else(|| format!("value change for undeclared identifier {}", code))?;
+                for signal_idx in signal_idxs {
+                    let signal = &mut file.signals[*signal_idx];
+                    if signal.width == 1 {
+                        signal.changes.push((time, value));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(file)
+}
+
+fn skip_to_end<'a, I>(tokens: &mut I) -> Result<(), String> where I : Iterator<Item=&'a str> {
+    if tokens.any(|t| t == "$end") {
+        Ok(())
+    } else {
+        Err("missing $end".into())
+    }
+}